@@ -1,7 +1,12 @@
 use cheers_lib::{
+    book::{polyglot_key, Book, PolyglotBook},
     chessgame::ChessGame,
-    moves::Move,
-    search::{Search, ABORT_SEARCH, NODE_COUNT, NPS_COUNT, SEARCH_COMPLETE, TIME_ELAPSED},
+    search::{
+        Search, ABORT_SEARCH, NODE_COUNT, NPS_COUNT, PAWN_HASH_HITS, PAWN_HASH_PROBES,
+        SEARCH_COMPLETE, TB_HITS, TIME_ELAPSED,
+    },
+    syzygy::Tablebase,
+    transposition_table::TranspositionTable,
     types::ColorIndex,
 };
 
@@ -10,19 +15,199 @@ use std::{
     fs::File,
     io::{prelude::*, stdin},
     path::PathBuf,
-    sync::atomic::Ordering,
-    thread,
-    time::{Duration, Instant},
+    sync::{atomic::Ordering, Arc},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
 };
 
-#[derive(Clone, Copy, Default)]
+/// Largest hash table size in MB, thread count and number of lines advertised to the GUI
+const MAX_HASH_MB: usize = 32768;
+const MAX_THREADS: usize = 256;
+const MAX_MULTI_PV: usize = 256;
+
+#[derive(Clone, Default)]
 struct EngineOptions {
     pub tt_size_mb: usize,
+    pub threads: usize,
+    pub multi_pv: usize,
+    pub contempt_opening: i32,
+    pub contempt_endgame: i32,
+    pub show_refutations: bool,
+    pub show_currline: bool,
+    pub analyse_mode: bool,
+    pub debug: bool,
+    pub own_book: bool,
+    /// Polyglot book probed before the built-in one, empty without a book file
+    pub book_file: String,
+    /// Castling moves are sent and received as the king taking its own rook
+    pub chess960: bool,
+    /// Directories of Syzygy tables, empty without tablebases
+    pub syzygy_path: String,
+}
+
+impl EngineOptions {
+    /// Apply a UCI option by its lowercase name
+    fn set(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        let value = value.ok_or(format!("Missing value for {name}"))?;
+        match name {
+            "hash" => {
+                self.tt_size_mb = value
+                    .parse()
+                    .ok()
+                    .filter(|size| (1..=MAX_HASH_MB).contains(size))
+                    .ok_or(format!("Invalid value for hash table size: {value}"))?
+            }
+            "threads" => {
+                self.threads = value
+                    .parse()
+                    .ok()
+                    .filter(|threads| (1..=MAX_THREADS).contains(threads))
+                    .ok_or(format!("Invalid value for thread count: {value}"))?
+            }
+            "multipv" => {
+                self.multi_pv = value
+                    .parse()
+                    .ok()
+                    .filter(|lines| (1..=MAX_MULTI_PV).contains(lines))
+                    .ok_or(format!("Invalid value for MultiPV: {value}"))?
+            }
+            "contempt" | "contemptopening" | "contemptendgame" => {
+                let contempt = value
+                    .parse()
+                    .map_err(|_| format!("Invalid value for contempt: {value}"))?;
+                // plain contempt is the same for the whole game
+                match name {
+                    "contemptopening" => self.contempt_opening = contempt,
+                    "contemptendgame" => self.contempt_endgame = contempt,
+                    _ => {
+                        self.contempt_opening = contempt;
+                        self.contempt_endgame = contempt;
+                    }
+                }
+            }
+            "uci_showrefutations"
+            | "uci_showcurrline"
+            | "uci_analysemode"
+            | "ownbook"
+            | "uci_chess960" => {
+                let enabled = value
+                    .parse()
+                    .map_err(|_| format!("Invalid value for {name}: {value}"))?;
+                match name {
+                    "uci_showrefutations" => self.show_refutations = enabled,
+                    "uci_showcurrline" => self.show_currline = enabled,
+                    "ownbook" => self.own_book = enabled,
+                    "uci_chess960" => self.chess960 = enabled,
+                    _ => self.analyse_mode = enabled,
+                }
+            }
+            // the GUI decides when to ponder, the option only tells it that the engine can
+            "ponder" => {
+                value
+                    .parse::<bool>()
+                    .map_err(|_| format!("Invalid value for {name}: {value}"))?;
+            }
+            "syzygypath" | "bookfile" => {
+                let path = match value {
+                    "<empty>" => String::new(),
+                    path => path.to_string(),
+                };
+                match name {
+                    "syzygypath" => self.syzygy_path = path,
+                    _ => self.book_file = path,
+                }
+            }
+            other => return Err(format!("Unrecognised engine option: {other}")),
+        }
+        Ok(())
+    }
+}
+
+/// Split `setoption name <id> [value <x>]` into the lowercase option name and its value.
+/// Both the name and value may contain spaces.
+fn parse_setoption(words: &[&str]) -> Option<(String, Option<String>)> {
+    let name_index = words.iter().position(|&w| w == "name")?;
+    let value_index = words.iter().position(|&w| w == "value");
+    let name_end = value_index.unwrap_or(words.len());
+    if name_end <= name_index + 1 {
+        return None;
+    }
+    let name = words[(name_index + 1)..name_end].join(" ").to_lowercase();
+    let value = value_index.map(|i| words[(i + 1)..].join(" "));
+    Some((name, value))
+}
+
+/// Build the game for `position [startpos | fen <fen>] [moves <move>...]`.
+/// Anything other than `moves` after the position, or an illegal move, is an error.
+/// In `chess960` mode castling moves are given as the king taking its own rook.
+fn parse_position(words: &[&str], chess960: bool) -> Result<ChessGame, String> {
+    let mut game = ChessGame::new();
+    game.set_chess960(chess960);
+    let moves_index = match words.get(1) {
+        Some(&"startpos") => 2,
+        Some(&"fen") => {
+            // the FEN runs up to the optional moves, the clocks may be left out
+            let fields = words[2..]
+                .iter()
+                .take_while(|&&w| w != "moves")
+                .copied()
+                .collect::<Vec<_>>();
+            if fields.len() < 4 {
+                return Err(String::from("incomplete FEN in position command"));
+            }
+            if fields.len() > 6 {
+                return Err(format!("expected 'moves' but found '{}'", fields[6]));
+            }
+            let clocks = ["0", "1"];
+            let fen = fields
+                .iter()
+                .chain(&clocks[(fields.len() - 4)..])
+                .copied()
+                .collect::<Vec<_>>()
+                .join(" ");
+            game.set_from_fen(fen.clone())
+                .map_err(|err| format!("invalid FEN {fen}: {err}"))?;
+            2 + fields.len()
+        }
+        Some(other) => return Err(format!("unknown position type '{other}'")),
+        None => return Err(String::from("no position given")),
+    };
+
+    match words.get(moves_index) {
+        None => return Ok(game),
+        Some(&"moves") => {}
+        Some(other) => return Err(format!("expected 'moves' but found '{other}'")),
+    }
+    for &coords in &words[(moves_index + 1)..] {
+        let move_ = game
+            .legal_moves()
+            .into_iter()
+            .find(|m| m.coords() == coords)
+            .ok_or_else(|| format!("illegal move {coords}"))?;
+        game.make_move(move_);
+    }
+    Ok(game)
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut position = ChessGame::new();
-    let mut options = EngineOptions { tt_size_mb: 64 };
+    let mut options = EngineOptions {
+        tt_size_mb: 64,
+        threads: 1,
+        multi_pv: 1,
+        ..Default::default()
+    };
+    // kept between searches so the expected reply after a search is already in the table
+    let mut transposition_table = TranspositionTable::new(options.tt_size_mb);
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(1, |t| t.as_nanos() as u64);
+    let mut book = Book::new(seed);
+    let mut polyglot_book: Option<PolyglotBook> = None;
+    let mut tablebase: Option<Arc<Tablebase>> = None;
+    let mut engine: Option<JoinHandle<()>> = None;
+    // a clone of the running search while it ponders, to tell it about a ponderhit
+    let mut pondering: Option<Search> = None;
 
     if std::env::args().nth(1) == Some(String::from("bench")) {
         let bench_game = position.clone();
@@ -38,6 +223,53 @@ fn main() -> Result<(), Box<dyn Error>> {
         let nodes = NODE_COUNT.load(Ordering::Relaxed);
         let nps = (nodes as f64 / time.as_secs_f64()) as usize;
         println!("{nodes} nodes {nps} nps");
+        let probes = PAWN_HASH_PROBES.load(Ordering::Relaxed);
+        let hits = PAWN_HASH_HITS.load(Ordering::Relaxed);
+        println!(
+            "pawn hash hit rate {:.1}%",
+            100.0 * hits as f64 / probes.max(1) as f64
+        );
+        return Ok(());
+    }
+
+    // run a test suite: cheers epd <file> [depth]
+    if std::env::args().nth(1) == Some(String::from("epd")) {
+        let path = std::env::args().nth(2).ok_or("No EPD file given")?;
+        let depth = match std::env::args().nth(3) {
+            Some(depth) => depth.parse::<usize>()?,
+            None => 8,
+        };
+        let (mut solved, mut total) = (0, 0);
+        for epd in std::fs::read_to_string(path)?.lines() {
+            if epd.trim().is_empty() {
+                continue;
+            }
+            let (game, ops) = ChessGame::from_epd(epd)?;
+            let search = Search::new(game.clone())
+                .max_depth(depth)
+                .tt_size_mb(16)
+                .output(false);
+            let (_, pv) = search.search();
+            let id = ops.get("id").and_then(|id| id.first()).map_or("?", |id| id);
+            if !ops.contains_key("bm") && !ops.contains_key("am") {
+                continue;
+            }
+            // mate and stalemate positions have no move to play
+            if pv.len == 0 {
+                total += 1;
+                println!("{id}: no legal move failed");
+                continue;
+            }
+            let result = match game.epd_solved(&ops, pv.moves[0]) {
+                Some(true) => "solved",
+                Some(false) => "failed",
+                None => continue,
+            };
+            total += 1;
+            solved += (result == "solved") as usize;
+            println!("{id}: {} {result}", pv.moves[0].to_san(&game));
+        }
+        println!("Solved {solved}/{total}");
         return Ok(());
     }
 
@@ -50,183 +282,174 @@ fn main() -> Result<(), Box<dyn Error>> {
             Some(&"uci") => {
                 println!("id name cheers");
                 println!("id author Algorhythm");
-                println!("option name Hash type spin default 64 min 1 max 32768");
+                println!("option name Hash type spin default 64 min 1 max {MAX_HASH_MB}");
+                println!("option name Threads type spin default 1 min 1 max {MAX_THREADS}");
+                println!("option name MultiPV type spin default 1 min 1 max {MAX_MULTI_PV}");
+                println!("option name Contempt type spin default 0 min -200 max 200");
+                println!("option name ContemptOpening type spin default 0 min -200 max 200");
+                println!("option name ContemptEndgame type spin default 0 min -200 max 200");
+                println!("option name UCI_ShowRefutations type check default false");
+                println!("option name UCI_ShowCurrLine type check default false");
+                println!("option name UCI_AnalyseMode type check default false");
+                println!("option name Ponder type check default false");
+                println!("option name OwnBook type check default false");
+                println!("option name BookFile type string default <empty>");
+                println!("option name UCI_Chess960 type check default false");
+                println!("option name SyzygyPath type string default <empty>");
                 println!("uciok");
             }
-            Some(&"quit") => break,
+            Some(&"quit") => {
+                stop_search(&mut engine);
+                break;
+            }
             Some(&"isready") => {
                 println!("readyok");
             }
-            Some(&"position") => {
-                let moves_index = match words.get(1) {
-                    Some(&"fen") => {
-                        let mut test_boards = ChessGame::new();
-                        let fen = words[2..=7].join(" ");
-                        if let Err(err) = test_boards.set_from_fen(fen.clone()) {
-                            println!("Failed to set board with FEN {}: {}", fen, err)
-                        }
-                        // position is valid
-                        position.set_from_fen(fen)?;
-                        9
-                    }
-                    Some(&"startpos") => {
-                        position.reset();
-                        if let Some(word) = words.get(2) {
-                            if word != &"moves" {
-                                println!("Malformed UCI command: no \'moves\' in position command");
-                                continue;
-                            };
-                        }
-                        3
-                    }
-                    _ => unreachable!(),
-                };
-                if words.get(moves_index).is_some() {
-                    words[moves_index..].iter().for_each(|xy| {
-                        let move_ = Move::from_pair(&position, xy);
-                        position.make_move(move_)
-                    });
-                }
+            Some(&"ucinewgame") => {
+                // scores from the last game shouldn't leak into this one. Killers, history and
+                // the pawn table belong to the search, which starts fresh on every `go`
+                stop_search(&mut engine);
+                transposition_table.clear();
             }
+            Some(&"position") => match parse_position(&words, options.chess960) {
+                Ok(game) => position = game,
+                Err(err) => println!("Malformed UCI command: {err}"),
+            },
             Some(&"go") => {
+                // finish any search that is still running so it reports its best move first
+                stop_search(&mut engine);
                 if words.get(1) == Some(&"perft") {
                     let depth = match words.get(2) {
                         None => 5,
                         Some(num) => num.parse::<usize>()?,
                     };
                     let start = Instant::now();
-                    let nodes = position.perft(depth);
+                    // spread over the search threads, to benchmark move generation scaling
+                    let nodes = position.perft_parallel(depth, options.threads);
                     let end = Instant::now();
                     let time = (end - start).as_secs_f32();
                     let nps = nodes as f32 / time;
                     println!("Perft({depth}): {nodes}\t\t{time}s\t\t{nps:.1}nps");
                 } else {
-                    let depth = words
-                        .iter()
-                        .enumerate()
-                        .skip_while(|(_, &w)| w != "depth")
-                        .nth(1);
-                    let depth = match depth {
-                        Some((i, w)) => match w.parse::<usize>() {
-                            Ok(n) => Some(n),
-                            _ => {
-                                println!("Invalid value for depth: {}", words[i]);
-                                continue;
-                            }
-                        },
-                        None => None,
-                    };
-                    let wtime = words
-                        .iter()
-                        .enumerate()
-                        .skip_while(|(_, &w)| w != "wtime")
-                        .nth(1);
-                    let wtime = match wtime {
-                        Some((i, w)) => match w.parse::<usize>() {
-                            Ok(n) => Some(n),
-                            _ => {
-                                println!("Invalid value for wtime: {}", words[i]);
-                                continue;
-                            }
-                        },
-                        None => None,
-                    };
-                    let btime = words
-                        .iter()
-                        .enumerate()
-                        .skip_while(|(_, &w)| w != "btime")
-                        .nth(1);
-                    let btime = match btime {
-                        Some((i, w)) => match w.parse::<usize>() {
-                            Ok(n) => Some(n),
-                            _ => {
-                                println!("Invalid value for btime: {}", words[i]);
-                                continue;
-                            }
-                        },
-                        None => None,
-                    };
-
-                    let winc = words
-                        .iter()
-                        .enumerate()
-                        .skip_while(|(_, &w)| w != "winc")
-                        .nth(1);
-                    let winc = match winc {
-                        Some((i, w)) => match w.parse::<usize>() {
-                            Ok(n) => Some(n),
-                            _ => {
-                                println!("Invalid value for winc: {}", words[i]);
-                                continue;
-                            }
-                        },
-                        None => None,
+                    let go = match parse_go(&words) {
+                        Ok(go) => go,
+                        Err(err) => {
+                            println!("Malformed UCI command: {err}");
+                            continue;
+                        }
                     };
 
-                    let binc = words
-                        .iter()
-                        .enumerate()
-                        .skip_while(|(_, &w)| w != "binc")
-                        .nth(1);
-                    let binc = match binc {
-                        Some((i, w)) => match w.parse::<usize>() {
-                            Ok(n) => Some(n),
-                            _ => {
-                                println!("Invalid value for binc: {}", words[i]);
-                                continue;
-                            }
-                        },
-                        None => None,
-                    };
+                    // a book move can't be reported before the opponent has moved
+                    if options.own_book && !go.ponder {
+                        let book_move = polyglot_book
+                            .as_mut()
+                            .and_then(|book| book.weighted_move(polyglot_key(&position), &position))
+                            .or_else(|| book.probe(&position));
+                        if let Some(move_) = book_move {
+                            println!("bestmove {}", move_.coords());
+                            continue;
+                        }
+                    }
 
                     let mut search = Search::new(position.clone())
-                        .tt_size_mb(options.tt_size_mb)
+                        .transposition_table(transposition_table.clone())
+                        .threads(options.threads)
+                        .multi_pv(options.multi_pv)
+                        .contempt(options.contempt_opening, options.contempt_endgame)
+                        .show_refutations(options.show_refutations)
+                        .show_currline(options.show_currline)
+                        .analyse_mode(options.analyse_mode)
                         .output(true);
-                    search.max_depth = depth;
-                    match position.current_player() {
-                        ColorIndex::White => {
-                            move_time(wtime, winc).map(|(move_time, abort_time)| {
-                                search.max_time_ms = Some(move_time);
-                                search.abort_time_ms = Some(abort_time);
-                            })
-                        }
-                        ColorIndex::Black => {
-                            move_time(btime, binc).map(|(move_time, abort_time)| {
-                                search.max_time_ms = Some(move_time);
-                                search.abort_time_ms = Some(abort_time);
-                            })
-                        }
-                    };
-                    let _ = thread::spawn(move || engine_thread(search).unwrap());
+                    if let Some(tablebase) = &tablebase {
+                        search = search.tablebase(tablebase.clone());
+                    }
+                    search.max_depth = go.depth;
+                    search.max_nodes = go.nodes;
+                    if let Some(budget) = go.time_budget(position.current_player()) {
+                        search.max_time_ms = Some(budget.soft_ms);
+                        search.abort_time_ms = Some(budget.hard_ms);
+                    }
+                    if let Some(movetime) = go.movetime {
+                        search = search.max_time(Duration::from_millis(
+                            movetime.saturating_sub(MOVE_OVERHEAD_MS) as u64,
+                        ));
+                    }
+                    let search = search.infinite(go.infinite).ponder(go.ponder);
+                    pondering = go.ponder.then(|| search.clone());
+                    // reset before spawning, so a stop sent straight after isn't lost
+                    ABORT_SEARCH.store(false, Ordering::Relaxed);
+                    TIME_ELAPSED.store(false, Ordering::Relaxed);
+                    SEARCH_COMPLETE.store(false, Ordering::Relaxed);
+                    NODE_COUNT.store(0, Ordering::Relaxed);
+                    NPS_COUNT.store(0, Ordering::Relaxed);
+                    TB_HITS.store(0, Ordering::Relaxed);
+                    engine = Some(thread::spawn(move || engine_thread(search).unwrap()));
                 }
             }
-            Some(&"stop") => ABORT_SEARCH.store(true, Ordering::Relaxed),
-            Some(&"setoption") => {
-                let option_name = words
-                    .iter()
-                    .position(|&w| w == "name")
-                    .and_then(|i| words.get(i + 1).map(|w| w.to_lowercase()));
-
-                if let Some(option) = option_name {
-                    match option.as_ref() {
-                        "hash" => {
-                            let option_value = words
-                                .iter()
-                                .position(|&w| w == "value")
-                                .and_then(|i| words.get(i + 1).map(|w| w.parse::<usize>().ok()))
-                                .flatten();
-                            if let Some(val) = option_value {
-                                options.tt_size_mb = val
-                            } else {
-                                println!("Invalid value for hash table size");
-                            }
-                        }
-                        other => {
-                            println!("Unrecognised engine option: {other}")
+            Some(&"stop") => stop_search(&mut engine),
+            // Pondering: `go ponder` searches the position after the move the engine expects
+            // the opponent to play with the clock stopped, and never reports its move on its
+            // own. `ponderhit` means the opponent played it, so the clock starts and the search
+            // goes on as a normal timed search. After any other move the GUI sends `stop`, which
+            // ends the search with a bestmove that is ignored, then the new position and `go`
+            Some(&"ponderhit") => {
+                if let Some(search) = pondering.take() {
+                    search.ponderhit();
+                }
+            }
+            Some(&"setoption") => match parse_setoption(&words) {
+                Some((name, value)) => {
+                    let tt_size_mb = options.tt_size_mb;
+                    let syzygy_path = options.syzygy_path.clone();
+                    let book_file = options.book_file.clone();
+                    if let Err(e) = options.set(&name, value.as_deref()) {
+                        if options.debug {
+                            println!("info string {e}");
                         }
                     }
+                    if options.tt_size_mb != tt_size_mb {
+                        transposition_table = TranspositionTable::new(options.tt_size_mb);
+                    }
+                    if options.syzygy_path != syzygy_path {
+                        tablebase = if options.syzygy_path.is_empty() {
+                            None
+                        } else {
+                            let tables = Tablebase::new(&options.syzygy_path);
+                            println!(
+                                "info string Found {} Syzygy tables with up to {} pieces",
+                                tables.table_count(),
+                                tables.max_pieces()
+                            );
+                            Some(Arc::new(tables))
+                        };
+                    }
+                    if options.book_file != book_file {
+                        polyglot_book = if options.book_file.is_empty() {
+                            None
+                        } else {
+                            match PolyglotBook::load(&options.book_file, seed) {
+                                Ok(book) => {
+                                    println!(
+                                        "info string Loaded {} Polyglot book entries",
+                                        book.len()
+                                    );
+                                    Some(book)
+                                }
+                                Err(err) => {
+                                    println!("info string Failed to load book file: {err}");
+                                    None
+                                }
+                            }
+                        };
+                    }
                 }
-            }
+                None => {
+                    if options.debug {
+                        println!("info string Malformed setoption command: {line}");
+                    }
+                }
+            },
+            Some(&"debug") => options.debug = words.get(1) == Some(&"on"),
             Some(&"test") => {
                 let path = match words.get(1) {
                     Some(p) => match PathBuf::try_from(p) {
@@ -288,32 +511,56 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn engine_thread(search: Search) -> Result<(), Box<dyn Error>> {
-    ABORT_SEARCH.store(false, Ordering::Relaxed);
-    TIME_ELAPSED.store(false, Ordering::Relaxed);
-    SEARCH_COMPLETE.store(false, Ordering::Relaxed);
-    NODE_COUNT.store(0, Ordering::Relaxed);
-    NPS_COUNT.store(0, Ordering::Relaxed);
-
-    let search_start = Instant::now();
-    let max_time_ms = search.max_time_ms.map(|ms| {
-        // limit the time of a search with 1 legal move
-        if search.game.legal_moves().len() == 1 {
-            ms.min(500)
-        } else {
-            ms
-        }
-    });
+/// Stop any running search and wait for it to report its best move
+fn stop_search(engine: &mut Option<JoinHandle<()>>) {
+    if let Some(handle) = engine.take() {
+        ABORT_SEARCH.store(true, Ordering::Relaxed);
+        let _ = handle.join();
+    }
+}
+
+fn engine_thread(mut search: Search) -> Result<(), Box<dyn Error>> {
+    let mut search_start = Instant::now();
+    // limit the time of a search with 1 legal move
+    if !search.is_analysing() && search.game.legal_moves().len() == 1 {
+        search.max_time_ms = search.max_time_ms.map(|ms| ms.min(500));
+    }
     let abort_time_ms = search.abort_time_ms;
+    let handle = search.clone();
     // spawn another thread to do the actual searching
-    thread::spawn(move || {
+    let search_thread = thread::spawn(move || {
         let (_, pv) = search.search();
-        println!("bestmove {}", pv.moves[0].coords(),);
+        if pv.len == 0 {
+            // no legal moves
+            println!("bestmove 0000");
+            return;
+        }
+        // the PV can be cut short by a TT cutoff, but the whole line is in the TT
+        let line = search.principal_variation();
+        let ponder = match pv.len {
+            1 => line
+                .get(1)
+                .filter(|_| line[0].coords() == pv.moves[0].coords())
+                .copied(),
+            _ => Some(pv.moves[1]),
+        };
+        match ponder {
+            Some(ponder) => println!(
+                "bestmove {} ponder {}",
+                pv.moves[0].coords(),
+                ponder.coords()
+            ),
+            None => println!("bestmove {}", pv.moves[0].coords()),
+        }
     });
 
     let mut nodes_report = Instant::now();
     while !SEARCH_COMPLETE.load(Ordering::Relaxed) {
         let now = Instant::now();
+        // the clock only runs once the opponent has played the expected move
+        if handle.is_pondering() {
+            search_start = now;
+        }
 
         // Emergency search abort when extemely low on time
         if let Some(abort_time) = abort_time_ms {
@@ -332,13 +579,6 @@ fn engine_thread(search: Search) -> Result<(), Box<dyn Error>> {
             println!("info nodes {nodes} nps {nps}");
         }
 
-        // hint to terminate search after max time elapsed
-        if let Some(max_time) = max_time_ms {
-            if (now - search_start).as_millis() as usize > max_time {
-                TIME_ELAPSED.store(true, Ordering::Relaxed);
-            }
-        }
-
         // search has ended cleanly after max time elapsed
         if SEARCH_COMPLETE.load(Ordering::Relaxed) {
             break;
@@ -346,17 +586,302 @@ fn engine_thread(search: Search) -> Result<(), Box<dyn Error>> {
 
         thread::sleep(Duration::from_millis(1));
     }
+    search_thread.join().map_err(|_| "Search thread panicked")?;
     Ok(())
 }
 
-fn move_time(time_millis: Option<usize>, inc_millis: Option<usize>) -> Option<(usize, usize)> {
-    let (time, inc) = match (time_millis, inc_millis) {
-        (None, None) => return None,
-        (t, i) => (t.unwrap_or(0), i.unwrap_or(0)),
-    };
-    if time < inc {
-        Some((time / 20, time / 2))
-    } else {
-        Some((time / 20 + inc / 2, time / 2))
+/// Time kept back from every clock-based search for communication delays
+const MOVE_OVERHEAD_MS: usize = 50;
+
+/// Search limits from `go [depth <x>] [wtime <x>] ... [infinite]`
+#[derive(Debug, Default, PartialEq, Eq)]
+struct GoOptions {
+    depth: Option<usize>,
+    wtime: Option<usize>,
+    btime: Option<usize>,
+    winc: Option<usize>,
+    binc: Option<usize>,
+    movestogo: Option<usize>,
+    movetime: Option<usize>,
+    nodes: Option<usize>,
+    infinite: bool,
+    ponder: bool,
+}
+
+/// Soft and hard time limits for a search in milliseconds
+#[derive(Debug, PartialEq, Eq)]
+struct TimeBudget {
+    soft_ms: usize,
+    hard_ms: usize,
+}
+
+impl GoOptions {
+    /// Time budget for `player` from the clock, if there is one
+    fn time_budget(&self, player: ColorIndex) -> Option<TimeBudget> {
+        let (time, inc) = match player {
+            ColorIndex::White => (self.wtime, self.winc),
+            ColorIndex::Black => (self.btime, self.binc),
+        };
+        let (time, inc) = match (time, inc) {
+            (None, None) => return None,
+            (t, i) => (
+                t.unwrap_or(0).saturating_sub(MOVE_OVERHEAD_MS),
+                i.unwrap_or(0),
+            ),
+        };
+        let moves = self.movestogo.unwrap_or(20).max(1);
+        let hard_ms = time / 2;
+        let soft_ms = if time < inc {
+            time / moves
+        } else {
+            time / moves + inc / 2
+        };
+        Some(TimeBudget {
+            soft_ms: soft_ms.min(hard_ms),
+            hard_ms,
+        })
+    }
+}
+
+/// Parse the limits of a `go` command, ignoring unknown words
+fn parse_go(words: &[&str]) -> Result<GoOptions, String> {
+    let mut go = GoOptions::default();
+    let mut words = words.iter().skip(1);
+    while let Some(&word) = words.next() {
+        let limit = match word {
+            "depth" => &mut go.depth,
+            "wtime" => &mut go.wtime,
+            "btime" => &mut go.btime,
+            "winc" => &mut go.winc,
+            "binc" => &mut go.binc,
+            "movestogo" => &mut go.movestogo,
+            "movetime" => &mut go.movetime,
+            "nodes" => &mut go.nodes,
+            "infinite" => {
+                go.infinite = true;
+                continue;
+            }
+            "ponder" => {
+                go.ponder = true;
+                continue;
+            }
+            _ => continue,
+        };
+        let value = words.next().ok_or(format!("Missing value for {word}"))?;
+        *limit = Some(
+            value
+                .parse()
+                .map_err(|_| format!("Invalid value for {word}: {value}"))?,
+        );
+    }
+    Ok(go)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setoption(options: &mut EngineOptions, line: &str) -> Result<(), String> {
+        let words = line.split(' ').collect::<Vec<_>>();
+        let (name, value) = parse_setoption(&words).ok_or("Malformed setoption command")?;
+        options.set(&name, value.as_deref())
+    }
+
+    #[test]
+    fn go_parsing() -> Result<(), String> {
+        let words = "go wtime 300000 btime 300000 movestogo 40"
+            .split(' ')
+            .collect::<Vec<_>>();
+        let go = parse_go(&words)?;
+        assert_eq!(
+            go,
+            GoOptions {
+                wtime: Some(300_000),
+                btime: Some(300_000),
+                movestogo: Some(40),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            go.time_budget(ColorIndex::Black),
+            Some(TimeBudget {
+                soft_ms: 7_498,
+                hard_ms: 149_975
+            })
+        );
+
+        let words = "go wtime 10000 winc 1000 depth 8 nodes 5000 infinite"
+            .split(' ')
+            .collect::<Vec<_>>();
+        let go = parse_go(&words)?;
+        assert_eq!(
+            (go.depth, go.nodes, go.infinite),
+            (Some(8), Some(5000), true)
+        );
+        assert_eq!(
+            go.time_budget(ColorIndex::White),
+            Some(TimeBudget {
+                soft_ms: 997,
+                hard_ms: 4_975
+            })
+        );
+        assert_eq!(go.time_budget(ColorIndex::Black), None);
+
+        // the soft limit never passes the hard limit
+        let go = parse_go(&["go", "btime", "1000", "movestogo", "1"])?;
+        assert_eq!(
+            go.time_budget(ColorIndex::Black),
+            Some(TimeBudget {
+                soft_ms: 475,
+                hard_ms: 475
+            })
+        );
+
+        let go = parse_go(&["go", "ponder", "wtime", "1000", "btime", "1000"])?;
+        assert!(go.ponder && go.wtime == Some(1000));
+
+        assert!(parse_go(&["go", "depth"]).is_err());
+        assert!(parse_go(&["go", "movetime", "soon"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn setoption_parsing() {
+        let mut options = EngineOptions {
+            tt_size_mb: 64,
+            ..Default::default()
+        };
+
+        assert!(setoption(&mut options, "setoption name Hash value 128").is_ok());
+        assert_eq!(options.tt_size_mb, 128);
+        assert!(setoption(&mut options, "setoption name Threads value 4").is_ok());
+        assert_eq!(options.threads, 4);
+        assert!(setoption(&mut options, "setoption name MultiPV value 3").is_ok());
+        assert_eq!(options.multi_pv, 3);
+        assert!(setoption(&mut options, "setoption name UCI_AnalyseMode value true").is_ok());
+        assert!(options.analyse_mode);
+        assert!(setoption(&mut options, "setoption name Ponder value true").is_ok());
+        assert!(setoption(&mut options, "setoption name UCI_Chess960 value true").is_ok());
+        assert!(options.chess960);
+        assert!(setoption(&mut options, "setoption name Contempt value 25").is_ok());
+        assert_eq!(
+            (options.contempt_opening, options.contempt_endgame),
+            (25, 25)
+        );
+        assert!(setoption(&mut options, "setoption name ContemptEndgame value -10").is_ok());
+        assert_eq!(
+            (options.contempt_opening, options.contempt_endgame),
+            (25, -10)
+        );
+
+        // string options are stored as given, and <empty> clears them
+        assert!(setoption(
+            &mut options,
+            "setoption name SyzygyPath value /tb/wdl:/tb/dtz"
+        )
+        .is_ok());
+        assert_eq!(options.syzygy_path, "/tb/wdl:/tb/dtz");
+        assert!(setoption(&mut options, "setoption name SyzygyPath value <empty>").is_ok());
+        assert!(options.syzygy_path.is_empty());
+        // values can contain spaces
+        assert!(setoption(
+            &mut options,
+            "setoption name BookFile value books/my book.bin"
+        )
+        .is_ok());
+        assert_eq!(options.book_file, "books/my book.bin");
+
+        // unsupported options with spaces in the name are rejected without side effects
+        assert!(setoption(&mut options, "setoption name Clear Hash").is_err());
+        assert!(setoption(&mut options, "setoption name Skill Level value 3").is_err());
+        assert_eq!(
+            parse_setoption(&["setoption", "name", "Skill", "Level", "value", "3"]),
+            Some((String::from("skill level"), Some(String::from("3"))))
+        );
+
+        // malformed commands
+        assert!(setoption(&mut options, "setoption").is_err());
+        assert!(setoption(&mut options, "setoption name").is_err());
+        assert!(setoption(&mut options, "setoption name Hash value").is_err());
+        assert!(setoption(&mut options, "setoption name Hash value lots").is_err());
+        // values outside the advertised range
+        assert!(setoption(&mut options, "setoption name Hash value 0").is_err());
+        assert!(setoption(&mut options, "setoption name Hash value 65536").is_err());
+        assert!(setoption(&mut options, "setoption name Threads value 0").is_err());
+        assert!(setoption(&mut options, "setoption name Threads value 257").is_err());
+        assert!(setoption(&mut options, "setoption name MultiPV value 0").is_err());
+        assert_eq!(options.threads, 4);
+        assert_eq!(options.tt_size_mb, 128);
+    }
+
+    fn position(line: &str) -> Result<ChessGame, String> {
+        parse_position(&line.split(' ').collect::<Vec<_>>(), false)
+    }
+
+    #[test]
+    fn position_parsing() -> Result<(), String> {
+        assert!(position("position startpos")? == ChessGame::new());
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let game = position(&format!("position fen {kiwipete}"))?;
+        assert_eq!(game.legal_moves().len(), 48);
+
+        let game = position("position startpos moves e2e4 e7e5")?;
+        assert_eq!(game.position_history().len(), 2);
+        let game = position(&format!("position fen {kiwipete} moves e1g1"))?;
+        assert_eq!(game.position_history().len(), 1);
+        // nothing after 'moves' is the same position
+        assert!(position("position startpos moves")? == ChessGame::new());
+
+        // malformed commands
+        assert!(position("position").is_err());
+        assert!(position("position somewhere").is_err());
+        assert!(position("position startpos e2e4").is_err());
+        assert!(position("position startpos moves e2e5").is_err());
+        assert!(position("position fen 8/8/8/8/8/8/8/8 w").is_err());
+        assert!(position(&format!("position fen {kiwipete} e1g1")).is_err());
+        assert!(position(&format!("position fen {kiwipete} moves e1g1 nonsense")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn position_fen_fields() -> Result<(), Box<dyn Error>> {
+        // en passent is possible and only white kingside and black queenside castling remain
+        let fen = "r3k2r/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/R3K2R w Kq f6 0 3";
+        let mut expected = ChessGame::new();
+        expected.set_from_fen(fen)?;
+
+        let game = position(&format!("position fen {fen}"))?;
+        assert!(game == expected);
+        assert!(game.en_passent_square().is_some());
+        assert!(game.legal_moves().iter().any(|m| m.en_passent()));
+        assert!(game.legal_moves().iter().any(|m| m.coords() == "e1g1"));
+        assert!(!game.legal_moves().iter().any(|m| m.coords() == "e1c1"));
+
+        let game = position(&format!("position fen {fen} moves e5f6 e8c8"))?;
+        assert_eq!(game.position_history().len(), 2);
+
+        // without the clocks
+        let game =
+            position("position fen r3k2r/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/R3K2R w Kq f6 moves e5f6")?;
+        assert_eq!(game.position_history().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn position_chess960() -> Result<(), String> {
+        let position = |line: &str| parse_position(&line.split(' ').collect::<Vec<_>>(), true);
+        // castling is the king taking its own rook
+        let moves = "position startpos moves g1f3 g8f6 e2e4 e7e5 f1c4 f8c5";
+        let game = position(&format!("{moves} e1h1"))?;
+        assert!(game.chess960());
+        assert!(game
+            .fen()
+            .starts_with("rnbqk2r/pppp1ppp/5n2/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQ1RK1 b ha "));
+        assert!(position(&format!("{moves} e1g1")).is_err());
+
+        // Shredder-FEN castling rights
+        let game = position("position fen 1r2k1r1/8/8/8/8/8/8/1R2K1R1 w GBgb - 0 1 moves e1b1")?;
+        assert_eq!(game.fen(), "1r2k1r1/8/8/8/8/8/8/2KR2R1 b gb - 1 1");
+        Ok(())
     }
 }
@@ -0,0 +1,265 @@
+use cheers_lib::{
+    chessgame::{
+        eval_params::{EvalParams, EVAL_PARAMS},
+        ChessGame, GameResult,
+    },
+    search::Search,
+    types::ColorIndex::{Black, White},
+};
+
+use std::{error::Error, fs::read_to_string};
+
+const DEFAULT_OPENINGS: [&str; 4] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    "rnbqkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - 1 2",
+];
+
+/// Games that run this long are adjudicated as draws
+const MAX_PLIES: usize = 400;
+
+/// Search depth when neither a depth nor a node limit is given
+const DEFAULT_DEPTH: usize = 6;
+
+/// Search settings for one side of the match
+#[derive(Clone, Copy)]
+struct Player {
+    depth: Option<usize>,
+    /// Nodes per move, reproducible unlike a time limit
+    nodes: Option<usize>,
+    contempt: i32,
+    probcut: bool,
+    eval_params: EvalParams,
+}
+
+impl Player {
+    fn search(&self, game: &ChessGame) -> Search {
+        let mut search = Search::new(game.clone())
+            .tt_size_mb(16)
+            .contempt(self.contempt, self.contempt)
+            .probcut(self.probcut)
+            .eval_params(self.eval_params);
+        if let Some(depth) = self.depth {
+            search = search.max_depth(depth);
+        }
+        if let Some(nodes) = self.nodes {
+            search = search.max_nodes(nodes);
+        }
+        search
+    }
+}
+
+/// Match score from the perspective of the first player
+#[derive(Clone, Copy, Default, Debug)]
+struct Score {
+    wins: usize,
+    losses: usize,
+    draws: usize,
+}
+
+fn adjudicate(game: &ChessGame, plies: usize) -> Option<GameResult> {
+    if let Some(result) = game.game_result() {
+        return Some(result);
+    }
+    // both players always claim draws
+    if game.is_draw() || plies >= MAX_PLIES {
+        return Some(GameResult::Draw);
+    }
+    None
+}
+
+fn play_game(opening: &str, white: Player, black: Player) -> Result<GameResult, Box<dyn Error>> {
+    let mut game = ChessGame::new();
+    game.set_from_fen(opening)?;
+
+    for plies in 0.. {
+        if let Some(result) = adjudicate(&game, plies) {
+            return Ok(result);
+        }
+        let player = match game.current_player() {
+            White => white,
+            Black => black,
+        };
+        let (_, pv) = player.search(&game).search();
+        if pv.len == 0 {
+            return Err(format!("No move found in position {}", game.fen()).into());
+        }
+        game.make_move(pv.moves[0]);
+    }
+    unreachable!()
+}
+
+/// Play each opening once with each colour
+fn play_match(
+    openings: &[String],
+    games: usize,
+    first: Player,
+    second: Player,
+) -> Result<Score, Box<dyn Error>> {
+    let mut score = Score::default();
+    for i in 0..games {
+        let opening = &openings[(i / 2) % openings.len()];
+        let first_is_white = i % 2 == 0;
+        let (white, black) = if first_is_white {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        let result = play_game(opening, white, black)?;
+        match (result, first_is_white) {
+            (GameResult::Draw, _) => score.draws += 1,
+            (GameResult::WhiteWin, true) | (GameResult::BlackWin, false) => score.wins += 1,
+            _ => score.losses += 1,
+        }
+        println!(
+            "Game {} ({opening}): {result:?}, score {}-{}-{}",
+            i + 1,
+            score.wins,
+            score.losses,
+            score.draws
+        );
+    }
+    Ok(score)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let arg = |name: &str| {
+        args.iter()
+            .position(|a| a == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    let optional = |name: &str| -> Result<Option<usize>, Box<dyn Error>> {
+        match arg(name) {
+            Some(n) => Ok(Some(
+                n.parse()
+                    .map_err(|_| format!("Invalid value for {name}: {n}"))?,
+            )),
+            None => Ok(None),
+        }
+    };
+    let number = |name: &str, default: usize| -> Result<usize, Box<dyn Error>> {
+        Ok(optional(name)?.unwrap_or(default))
+    };
+    let contempt = |name: &str| -> Result<i32, Box<dyn Error>> {
+        match arg(name) {
+            Some(n) => Ok(n
+                .parse()
+                .map_err(|_| format!("Invalid value for {name}: {n}"))?),
+            None => Ok(0),
+        }
+    };
+    let enabled = |name: &str| -> Result<bool, Box<dyn Error>> {
+        match arg(name) {
+            Some(b) => Ok(b
+                .parse()
+                .map_err(|_| format!("Invalid value for {name}: {b}"))?),
+            None => Ok(true),
+        }
+    };
+
+    // parameters as written out by the tuner
+    let eval_params = |name: &str| -> Result<EvalParams, Box<dyn Error>> {
+        match arg(name) {
+            Some(path) => Ok(EvalParams::from_source(&read_to_string(path)?)
+                .map_err(|e| format!("Invalid parameters in {path}: {e}"))?),
+            None => Ok(EVAL_PARAMS),
+        }
+    };
+    // the per-side limits override the shared ones
+    let limits = |side: &str| -> Result<(Option<usize>, Option<usize>), Box<dyn Error>> {
+        let depth = optional(&format!("--depth{side}"))?.or(optional("--depth")?);
+        let nodes = optional(&format!("--nodes{side}"))?.or(optional("--nodes")?);
+        match (depth, nodes) {
+            (None, None) => Ok((Some(DEFAULT_DEPTH), None)),
+            limits => Ok(limits),
+        }
+    };
+
+    let games = number("--games", 2 * DEFAULT_OPENINGS.len())?;
+    let (depth, nodes) = limits("1")?;
+    let first = Player {
+        depth,
+        nodes,
+        contempt: contempt("--contempt1")?,
+        probcut: enabled("--probcut1")?,
+        eval_params: eval_params("--params1")?,
+    };
+    let (depth, nodes) = limits("2")?;
+    let second = Player {
+        depth,
+        nodes,
+        contempt: contempt("--contempt2")?,
+        probcut: enabled("--probcut2")?,
+        eval_params: eval_params("--params2")?,
+    };
+
+    // one FEN per line
+    let openings = match arg("--openings") {
+        Some(path) => read_to_string(path)?
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>(),
+        None => DEFAULT_OPENINGS.iter().map(|s| s.to_string()).collect(),
+    };
+    if openings.is_empty() {
+        return Err("No openings to play".into());
+    }
+
+    let score = play_match(&openings, games, first, second)?;
+    println!(
+        "Final score: {} - {} - {} (W-L-D)",
+        score.wins, score.losses, score.draws
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_game_match() -> Result<(), Box<dyn Error>> {
+        let player = Player {
+            depth: Some(2),
+            nodes: None,
+            contempt: 0,
+            probcut: true,
+            eval_params: EVAL_PARAMS,
+        };
+        let openings = [String::from(DEFAULT_OPENINGS[1])];
+        let score = play_match(&openings, 2, player, player)?;
+        assert_eq!(score.wins + score.losses + score.draws, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn node_limited_match() -> Result<(), Box<dyn Error>> {
+        // the second player plays without a tempo bonus
+        let first = Player {
+            depth: None,
+            nodes: Some(2000),
+            contempt: 0,
+            probcut: true,
+            eval_params: EVAL_PARAMS,
+        };
+        let second = Player {
+            eval_params: EvalParams {
+                tempo: [0, 0],
+                ..EVAL_PARAMS
+            },
+            ..first
+        };
+        let openings = [String::from(DEFAULT_OPENINGS[0])];
+        let score = play_match(&openings, 2, first, second)?;
+        assert_eq!(score.wins + score.losses + score.draws, 2);
+
+        // node limits make the games reproducible
+        let game = play_game(&openings[0], first, second)?;
+        assert_eq!(play_game(&openings[0], first, second)?, game);
+        Ok(())
+    }
+}
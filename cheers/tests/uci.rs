@@ -0,0 +1,60 @@
+use cheers_lib::chessgame::ChessGame;
+
+use std::{
+    error::Error,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[test]
+fn go_while_searching() -> Result<(), Box<dyn Error>> {
+    let mut engine = Command::new(env!("CARGO_BIN_EXE_cheers"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = engine.stdin.take().ok_or("no stdin")?;
+    // the second go interrupts the first search, in a position with only one legal move
+    writeln!(stdin, "position startpos")?;
+    writeln!(stdin, "go depth 30")?;
+    writeln!(stdin, "position fen k7/8/8/8/8/8/1r6/K7 w - - 0 1")?;
+    writeln!(stdin, "go depth 3")?;
+    writeln!(stdin, "quit")?;
+    drop(stdin);
+
+    let output = engine.wait_with_output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let bestmoves = stdout
+        .lines()
+        .filter(|l| l.starts_with("bestmove"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(bestmoves.len(), 2);
+    assert_ne!(bestmoves[0], "bestmove a1b2");
+    assert_eq!(bestmoves[1], "bestmove a1b2");
+    Ok(())
+}
+
+#[test]
+fn go_depth_0() -> Result<(), Box<dyn Error>> {
+    let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1";
+    let mut engine = Command::new(env!("CARGO_BIN_EXE_cheers"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = engine.stdin.take().ok_or("no stdin")?;
+    writeln!(stdin, "position fen {fen}")?;
+    writeln!(stdin, "go depth 0")?;
+    writeln!(stdin, "quit")?;
+    drop(stdin);
+
+    let output = engine.wait_with_output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let mut game = ChessGame::new();
+    game.set_from_fen(fen)?;
+    let expected = format!("info depth 0 score cp {}", game.evaluate::<()>().0);
+    assert!(stdout.lines().any(|l| l == expected), "{stdout}");
+    assert!(stdout.lines().any(|l| l.starts_with("bestmove")));
+    Ok(())
+}
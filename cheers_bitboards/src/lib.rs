@@ -8,23 +8,23 @@ pub struct BitBoard(pub u64);
 
 impl BitBoard {
     #[inline(always)]
-    pub fn empty() -> Self {
+    pub const fn empty() -> Self {
         Self(0)
     }
     #[inline(always)]
-    pub fn is_empty(&self) -> bool {
+    pub const fn is_empty(&self) -> bool {
         self.0 == 0
     }
     #[inline(always)]
-    pub fn is_not_empty(&self) -> bool {
+    pub const fn is_not_empty(&self) -> bool {
         self.0 != 0
     }
     #[inline(always)]
-    pub fn as_u64(&self) -> u64 {
+    pub const fn as_u64(&self) -> u64 {
         self.0
     }
     #[inline(always)]
-    pub fn inverse(&self) -> Self {
+    pub const fn inverse(&self) -> Self {
         Self(!self.0)
     }
     #[inline(always)]
@@ -36,9 +36,34 @@ impl BitBoard {
         self.0 &= self.0 - 1;
     }
     #[inline(always)]
-    pub fn count_ones(&self) -> u32 {
+    pub const fn count_ones(&self) -> u32 {
         self.0.count_ones()
     }
+    // const versions of the bitwise operators, for building masks at compile time
+    #[inline(always)]
+    pub const fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+    #[inline(always)]
+    pub const fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+    #[inline(always)]
+    pub const fn bitxor(self, rhs: Self) -> Self {
+        Self(self.0 ^ rhs.0)
+    }
+    #[inline(always)]
+    pub const fn not(self) -> Self {
+        Self(!self.0)
+    }
+    #[inline(always)]
+    pub const fn shl(self, n: u8) -> Self {
+        Self(self.0 << n)
+    }
+    #[inline(always)]
+    pub const fn shr(self, n: u8) -> Self {
+        Self(self.0 >> n)
+    }
     #[inline(always)]
     pub fn ishift(&self, n: i32) -> Self {
         if n > 0 {
@@ -208,7 +233,7 @@ impl Square {
     pub const NULL: Self = Self(64);
 
     #[inline(always)]
-    pub fn bitboard(&self) -> BitBoard {
+    pub const fn bitboard(&self) -> BitBoard {
         BitBoard(1u64.wrapping_shl(self.0 as u32))
     }
 
@@ -273,3 +298,29 @@ square_from_impl!(i16);
 square_from_impl!(i32);
 square_from_impl!(i64);
 square_from_impl!(isize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A_FILE: BitBoard = BitBoard(0x0101010101010101);
+    const CENTER: BitBoard = A_FILE
+        .shl(3)
+        .bitor(A_FILE.shl(4))
+        .bitand(BitBoard(0x000000FFFF000000));
+
+    #[test]
+    fn const_ops() {
+        const { assert!(CENTER.count_ones() == 4) };
+        assert_eq!(
+            CENTER,
+            Square::D4.bitboard()
+                | Square::E4.bitboard()
+                | Square::D5.bitboard()
+                | Square::E5.bitboard()
+        );
+        assert_eq!(CENTER.not(), CENTER.inverse());
+        assert_eq!(CENTER.bitxor(CENTER), BitBoard::empty());
+        assert_eq!(CENTER.shr(8), CENTER >> 8);
+    }
+}
@@ -0,0 +1,216 @@
+use super::{evaluate::relative_board_index, see::SEE_PIECE_VALUES, *};
+
+/// Score of a won endgame, above anything the general evaluation gives
+/// but below the tablebase and mate scores
+pub const KNOWN_WIN: i32 = 10_000;
+
+fn distance(a: Square, b: Square) -> i32 {
+    a.file().abs_diff(b.file()).max(a.rank().abs_diff(b.rank())) as i32
+}
+
+/// 0 in the centre up to 3 on the edge
+fn edge_distance(square: Square) -> i32 {
+    let centre_distance = |x: usize| (2 * x as i32 - 7).abs() / 2;
+    centre_distance(square.file()).max(centre_distance(square.rank()))
+}
+
+impl ChessGame {
+    /// Score of a recognised endgame from the side to move's point of view, replacing the
+    /// general evaluation. Covers king and pawn against king, and mating material against a
+    /// bare king, where the score rewards driving the enemy king to the edge
+    pub fn endgame_score(&self) -> Option<i32> {
+        let score_for = |color: ColorIndex, score: i32| {
+            if color == self.current_player {
+                score
+            } else {
+                -score
+            }
+        };
+        for color in [White, Black] {
+            if self.color_masks[!color] != self.piece_masks[(!color, King)] {
+                continue;
+            }
+            if self.has_mating_material(color) {
+                return Some(score_for(color, self.mating_score(color)));
+            }
+            if self.color_masks[color].count_ones() == 2
+                && self.piece_masks[(color, Pawn)].count_ones() == 1
+            {
+                return self
+                    .king_pawn_score(color)
+                    .map(|score| score_for(color, score));
+            }
+        }
+        None
+    }
+
+    /// Pieces that force mate against a bare king: a queen, a rook, bishops on both colours
+    /// or a bishop and knight
+    fn has_mating_material(&self, color: ColorIndex) -> bool {
+        let bishops = self.piece_masks[(color, Bishop)];
+        (self.piece_masks[(color, Queen)] | self.piece_masks[(color, Rook)]).is_not_empty()
+            || ((bishops & LIGHT_SQUARES).is_not_empty() && (bishops & DARK_SQUARES).is_not_empty())
+            || (bishops.is_not_empty() && self.piece_masks[(color, Knight)].is_not_empty())
+    }
+
+    /// Material plus the enemy king's distance from the centre and the kings' closeness.
+    /// With only a bishop and knight the enemy king is driven to a corner of the bishop's colour
+    fn mating_score(&self, color: ColorIndex) -> i32 {
+        let king = self.piece_masks[(color, King)].first_square();
+        let enemy_king = self.piece_masks[(!color, King)].first_square();
+
+        let material = [Pawn, Knight, Bishop, Rook, Queen]
+            .into_iter()
+            .map(|piece| {
+                self.piece_masks[(color, piece)].count_ones() as i32 * SEE_PIECE_VALUES[piece]
+            })
+            .sum::<i32>();
+        let mut score = KNOWN_WIN
+            + material
+            + 30 * edge_distance(enemy_king)
+            + 20 * (7 - distance(king, enemy_king));
+
+        let bishops = self.piece_masks[(color, Bishop)];
+        if (self.piece_masks[(color, Queen)] | self.piece_masks[(color, Rook)]).is_empty()
+            && bishops.count_ones() == 1
+        {
+            let corners = if (bishops & DARK_SQUARES).is_not_empty() {
+                [Square::A1, Square::H8]
+            } else {
+                [Square::H1, Square::A8]
+            };
+            let corner_distance = corners
+                .iter()
+                .map(|&corner| distance(enemy_king, corner))
+                .min()
+                .unwrap_or(0);
+            score += 40 * (7 - corner_distance);
+        }
+        score
+    }
+
+    /// King and pawn against king, `None` when the simple rules don't settle it.
+    /// Won when the enemy king is outside the pawn's square or our king stands on a key
+    /// square, drawn when the pawn is lost or the enemy king reaches a rook pawn's corner
+    fn king_pawn_score(&self, color: ColorIndex) -> Option<i32> {
+        // squares from our side's point of view, pawns moving up the board
+        let pawn = relative_board_index(self.piece_masks[(color, Pawn)].first_square(), color);
+        let king = relative_board_index(self.piece_masks[(color, King)].first_square(), color);
+        let enemy_king =
+            relative_board_index(self.piece_masks[(!color, King)].first_square(), color);
+        let our_move = self.current_player == color;
+        let promotion = Square::from(56 + pawn.file());
+        let won = KNOWN_WIN + SEE_PIECE_VALUES[Pawn] + 20 * pawn.rank() as i32;
+
+        // the enemy king takes the undefended pawn
+        if !our_move && distance(enemy_king, pawn) == 1 && distance(king, pawn) > 1 {
+            return Some(0);
+        }
+
+        // rule of the square, unless our own king is in the pawn's way
+        let pawn_moves = (7 - pawn.rank()).min(5) as i32;
+        let king_in_path = king.file() == pawn.file() && king.rank() > pawn.rank();
+        if !king_in_path && distance(enemy_king, promotion) - (!our_move as i32) > pawn_moves {
+            return Some(won);
+        }
+
+        let rook_pawn = pawn.file() == 0 || pawn.file() == 7;
+        if rook_pawn {
+            // the enemy king in front of the pawn gets to the corner
+            if enemy_king.file().abs_diff(pawn.file()) <= 1 && enemy_king.rank() > pawn.rank() {
+                return Some(0);
+            }
+            return None;
+        }
+
+        // our king on a key square wins, two ranks in front of the pawn or also one rank
+        // in front once the pawn is past the middle of the board
+        if pawn.rank() <= 4 && king.file().abs_diff(pawn.file()) <= 1 {
+            let ahead = king.rank() as i32 - pawn.rank() as i32;
+            if ahead == 2 || (pawn.rank() >= 4 && ahead == 1) {
+                return Some(won);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Search;
+    use std::error::Error;
+
+    fn endgame_score(fen: &str) -> Result<Option<i32>, Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen)?;
+        Ok(game.endgame_score())
+    }
+
+    #[test]
+    fn king_pawn_endgames() -> Result<(), Box<dyn Error>> {
+        // outside the square of the pawn, or just inside it with the move
+        assert!(endgame_score("8/6k1/8/8/1P6/8/8/K7 w - - 0 1")?.unwrap() > KNOWN_WIN);
+        assert!(endgame_score("8/6k1/8/8/1P6/8/8/K7 b - - 0 1")?.is_none());
+        assert!(endgame_score("k7/8/8/8/8/6p1/8/K7 b - - 0 1")?.unwrap() > KNOWN_WIN);
+        assert!(endgame_score("k7/8/8/8/8/6p1/8/K7 w - - 0 1")?.unwrap() < -KNOWN_WIN);
+
+        // the pawn falls
+        assert_eq!(endgame_score("8/8/8/3k4/3P4/8/8/K7 b - - 0 1")?, Some(0));
+        // key squares
+        assert!(endgame_score("8/8/3k4/8/3K4/8/3P4/8 b - - 0 1")?.unwrap() < -KNOWN_WIN);
+        assert!(endgame_score("8/8/8/3k4/8/3K4/3P4/8 w - - 0 1")?.is_none());
+        // the corner holds against a rook pawn
+        assert_eq!(endgame_score("1k6/8/8/8/P7/2K5/8/8 w - - 0 1")?, Some(0));
+
+        // the winning side is the same for both colours
+        let mut game = ChessGame::new();
+        game.set_from_fen("8/8/3k4/8/3K4/8/3P4/8 w - - 0 1")?;
+        assert!(game.endgame_score().unwrap() > KNOWN_WIN);
+        assert_eq!(game.endgame_score(), game.mirror().endgame_score());
+        Ok(())
+    }
+
+    #[test]
+    fn mating_material() -> Result<(), Box<dyn Error>> {
+        assert!(endgame_score("8/8/8/3k4/8/8/8/KR6 w - - 0 1")?.unwrap() > KNOWN_WIN);
+        assert!(endgame_score("8/8/8/3k4/8/8/8/KR6 b - - 0 1")?.unwrap() < -KNOWN_WIN);
+        assert!(endgame_score("8/8/8/3k4/8/8/8/KBN5 w - - 0 1")?.is_some());
+        // not enough to mate, and more than a bare king to mate
+        assert!(endgame_score("8/8/8/3k4/8/8/8/KNN5 w - - 0 1")?.is_none());
+        assert!(endgame_score("8/8/8/3k4/8/8/8/KB1B4 w - - 0 1")?.is_none());
+        assert!(endgame_score("8/8/8/3k4/8/8/6p1/KR6 w - - 0 1")?.is_none());
+
+        // the enemy king on the edge and our king close by is progress
+        let centre = endgame_score("8/8/8/3k4/8/8/8/KQ6 w - - 0 1")?.unwrap();
+        let edge = endgame_score("3k4/8/8/8/8/8/8/KQ6 w - - 0 1")?.unwrap();
+        let close = endgame_score("3k4/8/3K4/8/8/8/8/1Q6 w - - 0 1")?.unwrap();
+        assert!(centre < edge && edge < close);
+        // bishop and knight mate in the corner of the bishop's colour
+        let right_corner = endgame_score("8/8/8/8/8/2K5/8/k1BN4 w - - 0 1")?.unwrap();
+        let wrong_corner = endgame_score("k7/8/2K5/8/8/8/8/2BN4 w - - 0 1")?.unwrap();
+        assert!(right_corner > wrong_corner);
+        Ok(())
+    }
+
+    #[test]
+    fn queen_mate() -> Result<(), Box<dyn Error>> {
+        // both sides searching shallowly, the queen mates well within fifty moves
+        let mut game = ChessGame::new();
+        game.set_from_fen("8/8/8/3k4/8/8/8/Q3K3 w - - 0 1")?;
+        for _ in 0..100 {
+            if game.legal_moves().is_empty() {
+                break;
+            }
+            let search = Search::new(game.clone()).max_depth(5).tt_size_mb(1);
+            let (_, pv) = search.search();
+            game.make_move(pv.moves[0]);
+        }
+        assert!(
+            game.legal_moves().is_empty() && game.in_check(Black),
+            "{}",
+            game.fen()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use std::{collections::HashMap, error::Error, fmt::Display};
+
+use crate::moves::Move;
+
+use super::ChessGame;
+
+/// EPD operations by opcode, e.g. `bm`, `am`, `id` or `c0`, each with its operands.
+/// Quoted operands are unquoted, and an operation may have no operands at all
+pub type EpdOps = HashMap<String, Vec<String>>;
+
+/// Why an EPD record couldn't be parsed
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EpdError {
+    /// The position fields aren't valid
+    InvalidPosition(String),
+    /// An operation is malformed, e.g. has an unterminated string
+    InvalidOperation(String),
+}
+
+impl Display for EpdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EpdError::InvalidPosition(epd) => write!(f, "Invalid EPD position: {epd}"),
+            EpdError::InvalidOperation(op) => write!(f, "Invalid EPD operation: {op}"),
+        }
+    }
+}
+
+impl Error for EpdError {}
+
+impl ChessGame {
+    /// Parse an EPD record: the first four FEN fields followed by operations such as
+    /// `bm Qg6; id "WAC.001";`. EPD has no move counters, so they are taken from the
+    /// `hmvc` and `fmvn` operations if present, otherwise they default to 0 and 1
+    pub fn from_epd(epd: &str) -> Result<(ChessGame, EpdOps), EpdError> {
+        let epd = epd.trim();
+        let mut fields = Vec::with_capacity(4);
+        let mut rest = epd;
+        for _ in 0..4 {
+            rest = rest.trim_start();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(EpdError::InvalidPosition(epd.to_string()));
+            }
+            fields.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+
+        let ops = parse_operations(rest)?;
+        let counter = |opcode: &str, default: &str| match ops.get(opcode) {
+            Some(operands) => match operands.as_slice() {
+                [value] if value.parse::<u16>().is_ok() => Ok(value.clone()),
+                _ => Err(EpdError::InvalidOperation(opcode.to_string())),
+            },
+            None => Ok(default.to_string()),
+        };
+        let fen = format!(
+            "{} {} {}",
+            fields.join(" "),
+            counter("hmvc", "0")?,
+            counter("fmvn", "1")?
+        );
+
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen)
+            .map_err(|_| EpdError::InvalidPosition(epd.to_string()))?;
+        Ok((game, ops))
+    }
+
+    /// Whether `move_` solves an EPD test position: it must be one of the `bm` moves and
+    /// none of the `am` moves. `None` if the record has neither operation
+    pub fn epd_solved(&self, ops: &EpdOps, move_: Move) -> Option<bool> {
+        let matches = |opcode: &str| {
+            ops.get(opcode).map(|operands| {
+                operands
+                    .iter()
+                    .filter_map(|san| self.parse_san(san).ok())
+                    .any(|m| m.coords() == move_.coords())
+            })
+        };
+        match (matches("bm"), matches("am")) {
+            (None, None) => None,
+            (best, avoid) => Some(best.unwrap_or(true) && !avoid.unwrap_or(false)),
+        }
+    }
+}
+
+/// Split `op operand operand; op "quoted operand";` into operations
+fn parse_operations(text: &str) -> Result<EpdOps, EpdError> {
+    let mut ops = EpdOps::new();
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            ';' => {
+                chars.next();
+                let mut tokens = std::mem::take(&mut tokens).into_iter();
+                if let Some(opcode) = tokens.next() {
+                    ops.insert(opcode, tokens.collect());
+                }
+            }
+            '"' => {
+                chars.next();
+                let mut operand = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => operand.push(c),
+                        None => return Err(EpdError::InvalidOperation(text[start..].to_string())),
+                    }
+                }
+                tokens.push(operand);
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            _ => {
+                let mut token = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || c == ';' || c == '"' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    // every operation has to be terminated
+    if !tokens.is_empty() {
+        return Err(EpdError::InvalidOperation(tokens.join(" ")));
+    }
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Search;
+
+    const WAC_SAMPLE: &str = include_str!("../wacsample.epd");
+
+    #[test]
+    fn parse_epd() -> Result<(), EpdError> {
+        let (game, ops) = ChessGame::from_epd(
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\"; c0 \"mate in 3; or better\"; noop;",
+        )?;
+        assert_eq!(
+            game.fen(),
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1"
+        );
+        assert_eq!(ops["bm"], ["Qg6"]);
+        assert_eq!(ops["id"], ["WAC.001"]);
+        assert_eq!(ops["c0"], ["mate in 3; or better"]);
+        assert!(ops["noop"].is_empty());
+
+        // move counters from operations, several best moves
+        let (game, ops) = ChessGame::from_epd(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4 d4; hmvc 3; fmvn 12;",
+        )?;
+        assert_eq!(game.halfmove_clock(), 3);
+        assert_eq!(game.fullmove_number(), 12);
+        assert_eq!(ops["bm"], ["e4", "d4"]);
+
+        // no operations at all
+        let (_, ops) = ChessGame::from_epd("4k3/8/8/8/8/8/4P3/4K3 w - -")?;
+        assert!(ops.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_epd() {
+        let invalid_position = |epd: &str| {
+            assert_eq!(
+                ChessGame::from_epd(epd).err(),
+                Some(EpdError::InvalidPosition(epd.to_string()))
+            )
+        };
+        invalid_position("4k3/8/8/8/8/8/4P3/4K3 w -");
+        invalid_position("4k3/8/8/8/8/8/4P3/4K3 x - - bm e4;");
+
+        for epd in [
+            "4k3/8/8/8/8/8/4P3/4K3 w - - bm e4",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - id \"unterminated;",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - hmvc many;",
+        ] {
+            assert!(
+                matches!(ChessGame::from_epd(epd), Err(EpdError::InvalidOperation(_))),
+                "{epd}"
+            );
+        }
+    }
+
+    #[test]
+    fn epd_solved() -> Result<(), Box<dyn Error>> {
+        let (game, ops) = ChessGame::from_epd(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4 d4; am f3;",
+        )?;
+        assert_eq!(game.epd_solved(&ops, game.parse_san("d4")?), Some(true));
+        assert_eq!(game.epd_solved(&ops, game.parse_san("c4")?), Some(false));
+
+        let (game, ops) =
+            ChessGame::from_epd("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - am f3;")?;
+        assert_eq!(game.epd_solved(&ops, game.parse_san("c4")?), Some(true));
+        assert_eq!(game.epd_solved(&ops, game.parse_san("f3")?), Some(false));
+
+        let (game, ops) =
+            ChessGame::from_epd("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - id \"x\";")?;
+        assert_eq!(game.epd_solved(&ops, game.parse_san("e4")?), None);
+        Ok(())
+    }
+
+    #[test]
+    fn wac_sample() -> Result<(), Box<dyn Error>> {
+        for epd in WAC_SAMPLE.lines() {
+            let (game, ops) = ChessGame::from_epd(epd)?;
+            let search = Search::new(game.clone()).max_depth(6).tt_size_mb(8);
+            let (_, pv) = search.search();
+            assert_eq!(
+                game.epd_solved(&ops, pv.moves[0]),
+                Some(true),
+                "{} played {}",
+                ops["id"][0],
+                pv.moves[0].coords()
+            );
+        }
+        Ok(())
+    }
+}
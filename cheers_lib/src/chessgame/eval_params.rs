@@ -13,6 +13,7 @@ pub struct EvalParams {
     pub knight_behind_pawn: [i32; 2],
     pub knight_king_distance: [[i32; 2]; 4],
     pub knight_outpost: [[i32; 2]; 2],
+    pub knight_trapped: [i32; 2],
 
     pub bishop_mobility: [[i32; 2]; 14],
     pub bishop_behind_pawn: [i32; 2],
@@ -20,6 +21,7 @@ pub struct EvalParams {
     pub bishop_outpost: [[i32; 2]; 2],
     pub bishop_pair: [i32; 2],
     pub bishop_long_diagonal: [i32; 2],
+    pub bishop_trapped: [i32; 2],
 
     pub rook_mobility: [[i32; 2]; 15],
     pub rook_open_file: [[i32; 2]; 2],
@@ -29,14 +31,72 @@ pub struct EvalParams {
     pub queen_discovery_risk: [i32; 2],
 
     pub king_defenders: [[i32; 2]; 12],
+    // by rank distance from the king: [1, 2, 3+, no pawn]
+    pub pawn_shield: [[i32; 2]; 4],
+    // by rank distance from the king: [1, 2, 3, 4+]
+    pub pawn_storm: [[i32; 2]; 4],
+    pub back_rank_weakness: [i32; 2],
+    // by attack units of enemy pieces reaching the king area, see `KING_ATTACK_WEIGHTS`
+    pub king_attacks: [[i32; 2]; 16],
 
-    pub passed_pawn: [i32; 2],
+    // by relative rank
+    pub passed_pawn_by_rank: [[i32; 2]; 8],
+    // an enemy piece directly in front of a passed pawn
+    pub passer_blockaded: [i32; 2],
+    // enemy pieces attacking squares on the way to promotion
+    pub passer_path_attacked: [i32; 2],
+    // by the distance from our king and their king to each passed pawn
+    pub passer_king_distance: [[i32; 2]; 8],
+    pub passer_enemy_king_distance: [[i32; 2]; 8],
     pub double_pawn: [i32; 2],
     pub isolated_pawn: [[i32; 2]; 8],
-    // pub backward_pawn: [[i32; 2]; 8],
+    pub backward_pawn: [i32; 2],
+    // defended by or side by side with another pawn
+    pub connected_pawn: [i32; 2],
+    pub space: [i32; 2],
+    // bonus for the side to move
+    pub tempo: [i32; 2],
+
     pub piece_tables: PieceTables,
+
+    // endgame scale out of 128 for opposite coloured bishops, [without rooks, with rooks].
+    // Scales the eval rather than adding to it, so it has no trace and comes last
+    pub opposite_bishops: [i32; 2],
 }
 
+impl EvalParams {
+    /// Read back parameters written out by the tuner as Rust source, whose numbers are
+    /// the fields in declaration order. Names and punctuation are skipped.
+    pub fn from_source(source: &str) -> Result<Self, String> {
+        const LEN: usize = std::mem::size_of::<EvalParams>() / std::mem::size_of::<i32>();
+        let numbers = source
+            .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .filter_map(|token| token.parse::<i32>().ok())
+            .collect::<Vec<_>>();
+        if numbers.len() != LEN {
+            return Err(format!(
+                "Expected {LEN} parameters, found {}",
+                numbers.len()
+            ));
+        }
+
+        let mut params = EvalParams::default();
+        // SAFETY: EvalParams and the PieceValues and PieceTables inside it are all repr(C)
+        // and only made of i32s, so there is no padding, as checked by _I32_LAYOUT below and
+        // by the Pod derive with eval-tracing
+        let slots =
+            unsafe { std::slice::from_raw_parts_mut(&mut params as *mut Self as *mut i32, LEN) };
+        slots.copy_from_slice(&numbers);
+        Ok(params)
+    }
+}
+
+/// [`EvalParams::from_source`] writes the parameters as a slice of i32s
+const _I32_LAYOUT: () = assert!(
+    std::mem::size_of::<EvalParams>().is_multiple_of(std::mem::size_of::<i32>())
+        && std::mem::align_of::<EvalParams>() == std::mem::align_of::<i32>()
+);
+
 #[cfg(feature = "eval-tracing")]
 impl EvalParams {
     pub const LEN: usize = std::mem::size_of::<Self>() / std::mem::size_of::<i32>();
@@ -67,6 +127,7 @@ pub struct EvalTrace {
     pub knights_behind_pawns: [i32; 2],
     pub knight_king_distance: [[i32; 2]; 4],
     pub knight_outposts: [[i32; 2]; 2],
+    pub knights_trapped: [i32; 2],
 
     pub bishop_mobility: [[i32; 2]; 14],
     pub bishops_behind_pawns: [i32; 2],
@@ -74,6 +135,7 @@ pub struct EvalTrace {
     pub bishop_outposts: [[i32; 2]; 2],
     pub bishop_pair: [i32; 2],
     pub bishop_long_diagonals: [i32; 2],
+    pub bishops_trapped: [i32; 2],
 
     pub rook_mobility: [[i32; 2]; 15],
     pub rook_open_files: [[i32; 2]; 2],
@@ -83,11 +145,23 @@ pub struct EvalTrace {
     pub queen_discovery_risks: [i32; 2],
 
     pub king_defenders: [[i32; 2]; 12],
+    pub pawn_shield: [[i32; 2]; 4],
+    pub pawn_storm: [[i32; 2]; 4],
+    pub back_rank_weaknesses: [i32; 2],
+    pub king_attacks: [[i32; 2]; 16],
 
-    pub passed_pawns: [i32; 2],
+    pub passed_pawns_by_rank: [[i32; 2]; 8],
+    pub passers_blockaded: [i32; 2],
+    pub passer_paths_attacked: [i32; 2],
+    pub passer_king_distance: [[i32; 2]; 8],
+    pub passer_enemy_king_distance: [[i32; 2]; 8],
     pub double_pawns: [i32; 2],
     pub isolated_pawns: [[i32; 2]; 8],
-    // pub backward_pawns: [[i32; 2]; 8],
+    pub backward_pawns: [i32; 2],
+    pub connected_pawns: [i32; 2],
+    pub space: [i32; 2],
+    pub tempo: [i32; 2],
+
     pub pawn_placement: [[i32; 2]; 64],
     pub knight_placement: [[i32; 2]; 64],
     pub bishop_placement: [[i32; 2]; 64],
@@ -119,6 +193,9 @@ impl Default for EvalTrace {
 pub const CHECKMATE_SCORE: i32 = 20000;
 pub const DRAW_SCORE: i32 = 0;
 
+/// Attack units for each enemy piece type that attacks the king area
+pub const KING_ATTACK_WEIGHTS: [usize; 6] = [0, 2, 2, 3, 5, 0];
+
 pub const EVAL_PARAMS: EvalParams = EvalParams {
     piece_values: PieceValues([
         [45, 121],
@@ -142,6 +219,7 @@ pub const EVAL_PARAMS: EvalParams = EvalParams {
     knight_behind_pawn: [9, 4],
     knight_king_distance: [[-18, 4], [-10, -15], [-19, -5], [-31, -15]],
     knight_outpost: [[0, -4], [3, 12]],
+    knight_trapped: [-40, -30],
     bishop_mobility: [
         [-154, -30],
         [-67, -48],
@@ -163,6 +241,7 @@ pub const EVAL_PARAMS: EvalParams = EvalParams {
     bishop_outpost: [[-11, 1], [1, 1]],
     bishop_pair: [-4, 94],
     bishop_long_diagonal: [0, -5],
+    bishop_trapped: [-80, -60],
     rook_mobility: [
         [-44, -132],
         [-121, -42],
@@ -227,7 +306,59 @@ pub const EVAL_PARAMS: EvalParams = EvalParams {
         [0, 0],
         [0, 0],
     ],
-    passed_pawn: [-6, 32],
+    pawn_shield: [[16, -4], [8, -2], [-6, 0], [-18, 2]],
+    pawn_storm: [[-4, 0], [-20, -2], [-10, 0], [-4, 0]],
+    back_rank_weakness: [-30, -20],
+    king_attacks: [
+        [0, 0],
+        [-2, -1],
+        [-6, -2],
+        [-12, -4],
+        [-20, -6],
+        [-30, -9],
+        [-42, -12],
+        [-56, -16],
+        [-72, -20],
+        [-90, -25],
+        [-110, -30],
+        [-132, -36],
+        [-156, -42],
+        [-182, -49],
+        [-210, -56],
+        [-240, -64],
+    ],
+    passed_pawn_by_rank: [
+        [0, 0],
+        [-6, 8],
+        [-8, 12],
+        [-4, 26],
+        [10, 50],
+        [28, 90],
+        [54, 150],
+        [0, 0],
+    ],
+    passer_blockaded: [-8, -24],
+    passer_path_attacked: [-4, -16],
+    passer_king_distance: [
+        [0, 0],
+        [0, 16],
+        [0, 10],
+        [0, 4],
+        [0, -2],
+        [0, -8],
+        [0, -14],
+        [0, -20],
+    ],
+    passer_enemy_king_distance: [
+        [0, -30],
+        [0, -22],
+        [0, -12],
+        [0, -4],
+        [0, 4],
+        [0, 12],
+        [0, 20],
+        [0, 28],
+    ],
     double_pawn: [-16, -54],
     isolated_pawn: [
         [-11, -23],
@@ -239,6 +370,10 @@ pub const EVAL_PARAMS: EvalParams = EvalParams {
         [-6, -20],
         [-19, -16],
     ],
+    backward_pawn: [-10, -8],
+    connected_pawn: [8, 6],
+    space: [1, 0],
+    tempo: [15, 5],
     piece_tables: PieceTables([
         [
             [0, 0],
@@ -637,4 +772,5 @@ pub const EVAL_PARAMS: EvalParams = EvalParams {
             [38, -88],
         ],
     ]),
+    opposite_bishops: [32, 96],
 };
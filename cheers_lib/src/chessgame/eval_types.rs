@@ -1,4 +1,4 @@
-use std::ops::{Add, AddAssign, Index, IndexMut, Sub};
+use std::ops::{Add, AddAssign, Index, IndexMut, Neg, Sub};
 
 #[cfg(feature = "eval-tracing")]
 use bytemuck::{Pod, Zeroable};
@@ -22,7 +22,9 @@ pub struct EvalInfo {
     pub mobility_area: [BitBoard; 2],
     pub behind_pawns: [BitBoard; 2],
     pub outposts: [BitBoard; 2],
+    pub pawn_attacks: [BitBoard; 2],
     pub seventh_rank: [BitBoard; 2],
+    pub trap_squares: [BitBoard; 2],
     pub king_square: [Square; 2],
     pub king_area: [BitBoard; 2],
 }
@@ -68,6 +70,17 @@ impl Sub<EvalScore> for EvalScore {
     }
 }
 
+impl Neg for EvalScore {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self {
+            mg: -self.mg,
+            eg: -self.eg,
+        }
+    }
+}
+
 pub trait TraceTarget {
     const TRACING: bool = false;
     fn term(&mut self, _term: impl FnMut(&mut EvalTrace)) {}
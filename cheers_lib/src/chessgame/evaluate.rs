@@ -7,11 +7,21 @@ pub struct EvalContext<'g, T> {
     game: &'g ChessGame,
     trace: &'g mut T,
     params: &'g EvalParams,
+    /// Cache for the pawn structure terms, not used when tracing
+    pawn_table: Option<&'g mut PawnHashTable>,
 }
 
 impl<'g, T: TraceTarget + Default> EvalContext<'g, T> {
     #[inline]
     pub fn evaluate(&mut self) -> i32 {
+        // the general evaluation can't find the way to win simple endgames,
+        // and their scores aren't linear in the parameters
+        if !T::TRACING {
+            if let Some(score) = self.game.endgame_score() {
+                return score;
+            }
+        }
+
         let mut eval = EvalScore::zero();
 
         self.trace
@@ -19,33 +29,7 @@ impl<'g, T: TraceTarget + Default> EvalContext<'g, T> {
 
         let phase = self.game.game_phase();
 
-        let white_king_square = self.game.piece_masks()[(White, King)].first_square();
-        let black_king_square = self.game.piece_masks()[(Black, King)].first_square();
-
-        let white_king_attacks = lookup_king(white_king_square);
-        let black_king_attacks = lookup_king(black_king_square);
-
-        // initialise eval info
-        let info = EvalInfo {
-            mobility_area: [
-                self.game.mobility_area(White),
-                self.game.mobility_area(Black),
-            ],
-            behind_pawns: [
-                self.game.piece_masks()[(White, Pawn)] >> 8,
-                self.game.piece_masks()[(Black, Pawn)] << 8,
-            ],
-            outposts: [
-                self.game.pawn_attack_spans(Black).inverse(),
-                self.game.pawn_attacks(White).inverse(),
-            ],
-            seventh_rank: [SEVENTH_RANK, SECOND_RANK],
-            king_square: [white_king_square, black_king_square],
-            king_area: [
-                white_king_attacks | (white_king_attacks << 8),
-                black_king_attacks | (black_king_attacks >> 8),
-            ],
-        };
+        let info = EvalInfo::new(self.game);
 
         eval += self.evaluate_knights(self.game.current_player(), &info, self.params)
             - self.evaluate_knights(!self.game.current_player(), &info, self.params);
@@ -59,15 +43,89 @@ impl<'g, T: TraceTarget + Default> EvalContext<'g, T> {
         eval += self.evaluate_queens(self.game.current_player(), &info, self.params)
             - self.evaluate_queens(!self.game.current_player(), &info, self.params);
 
-        eval += self.evaluate_pawns(self.game.current_player(), &info, self.params)
-            - self.evaluate_pawns(!self.game.current_player(), &info, self.params);
+        eval += match self.game.current_player() {
+            White => self.pawn_structure(&info),
+            Black => -self.pawn_structure(&info),
+        };
+        eval += self.evaluate_passers(self.game.current_player(), &info, self.params)
+            - self.evaluate_passers(!self.game.current_player(), &info, self.params);
 
         eval += self.evaluate_king(self.game.current_player(), &info, self.params)
             - self.evaluate_king(!self.game.current_player(), &info, self.params);
 
+        eval += self.evaluate_space(self.game.current_player(), &info, self.params)
+            - self.evaluate_space(!self.game.current_player(), &info, self.params);
+
+        // tempo
+        eval.mg += self.params.tempo[Midgame];
+        eval.eg += self.params.tempo[Endgame];
+        self.trace.term(|t| t.tempo[self.game.current_player()] = 1);
+
+        // drawish endgames are scaled before blending, the midgame is left alone
+        eval.eg = eval.eg * self.scale_factor() / 128;
+
         ((eval.mg * (256 - phase)) + (eval.eg * phase)) / 256
     }
 
+    /// Pawn structure score from white's point of view, from the pawn hash table if possible
+    #[inline]
+    fn pawn_structure(&mut self, info: &EvalInfo) -> EvalScore {
+        let pawn_table = match self.pawn_table.take() {
+            Some(pawn_table) if !T::TRACING => pawn_table,
+            pawn_table => {
+                self.pawn_table = pawn_table;
+                return self.evaluate_pawn_structure(White, info, self.params)
+                    - self.evaluate_pawn_structure(Black, info, self.params);
+            }
+        };
+
+        let pawn_hash = self.game.pawn_hash();
+        let score = match pawn_table.get(pawn_hash) {
+            Some(score) => score,
+            None => {
+                let score = self.evaluate_pawn_structure(White, info, self.params)
+                    - self.evaluate_pawn_structure(Black, info, self.params);
+                pawn_table.set(pawn_hash, score);
+                score
+            }
+        };
+        self.pawn_table = Some(pawn_table);
+        score
+    }
+
+    /// Endgame scale out of 128 for drawish opposite coloured bishop positions.
+    /// Only applies without knights or queens, with at most one rook each and at most
+    /// two pawns between the sides, as more gives the stronger side real winning chances.
+    #[inline]
+    pub fn scale_factor(&self) -> i32 {
+        let masks = self.game.piece_masks();
+        let white_bishops = masks[(White, Bishop)];
+        let black_bishops = masks[(Black, Bishop)];
+        let opposite_bishops = white_bishops.count_ones() == 1
+            && black_bishops.count_ones() == 1
+            && ((white_bishops & LIGHT_SQUARES).is_empty()
+                != (black_bishops & LIGHT_SQUARES).is_empty());
+        let pawn_difference = masks[(White, Pawn)]
+            .count_ones()
+            .abs_diff(masks[(Black, Pawn)].count_ones());
+        if !opposite_bishops
+            || pawn_difference > 2
+            || (masks[(White, Knight)] | masks[(Black, Knight)]).is_not_empty()
+            || (masks[(White, Queen)] | masks[(Black, Queen)]).is_not_empty()
+        {
+            return 128;
+        }
+
+        match (
+            masks[(White, Rook)].count_ones(),
+            masks[(Black, Rook)].count_ones(),
+        ) {
+            (0, 0) => self.params.opposite_bishops[0],
+            (1, 1) => self.params.opposite_bishops[1],
+            _ => 128,
+        }
+    }
+
     #[inline]
     pub fn evaluate_knights(
         &mut self,
@@ -129,6 +187,13 @@ impl<'g, T: TraceTarget + Default> EvalContext<'g, T> {
             eval.mg += params.knight_mobility[mobility][Midgame];
             eval.eg += params.knight_mobility[mobility][Endgame];
             self.trace.term(|t| t.knight_mobility[mobility][color] += 1);
+
+            // trapped on the edge in the enemy camp
+            if mobility <= 1 && (knight.bitboard() & info.trap_squares[color]).is_not_empty() {
+                eval.mg += params.knight_trapped[Midgame];
+                eval.eg += params.knight_trapped[Endgame];
+                self.trace.term(|t| t.knights_trapped[color] += 1);
+            }
         }
         eval
     }
@@ -211,6 +276,13 @@ impl<'g, T: TraceTarget + Default> EvalContext<'g, T> {
             eval.mg += params.bishop_mobility[mobility][Midgame];
             eval.eg += params.bishop_mobility[mobility][Endgame];
             self.trace.term(|t| t.bishop_mobility[mobility][color] += 1);
+
+            // trapped on the edge in the enemy camp
+            if mobility <= 1 && (bishop.bitboard() & info.trap_squares[color]).is_not_empty() {
+                eval.mg += params.bishop_trapped[Midgame];
+                eval.eg += params.bishop_trapped[Endgame];
+                self.trace.term(|t| t.bishops_trapped[color] += 1);
+            }
         }
         eval
     }
@@ -331,14 +403,102 @@ impl<'g, T: TraceTarget + Default> EvalContext<'g, T> {
         eval.eg += params.king_defenders[defenders][Endgame];
         self.trace.term(|t| t.king_defenders[defenders][color] += 1);
 
+        // enemy pieces attacking the king area, weighted by piece type
+        let occupied = self.game.combined();
+        let mut attack_units = 0;
+        for piece in [Knight, Bishop, Rook, Queen] {
+            for square in self.game.piece_masks()[(!color, piece)] {
+                let attacks = match piece {
+                    Knight => lookup_knight(square),
+                    Bishop => lookup_bishop(square, occupied),
+                    Rook => lookup_rook(square, occupied),
+                    _ => lookup_queen(square, occupied),
+                };
+                if (attacks & info.king_area[color]).is_not_empty() {
+                    attack_units += KING_ATTACK_WEIGHTS[piece as usize];
+                }
+            }
+        }
+        let attacks = attack_units.min(15);
+        eval.mg += params.king_attacks[attacks][Midgame];
+        eval.eg += params.king_attacks[attacks][Endgame];
+        self.trace.term(|t| t.king_attacks[attacks][color] += 1);
+
+        // pawn shield and pawn storms on the king's file and the files next to it
+        let king_file = info.king_square[color].file();
+        let king_rank = king.rank();
+        // relative rank distance of the closest pawn in front of the king
+        let closest_in_front = |pawns: BitBoard| {
+            pawns
+                .map(|pawn| relative_board_index(pawn, color).rank())
+                .filter(|&rank| rank > king_rank)
+                .map(|rank| rank - king_rank)
+                .min()
+        };
+        for &file in &FILES[king_file.saturating_sub(1)..=(king_file + 1).min(7)] {
+            let shield = match closest_in_front(self.game.piece_masks()[(color, Pawn)] & file) {
+                Some(distance) => distance.min(3) - 1,
+                None => 3,
+            };
+            eval.mg += params.pawn_shield[shield][Midgame];
+            eval.eg += params.pawn_shield[shield][Endgame];
+            self.trace.term(|t| t.pawn_shield[shield][color] += 1);
+
+            if let Some(distance) = closest_in_front(self.game.piece_masks()[(!color, Pawn)] & file)
+            {
+                let storm = distance.min(4) - 1;
+                eval.mg += params.pawn_storm[storm][Midgame];
+                eval.eg += params.pawn_storm[storm][Endgame];
+                self.trace.term(|t| t.pawn_storm[storm][color] += 1);
+            }
+        }
+
+        // back rank weakness: no luft in front of a king on the back rank,
+        // with enemy rooks or queens on the back rank or on files without our pawns
+        let (back_rank, second_rank) = match color {
+            White => (FIRST_RANK, SECOND_RANK),
+            Black => (EIGHTH_RANK, SEVENTH_RANK),
+        };
+        let luft = lookup_king(info.king_square[color]) & second_rank;
+        let enemy_heavies =
+            self.game.piece_masks()[(!color, Rook)] | self.game.piece_masks()[(!color, Queen)];
+        if (info.king_square[color].bitboard() & back_rank).is_not_empty()
+            && luft.is_not_empty()
+            && (luft & self.game.piece_masks()[(color, Pawn)]) == luft
+        {
+            let threatened = enemy_heavies.into_iter().any(|sq| {
+                (sq.bitboard() & back_rank).is_not_empty()
+                    || (FILES[sq.file()] & self.game.piece_masks()[(color, Pawn)]).is_empty()
+            });
+            if threatened {
+                eval.mg += params.back_rank_weakness[Midgame];
+                eval.eg += params.back_rank_weakness[Endgame];
+                self.trace.term(|t| t.back_rank_weaknesses[color] += 1);
+            }
+        }
+
         eval
     }
 
+    /// Every pawn term, the cached pawn structure and the passed pawn terms that depend
+    /// on the other pieces
     #[inline]
     pub fn evaluate_pawns(
         &mut self,
         color: ColorIndex,
-        _info: &EvalInfo,
+        info: &EvalInfo,
+        params: &EvalParams,
+    ) -> EvalScore {
+        self.evaluate_pawn_structure(color, info, params)
+            + self.evaluate_passers(color, info, params)
+    }
+
+    /// Pawn terms that only depend on where the pawns are, so they can be cached by pawn hash
+    #[inline]
+    pub fn evaluate_pawn_structure(
+        &mut self,
+        color: ColorIndex,
+        info: &EvalInfo,
         params: &EvalParams,
     ) -> EvalScore {
         let mut eval = EvalScore::zero();
@@ -349,15 +509,14 @@ impl<'g, T: TraceTarget + Default> EvalContext<'g, T> {
         eval.eg += params.piece_values[(Endgame, Pawn)] * count;
         self.trace.term(|t| t.pawn_count[color] = count);
 
-        // passed pawns
-        let front_spans = self.game.pawn_front_spans(!color);
-        let all_front_spans =
-            front_spans | (front_spans & NOT_H_FILE) << 1 | (front_spans & NOT_A_FILE) >> 1;
-        let passers = (self.game.piece_masks()[(color, Pawn)] & all_front_spans.inverse())
-            .count_ones() as i32;
-        eval.mg += params.passed_pawn[Midgame] * passers;
-        eval.eg += params.passed_pawn[Endgame] * passers;
-        self.trace.term(|t| t.passed_pawns[color] = passers);
+        // passed pawns are worth much more the further they have advanced
+        for passer in self.game.passed_pawns(color) {
+            let rank = relative_board_index(passer, color).rank();
+            eval.mg += params.passed_pawn_by_rank[rank][Midgame];
+            eval.eg += params.passed_pawn_by_rank[rank][Endgame];
+            self.trace
+                .term(|t| t.passed_pawns_by_rank[rank][color] += 1);
+        }
 
         // unsupported double pawns
         let pawns = self.game.piece_masks()[(color, Pawn)];
@@ -396,13 +555,201 @@ impl<'g, T: TraceTarget + Default> EvalContext<'g, T> {
                 self.trace
                     .term(|t| t.isolated_pawns[pawn.file()][color] += 1);
             }
+
+            // backward pawns: an enemy pawn controls the stop square and the pawns next to it
+            // have all advanced further, so it can never be supported
+            let stop = match color {
+                White => board << 8,
+                Black => board >> 8,
+            };
+            let supportable = neighbors
+                .into_iter()
+                .any(|p| relative_board_index(p, color).rank() <= relative_pawn.rank());
+            if neighbors.is_not_empty()
+                && !supportable
+                && (stop & info.pawn_attacks[!color]).is_not_empty()
+            {
+                eval.mg += params.backward_pawn[Midgame];
+                eval.eg += params.backward_pawn[Endgame];
+                self.trace.term(|t| t.backward_pawns[color] += 1);
+            }
+
+            // connected pawns: defended by a pawn or beside one
+            let supporters = lookup_pawn_attack(pawn, !color)
+                | (board & NOT_H_FILE) << 1
+                | (board & NOT_A_FILE) >> 1;
+            if (supporters & self.game.piece_masks()[(color, Pawn)]).is_not_empty() {
+                eval.mg += params.connected_pawn[Midgame];
+                eval.eg += params.connected_pawn[Endgame];
+                self.trace.term(|t| t.connected_pawns[color] += 1);
+            }
+        }
+
+        eval
+    }
+
+    /// Passed pawn terms that depend on the pieces as well as the pawns
+    #[inline]
+    pub fn evaluate_passers(
+        &mut self,
+        color: ColorIndex,
+        info: &EvalInfo,
+        params: &EvalParams,
+    ) -> EvalScore {
+        let mut eval = EvalScore::zero();
+
+        let passer_mask = self.game.passed_pawns(color);
+        let enemy_attacks = if passer_mask.is_not_empty() {
+            self.game.all_attacks(!color, self.game.combined())
+        } else {
+            BitBoard::empty()
+        };
+        for passer in passer_mask {
+            let mut path = match color {
+                White => passer.bitboard() << 8,
+                Black => passer.bitboard() >> 8,
+            };
+            let stop = path;
+            path |= match color {
+                White => path << 8 | path << 16 | path << 24 | path << 32 | path << 40,
+                Black => path >> 8 | path >> 16 | path >> 24 | path >> 32 | path >> 40,
+            };
+
+            // blockaded by an enemy piece
+            if (stop & self.game.color_masks[!color]).is_not_empty() {
+                eval.mg += params.passer_blockaded[Midgame];
+                eval.eg += params.passer_blockaded[Endgame];
+                self.trace.term(|t| t.passers_blockaded[color] += 1);
+            }
+
+            // enemy pieces covering the way to promotion
+            if (path & enemy_attacks).is_not_empty() {
+                eval.mg += params.passer_path_attacked[Midgame];
+                eval.eg += params.passer_path_attacked[Endgame];
+                self.trace.term(|t| t.passer_paths_attacked[color] += 1);
+            }
+        }
+
+        // king distance to passed pawns, mostly relevant in the endgame:
+        // our king escorts the pawn, their king tries to stop it
+        let king_distance = |king: Square, pawn: Square| {
+            (king.file().abs_diff(pawn.file()))
+                .max(king.rank().abs_diff(pawn.rank()))
+                .min(7)
+        };
+        for passer in passer_mask {
+            let distance = king_distance(info.king_square[color], passer);
+            eval.mg += params.passer_king_distance[distance][Midgame];
+            eval.eg += params.passer_king_distance[distance][Endgame];
+            self.trace
+                .term(|t| t.passer_king_distance[distance][color] += 1);
+
+            let distance = king_distance(info.king_square[!color], passer);
+            eval.mg += params.passer_enemy_king_distance[distance][Midgame];
+            eval.eg += params.passer_enemy_king_distance[distance][Endgame];
+            self.trace
+                .term(|t| t.passer_enemy_king_distance[distance][color] += 1);
         }
 
         eval
     }
+
+    #[inline]
+    pub fn evaluate_space(
+        &mut self,
+        color: ColorIndex,
+        info: &EvalInfo,
+        params: &EvalParams,
+    ) -> EvalScore {
+        let mut eval = EvalScore::zero();
+
+        // safe central squares behind our own pawns
+        let pawns = self.game.piece_masks()[(color, Pawn)];
+        let mut behind_pawns = match color {
+            White => pawns >> 8,
+            Black => pawns << 8,
+        };
+        behind_pawns |= match color {
+            White => behind_pawns >> 8 | behind_pawns >> 16,
+            Black => behind_pawns << 8 | behind_pawns << 16,
+        };
+        let space_mask = match color {
+            White => WHITE_SPACE,
+            Black => BLACK_SPACE,
+        };
+        let safe_squares = (space_mask
+            & behind_pawns
+            & (pawns | info.pawn_attacks[!color]).inverse())
+        .count_ones() as i32;
+
+        // space is worth more with more minor pieces left to use it
+        let minor_pieces = (self.game.piece_masks()[(White, Knight)]
+            | self.game.piece_masks()[(White, Bishop)]
+            | self.game.piece_masks()[(Black, Knight)]
+            | self.game.piece_masks()[(Black, Bishop)])
+            .count_ones() as i32;
+
+        let space = safe_squares * minor_pieces;
+        eval.mg += params.space[Midgame] * space;
+        eval.eg += params.space[Endgame] * space;
+        self.trace.term(|t| t.space[color] = space);
+
+        eval
+    }
+}
+
+impl EvalInfo {
+    pub fn new(game: &ChessGame) -> Self {
+        let white_king_square = game.piece_masks()[(White, King)].first_square();
+        let black_king_square = game.piece_masks()[(Black, King)].first_square();
+
+        let white_king_attacks = lookup_king(white_king_square);
+        let black_king_attacks = lookup_king(black_king_square);
+
+        Self {
+            mobility_area: [game.mobility_area(White), game.mobility_area(Black)],
+            behind_pawns: [
+                game.piece_masks()[(White, Pawn)] >> 8,
+                game.piece_masks()[(Black, Pawn)] << 8,
+            ],
+            // squares enemy pawns can never attack
+            outposts: [
+                game.pawn_attack_spans(Black).inverse(),
+                game.pawn_attack_spans(White).inverse(),
+            ],
+            pawn_attacks: [game.pawn_attacks(White), game.pawn_attacks(Black)],
+            seventh_rank: [SEVENTH_RANK, SECOND_RANK],
+            trap_squares: [WHITE_TRAP_SQUARES, BLACK_TRAP_SQUARES],
+            king_square: [white_king_square, black_king_square],
+            king_area: [
+                white_king_attacks | (white_king_attacks << 8),
+                black_king_attacks | (black_king_attacks >> 8),
+            ],
+        }
+    }
 }
 
 impl ChessGame {
+    /// Endgame scale out of 128 with the default parameters, see [`EvalContext::scale_factor`]
+    pub fn scale_factor(&self) -> i32 {
+        EvalContext {
+            game: self,
+            trace: &mut (),
+            params: &EVAL_PARAMS,
+            pawn_table: None,
+        }
+        .scale_factor()
+    }
+
+    /// Pawns with no enemy pawns in front of them on their own or adjacent files
+    #[inline]
+    pub fn passed_pawns(&self, color: ColorIndex) -> BitBoard {
+        let front_spans = self.pawn_front_spans(!color);
+        let all_front_spans =
+            front_spans | (front_spans & NOT_H_FILE) << 1 | (front_spans & NOT_A_FILE) >> 1;
+        self.piece_masks[(color, Pawn)] & all_front_spans.inverse()
+    }
+
     #[inline]
     pub fn mobility_area(&self, color: ColorIndex) -> BitBoard {
         let blocked_pawns = match color {
@@ -421,6 +768,26 @@ impl ChessGame {
             game: self,
             trace: &mut trace,
             params: &EVAL_PARAMS,
+            pawn_table: None,
+        };
+        let score = eval.evaluate();
+        (score, trace)
+    }
+
+    /// [`ChessGame::evaluate`] with `params` and the pawn structure cached in `pawn_table`,
+    /// which must only be used with the same parameters
+    #[inline]
+    pub fn evaluate_with_pawn_table<T: TraceTarget + Default>(
+        &self,
+        pawn_table: &mut PawnHashTable,
+        params: &EvalParams,
+    ) -> (i32, T) {
+        let mut trace = T::default();
+        let mut eval = EvalContext {
+            game: self,
+            trace: &mut trace,
+            params,
+            pawn_table: Some(pawn_table),
         };
         let score = eval.evaluate();
         (score, trace)
@@ -458,3 +825,460 @@ impl ChessGame {
 pub fn relative_board_index(i: Square, color: ColorIndex) -> Square {
     (*i as usize ^ (56 * color as usize)).into()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+
+    fn space_score(fen: &str, color: ColorIndex) -> Result<EvalScore, Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen)?;
+        let info = EvalInfo::new(&game);
+        let mut eval = EvalContext {
+            game: &game,
+            trace: &mut (),
+            params: &EVAL_PARAMS,
+            pawn_table: None,
+        };
+        Ok(eval.evaluate_space(color, &info, &EVAL_PARAMS))
+    }
+
+    #[test]
+    fn space() -> Result<(), Box<dyn Error>> {
+        let fen = "rnbqkbnr/pppppppp/8/8/2PPPP2/8/PP4PP/RNBQKBNR b KQkq - 0 1";
+        let cramped = space_score(fen, Black)?;
+        let expansive = space_score(fen, White)?;
+        assert!(expansive.mg > cramped.mg);
+
+        // no pieces to make use of the space
+        let pawns_only = space_score("4k3/pppppppp/8/8/2PPPP2/8/PP4PP/4K3 b - - 0 1", White)?;
+        assert_eq!(pawns_only.mg, 0);
+
+        Ok(())
+    }
+
+    fn bishop_score(
+        fen: &str,
+        color: ColorIndex,
+        params: &EvalParams,
+    ) -> Result<EvalScore, Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen)?;
+        let info = EvalInfo::new(&game);
+        let mut eval = EvalContext {
+            game: &game,
+            trace: &mut (),
+            params,
+            pawn_table: None,
+        };
+        Ok(eval.evaluate_bishops(color, &info, params))
+    }
+
+    #[test]
+    fn trapped_bishop() -> Result<(), Box<dyn Error>> {
+        let untrapped_params = EvalParams {
+            bishop_trapped: [0, 0],
+            ..EVAL_PARAMS
+        };
+
+        // Bxa7 b6 shuts the bishop in
+        let trapped = "4k3/B1p5/1p6/8/8/8/8/4K3 w - - 0 1";
+        let penalty = bishop_score(trapped, White, &EVAL_PARAMS)?
+            - bishop_score(trapped, White, &untrapped_params)?;
+        assert_eq!(penalty.mg, EVAL_PARAMS.bishop_trapped[Midgame]);
+        assert_eq!(penalty.eg, EVAL_PARAMS.bishop_trapped[Endgame]);
+
+        // the bishop can still get out
+        let free = "4k3/B1p5/8/1p6/8/8/8/4K3 w - - 0 1";
+        let penalty = bishop_score(free, White, &EVAL_PARAMS)?
+            - bishop_score(free, White, &untrapped_params)?;
+        assert_eq!(penalty.mg, 0);
+        assert_eq!(penalty.eg, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bishop_pair() -> Result<(), Box<dyn Error>> {
+        let no_pair_params = EvalParams {
+            bishop_pair: [0, 0],
+            ..EVAL_PARAMS
+        };
+        let pair_bonus =
+            |fen: &str| -> Result<EvalScore, Box<dyn Error>> {
+                Ok(bishop_score(fen, White, &EVAL_PARAMS)?
+                    - bishop_score(fen, White, &no_pair_params)?)
+            };
+
+        // c1 and c2 are on the same file but different colours
+        let pair = pair_bonus("4k3/8/8/8/8/8/2B5/2B1K3 w - - 0 1")?;
+        assert_eq!(pair.mg, EVAL_PARAMS.bishop_pair[Midgame]);
+        assert_eq!(pair.eg, EVAL_PARAMS.bishop_pair[Endgame]);
+
+        // c1 and d2 are both dark squares
+        let same_colour = pair_bonus("4k3/8/8/8/8/8/3B4/2B1K3 w - - 0 1")?;
+        assert_eq!(same_colour.mg, 0);
+        assert_eq!(same_colour.eg, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn params_from_source() -> Result<(), Box<dyn Error>> {
+        // the tuner's output format
+        let source = format!("pub const EVAL_PARAMS: EvalParams = {EVAL_PARAMS:#?};\n");
+        assert_eq!(EvalParams::from_source(&source)?, EVAL_PARAMS);
+
+        let changed = EvalParams {
+            tempo: [-20, 7],
+            ..EVAL_PARAMS
+        };
+        assert_eq!(EvalParams::from_source(&format!("{changed:?}"))?, changed);
+
+        // a missing parameter would shift all the others
+        let truncated = source.replacen(" 15,", "", 1);
+        assert_ne!(truncated, source);
+        assert!(EvalParams::from_source(&truncated).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn opposite_bishops() -> Result<(), Box<dyn Error>> {
+        let unscaled_params = EvalParams {
+            opposite_bishops: [128, 128],
+            ..EVAL_PARAMS
+        };
+        let evaluate = |fen: &str, params: &EvalParams| -> Result<i32, Box<dyn Error>> {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            Ok(EvalContext {
+                game: &game,
+                trace: &mut (),
+                params,
+                pawn_table: None,
+            }
+            .evaluate())
+        };
+
+        // a pawn up with opposite coloured bishops
+        let fen = "4k3/p4p2/4b1p1/8/8/6P1/PP3P2/2B1K3 w - - 0 1";
+        let raw = evaluate(fen, &unscaled_params)?;
+        let scaled = evaluate(fen, &EVAL_PARAMS)?;
+        assert!(raw > 0);
+        assert!(scaled < raw);
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen)?;
+        assert_eq!(game.scale_factor(), EVAL_PARAMS.opposite_bishops[0]);
+
+        // the bishop holds the blockade, so the extra pawn is worth almost nothing,
+        // leaving out the side to move's tempo bonus
+        let fen = "8/8/4k3/8/2B1P3/8/5b2/4K3 w - - 0 1";
+        let no_tempo_params = EvalParams {
+            tempo: [0, 0],
+            ..EVAL_PARAMS
+        };
+        assert!(evaluate(fen, &unscaled_params)? > 30);
+        assert!(evaluate(fen, &no_tempo_params)?.abs() <= 15);
+
+        // three pawns up is winning even with opposite coloured bishops
+        let fen = "4k3/5p2/4b3/8/8/6P1/PP3P2/2B1K3 w - - 0 1";
+        assert_eq!(
+            evaluate(fen, &EVAL_PARAMS)?,
+            evaluate(fen, &unscaled_params)?
+        );
+
+        // bishops on the same colour aren't drawish
+        let fen = "4kb2/p4p2/6p1/8/8/6P1/PP3P2/2B1K3 w - - 0 1";
+        assert_eq!(
+            evaluate(fen, &EVAL_PARAMS)?,
+            evaluate(fen, &unscaled_params)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tempo() -> Result<(), Box<dyn Error>> {
+        // no pieces have been traded, so only the midgame tempo counts
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen)?;
+        let white = game.evaluate::<()>().0;
+        game.set_from_fen(fen.replace(" w ", " b "))?;
+        let black = game.evaluate::<()>().0;
+        assert_eq!(white + black, 2 * EVAL_PARAMS.tempo[Midgame]);
+        Ok(())
+    }
+
+    #[test]
+    fn symmetric_evaluation() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ];
+        // xorshift, so the random games are the same every run
+        let mut rng = 0x2545f4914f6cdd1du64;
+        for fen in fens {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            for _ in 0..300 {
+                // scores are from the side to move's point of view, which the mirror swaps
+                let score = game.evaluate::<()>().0;
+                let mirrored = game.mirror();
+                assert_eq!(
+                    score,
+                    mirrored.evaluate::<()>().0,
+                    "{} mirrored to {}",
+                    game.fen(),
+                    mirrored.fen()
+                );
+
+                let moves = game.legal_moves();
+                if moves.is_empty() {
+                    break;
+                }
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                game.make_move(moves[rng as usize % moves.len()]);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pawn_hash_table() -> Result<(), Box<dyn Error>> {
+        let mut pawn_table = PawnHashTable::new(1024);
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        // siblings mostly share the same pawns
+        for move_ in game.legal_moves() {
+            game.make_move(move_);
+            let cached = game
+                .evaluate_with_pawn_table::<()>(&mut pawn_table, &EVAL_PARAMS)
+                .0;
+            assert_eq!(cached, game.evaluate::<()>().0, "{}", game.fen());
+            game.unmake_move();
+        }
+        assert!(pawn_table.hit_rate() > 0.5);
+
+        pawn_table.clear();
+        assert_eq!(pawn_table.probes(), 0);
+        assert_eq!(pawn_table.hit_rate(), 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn symmetric_outposts() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/pp3ppp/3p4/2p5/2P5/3P4/PP3PPP/4K3 w - - 0 1")?;
+        let info = EvalInfo::new(&game);
+
+        let mirrored = BitBoard(info.outposts[White].0.swap_bytes());
+        assert!(mirrored == info.outposts[Black]);
+        Ok(())
+    }
+
+    fn king_score(
+        fen: &str,
+        color: ColorIndex,
+        params: &EvalParams,
+    ) -> Result<EvalScore, Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen)?;
+        let info = EvalInfo::new(&game);
+        let mut eval = EvalContext {
+            game: &game,
+            trace: &mut (),
+            params,
+            pawn_table: None,
+        };
+        Ok(eval.evaluate_king(color, &info, params))
+    }
+
+    #[test]
+    fn pawn_shield() -> Result<(), Box<dyn Error>> {
+        let intact = king_score("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1", White, &EVAL_PARAMS)?;
+        let pushed = king_score("6k1/5ppp/8/8/8/6P1/5P1P/6K1 w - - 0 1", White, &EVAL_PARAMS)?;
+        let missing = king_score("6k1/5ppp/8/8/8/8/5P1P/6K1 w - - 0 1", White, &EVAL_PARAMS)?;
+        assert!(intact.mg > pushed.mg);
+        assert!(pushed.mg > missing.mg);
+
+        // the same holds from black's side of the board
+        let intact = king_score("6k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1", Black, &EVAL_PARAMS)?;
+        let pushed = king_score("6k1/5p1p/6p1/8/8/8/5PPP/6K1 b - - 0 1", Black, &EVAL_PARAMS)?;
+        assert!(intact.mg > pushed.mg);
+        Ok(())
+    }
+
+    #[test]
+    fn king_attacks() -> Result<(), Box<dyn Error>> {
+        let no_attack_params = EvalParams {
+            king_attacks: [[0, 0]; 16],
+            ..EVAL_PARAMS
+        };
+        let penalty = |fen: &str, color: ColorIndex| -> Result<EvalScore, Box<dyn Error>> {
+            Ok(king_score(fen, color, &EVAL_PARAMS)? - king_score(fen, color, &no_attack_params)?)
+        };
+
+        // nothing near the king
+        let safe = penalty("n5k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1", White)?;
+        assert_eq!(safe.mg, 0);
+
+        // a knight on f3, then joined by a queen on h4
+        let knight = penalty("6k1/5ppp/8/8/8/5n2/5PPP/6K1 w - - 0 1", White)?;
+        assert_eq!(knight.mg, EVAL_PARAMS.king_attacks[2][Midgame]);
+        let knight_queen = penalty("6k1/5ppp/8/8/7q/5n2/5PPP/6K1 w - - 0 1", White)?;
+        assert_eq!(knight_queen.mg, EVAL_PARAMS.king_attacks[7][Midgame]);
+        assert!(knight_queen.mg < knight.mg);
+
+        // and the same for black
+        let knight = penalty("6k1/5ppp/5N2/8/8/8/5PPP/6K1 b - - 0 1", Black)?;
+        assert_eq!(knight.mg, EVAL_PARAMS.king_attacks[2][Midgame]);
+        Ok(())
+    }
+
+    #[test]
+    fn pawn_storm() -> Result<(), Box<dyn Error>> {
+        let quiet = king_score("6k1/5p1p/8/6p1/8/8/5PPP/6K1 w - - 0 1", White, &EVAL_PARAMS)?;
+        let storm = king_score("6k1/5p1p/8/8/8/6p1/5PPP/6K1 w - - 0 1", White, &EVAL_PARAMS)?;
+        assert!(quiet.mg > storm.mg);
+        Ok(())
+    }
+
+    fn pawn_score(
+        fen: &str,
+        color: ColorIndex,
+        params: &EvalParams,
+    ) -> Result<EvalScore, Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen)?;
+        let info = EvalInfo::new(&game);
+        let mut eval = EvalContext {
+            game: &game,
+            trace: &mut (),
+            params,
+            pawn_table: None,
+        };
+        Ok(eval.evaluate_pawns(color, &info, params))
+    }
+
+    #[test]
+    fn king_passer_distance() -> Result<(), Box<dyn Error>> {
+        let pawn_score = |fen: &str| pawn_score(fen, White, &EVAL_PARAMS);
+
+        // the white king walks up to escort the passer
+        let far = pawn_score("k7/8/8/4P3/8/8/8/K7 w - - 0 1")?;
+        let closer = pawn_score("k7/8/8/4P3/8/8/2K5/8 w - - 0 1")?;
+        let escorting = pawn_score("k7/8/8/4P3/4K3/8/8/8 w - - 0 1")?;
+        assert!(closer.eg > far.eg);
+        assert!(escorting.eg > closer.eg);
+
+        // the black king in front of the passer
+        let stopped = pawn_score("8/8/4k3/4P3/8/8/8/K7 w - - 0 1")?;
+        assert!(far.eg > stopped.eg);
+        Ok(())
+    }
+
+    #[test]
+    fn back_rank_weakness() -> Result<(), Box<dyn Error>> {
+        let no_weakness_params = EvalParams {
+            back_rank_weakness: [0, 0],
+            ..EVAL_PARAMS
+        };
+        let penalty =
+            |fen: &str, color: ColorIndex| -> Result<EvalScore, Box<dyn Error>> {
+                Ok(king_score(fen, color, &EVAL_PARAMS)?
+                    - king_score(fen, color, &no_weakness_params)?)
+            };
+
+        // rook on the open e-file against a king without luft
+        let weak = penalty("4r1k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1", White)?;
+        assert_eq!(weak.mg, EVAL_PARAMS.back_rank_weakness[Midgame]);
+        assert_eq!(weak.eg, EVAL_PARAMS.back_rank_weakness[Endgame]);
+        // and the same for black
+        let weak = penalty("6k1/5ppp/8/8/8/8/5PPP/4R1K1 b - - 0 1", Black)?;
+        assert_eq!(weak.mg, EVAL_PARAMS.back_rank_weakness[Midgame]);
+
+        // h3 gives the king luft
+        let luft = penalty("4r1k1/5ppp/8/8/8/7P/5PP1/6K1 w - - 0 1", White)?;
+        assert_eq!(luft.mg, 0);
+        // no rook or queen to deliver mate
+        let no_heavies = penalty("4b1k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1", White)?;
+        assert_eq!(no_heavies.mg, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn backward_and_connected_pawns() -> Result<(), Box<dyn Error>> {
+        let backward_params = EvalParams {
+            backward_pawn: [0, 0],
+            ..EVAL_PARAMS
+        };
+        let connected_params = EvalParams {
+            connected_pawn: [0, 0],
+            ..EVAL_PARAMS
+        };
+        // number of times each term is counted, from the difference it makes to the score
+        let terms = |fen: &str, color: ColorIndex| -> Result<(i32, i32), Box<dyn Error>> {
+            let score = pawn_score(fen, color, &EVAL_PARAMS)?;
+            let backward = score - pawn_score(fen, color, &backward_params)?;
+            let connected = score - pawn_score(fen, color, &connected_params)?;
+            Ok((
+                backward.mg / EVAL_PARAMS.backward_pawn[Midgame],
+                connected.mg / EVAL_PARAMS.connected_pawn[Midgame],
+            ))
+        };
+
+        // b5 stops c3 from reaching c4, and c3 defends d4
+        assert_eq!(terms("4k3/8/8/1p6/3P4/2P5/8/4K3 w - - 0 1", White)?, (1, 1));
+        // and the same for black
+        assert_eq!(terms("4k3/8/2p5/3p4/1P6/8/8/4K3 b - - 0 1", Black)?, (1, 1));
+        // c4 is safe, so c3 isn't backward
+        assert_eq!(terms("4k3/8/8/8/3P4/2P5/8/4K3 w - - 0 1", White)?, (0, 1));
+        // b2 can still come up to support c3
+        assert_eq!(
+            terms("4k3/8/8/1p6/3P4/2P5/1P6/4K3 w - - 0 1", White)?,
+            (0, 2)
+        );
+        // a phalanx and an isolated pawn
+        assert_eq!(terms("4k3/8/8/8/3PP3/8/8/P3K3 w - - 0 1", White)?, (0, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn passed_pawns() -> Result<(), Box<dyn Error>> {
+        let penalties = |fen: &str, color: ColorIndex| -> Result<EvalScore, Box<dyn Error>> {
+            let params = EvalParams {
+                passer_blockaded: [0, 0],
+                passer_path_attacked: [0, 0],
+                ..EVAL_PARAMS
+            };
+            Ok(pawn_score(fen, color, &EVAL_PARAMS)? - pawn_score(fen, color, &params)?)
+        };
+
+        // a passer about to promote is worth far more than one that has barely moved
+        let seventh = pawn_score("7k/1P6/8/8/8/8/8/K7 w - - 0 1", White, &EVAL_PARAMS)?;
+        let third = pawn_score("7k/8/8/8/8/1P6/8/K7 w - - 0 1", White, &EVAL_PARAMS)?;
+        assert!(seventh.eg > third.eg + 100);
+        let seventh = pawn_score("k7/8/8/8/8/8/1p6/7K b - - 0 1", Black, &EVAL_PARAMS)?;
+        let third = pawn_score("k7/8/1p6/8/8/8/8/7K b - - 0 1", Black, &EVAL_PARAMS)?;
+        assert!(seventh.eg > third.eg + 100);
+
+        // a free path
+        let free = penalties("7k/8/8/1P6/8/8/8/K7 w - - 0 1", White)?;
+        assert_eq!(free.eg, 0);
+        // a bishop covers b7 from a distance
+        let attacked = penalties("7k/8/8/1P6/8/8/6b1/K7 w - - 0 1", White)?;
+        assert_eq!(attacked.eg, EVAL_PARAMS.passer_path_attacked[Endgame]);
+        // a knight blockades b6 and so covers nothing in front of the pawn
+        let blockaded = penalties("7k/8/1n6/1P6/8/8/8/K7 w - - 0 1", White)?;
+        assert_eq!(blockaded.eg, EVAL_PARAMS.passer_blockaded[Endgame]);
+        // and the same for black
+        let blockaded = penalties("k7/8/8/8/1p6/1N6/8/7K b - - 0 1", Black)?;
+        assert_eq!(blockaded.eg, EVAL_PARAMS.passer_blockaded[Endgame]);
+        Ok(())
+    }
+}
@@ -2,7 +2,8 @@ use crate::{
     lookup_tables::*,
     moves::*,
     types::{
-        CastlingIndex::*,
+        CastlingFiles,
+        CastlingIndex::{self, *},
         CastlingRights, ColorIndex,
         ColorIndex::*,
         ColorMasks,
@@ -12,13 +13,94 @@ use crate::{
     zobrist::*,
 };
 use cheers_bitboards::{BitBoard, Square};
+use std::{
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
 
+pub mod endgame;
+pub mod epd;
 pub mod eval_params;
 pub mod eval_types;
 pub mod evaluate;
+pub mod pawn_hash;
+pub mod perft;
+pub mod san;
 pub mod see;
 
-pub use self::eval_params::*;
+pub use self::{
+    epd::{EpdError, EpdOps},
+    eval_params::*,
+    pawn_hash::PawnHashTable,
+    perft::PerftTable,
+    san::SanError,
+};
+
+/// The outcome of a finished game
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameResult {
+    WhiteWin,
+    BlackWin,
+    Draw,
+}
+
+/// Whether the game is still going, and if not why it ended.
+/// Draws by the fifty move rule or threefold repetition are reported as soon as they can be claimed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameStatus {
+    Ongoing,
+    /// Won by the given side
+    Checkmate(ColorIndex),
+    Stalemate,
+    FiftyMoveDraw,
+    ThreefoldRepetition,
+    InsufficientMaterial,
+}
+
+/// The board state of a `ChessGame` without its move history.
+/// Cheap to copy, e.g. to hand positions to other threads.
+#[derive(Clone, Copy, Debug)]
+pub struct Position {
+    color_masks: ColorMasks,
+    combined: BitBoard,
+    piece_masks: PieceMasks,
+    current_player: ColorIndex,
+    castling_rights: CastlingRights,
+    /// Start files of each side's king and castling rooks
+    castling_files: [CastlingFiles; 2],
+    /// Castling moves are encoded as the king taking its own rook
+    chess960: bool,
+    en_passent_mask: BitBoard,
+    halfmove_clock: u16,
+    fullmove_number: u16,
+    hash: u64,
+    /// Zobrist hash of the pawns alone, for the pawn structure cache
+    pawn_hash: u64,
+    checkers: BitBoard,
+    pinned: BitBoard,
+}
+
+impl Position {
+    #[inline]
+    pub fn piece_masks(&self) -> PieceMasks {
+        self.piece_masks
+    }
+
+    #[inline]
+    pub fn current_player(&self) -> ColorIndex {
+        self.current_player
+    }
+
+    #[inline]
+    pub fn halfmove_clock(&self) -> u16 {
+        self.halfmove_clock
+    }
+
+    #[inline]
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+}
 
 #[derive(Clone)]
 pub struct ChessGame {
@@ -27,9 +109,18 @@ pub struct ChessGame {
     piece_masks: PieceMasks,
     current_player: ColorIndex,
     castling_rights: CastlingRights,
+    /// Start files of each side's king and castling rooks
+    castling_files: [CastlingFiles; 2],
+    /// Castling moves are encoded as the king taking its own rook
+    chess960: bool,
     en_passent_mask: BitBoard,
-    halfmove_clock: u8,
+    halfmove_clock: u16,
+    fullmove_number: u16,
     hash: u64,
+    /// Zobrist hash of the pawns alone, for the pawn structure cache
+    pawn_hash: u64,
+    checkers: BitBoard,
+    pinned: BitBoard,
     position_history: Vec<u64>,
     unmove_history: Vec<UnMove>,
 }
@@ -42,9 +133,15 @@ impl ChessGame {
             piece_masks: PieceMasks::default(),
             current_player: ColorIndex::default(),
             castling_rights: CastlingRights::default(),
+            castling_files: [CastlingFiles::default(); 2],
+            chess960: false,
             en_passent_mask: BitBoard::empty(),
             halfmove_clock: 0,
+            fullmove_number: 1,
             hash: 0,
+            pawn_hash: 0,
+            checkers: BitBoard::empty(),
+            pinned: BitBoard::empty(),
             position_history: Vec::new(),
             unmove_history: Vec::new(),
         };
@@ -55,6 +152,89 @@ impl ChessGame {
         boards
     }
 
+    /// A game starting from `position`, with no move history
+    pub fn from_position(position: Position) -> Self {
+        Self {
+            color_masks: position.color_masks,
+            combined: position.combined,
+            piece_masks: position.piece_masks,
+            current_player: position.current_player,
+            castling_rights: position.castling_rights,
+            castling_files: position.castling_files,
+            chess960: position.chess960,
+            en_passent_mask: position.en_passent_mask,
+            halfmove_clock: position.halfmove_clock,
+            fullmove_number: position.fullmove_number,
+            hash: position.hash,
+            pawn_hash: position.pawn_hash,
+            checkers: position.checkers,
+            pinned: position.pinned,
+            position_history: Vec::new(),
+            unmove_history: Vec::new(),
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        Position {
+            color_masks: self.color_masks,
+            combined: self.combined,
+            piece_masks: self.piece_masks,
+            current_player: self.current_player,
+            castling_rights: self.castling_rights,
+            castling_files: self.castling_files,
+            chess960: self.chess960,
+            en_passent_mask: self.en_passent_mask,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            hash: self.hash,
+            pawn_hash: self.pawn_hash,
+            checkers: self.checkers,
+            pinned: self.pinned,
+        }
+    }
+
+    /// The position flipped vertically with the colours swapped, so white's pieces
+    /// become black's on the mirrored squares and the other side is to move.
+    /// Move history is not carried over.
+    pub fn mirror(&self) -> ChessGame {
+        let flip = |board: BitBoard| BitBoard(board.0.swap_bytes());
+
+        let mut piece_masks = PieceMasks::default();
+        for piece in [Pawn, Knight, Bishop, Rook, Queen, King] {
+            for color in [White, Black] {
+                piece_masks[(!color, piece)] = flip(self.piece_masks[(color, piece)]);
+            }
+        }
+        let color_masks =
+            ColorMasks([flip(self.color_masks[Black]), flip(self.color_masks[White])]);
+
+        let mut mirrored = Self {
+            color_masks,
+            combined: flip(self.combined),
+            piece_masks,
+            current_player: !self.current_player,
+            castling_rights: CastlingRights([
+                self.castling_rights[Black],
+                self.castling_rights[White],
+            ]),
+            castling_files: [self.castling_files[Black], self.castling_files[White]],
+            chess960: self.chess960,
+            en_passent_mask: flip(self.en_passent_mask),
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            hash: 0,
+            pawn_hash: 0,
+            checkers: BitBoard::empty(),
+            pinned: BitBoard::empty(),
+            position_history: Vec::new(),
+            unmove_history: Vec::new(),
+        };
+        mirrored.hash = mirrored.zobrist_hash();
+        mirrored.pawn_hash = mirrored.zobrist_pawn_hash();
+        mirrored.update_check_info();
+        mirrored
+    }
+
     pub fn reset(&mut self) {
         self.set_from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
             .unwrap()
@@ -64,15 +244,22 @@ impl ChessGame {
         &mut self,
         fen: impl Into<String>,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let chess960 = self.chess960;
         *self = Self {
             color_masks: ColorMasks::default(),
             combined: BitBoard::empty(),
             piece_masks: PieceMasks::default(),
             current_player: ColorIndex::default(),
             castling_rights: CastlingRights::default(),
+            castling_files: [CastlingFiles::default(); 2],
+            chess960,
             en_passent_mask: BitBoard::empty(),
             halfmove_clock: 0,
+            fullmove_number: 1,
             hash: 0,
+            pawn_hash: 0,
+            checkers: BitBoard::empty(),
+            pinned: BitBoard::empty(),
             position_history: Vec::new(),
             unmove_history: Vec::new(),
         };
@@ -87,56 +274,14 @@ impl ChessGame {
             let mut index = 56 - i * 8;
             for chr in line.chars() {
                 match chr {
-                    'n' => {
-                        self.piece_masks[(Black, Knight)] |= BitBoard(1 << index);
-                        self.color_masks[Black] |= BitBoard(1 << index);
-                    }
-                    'N' => {
-                        self.piece_masks[(White, Knight)] |= BitBoard(1 << index);
-                        self.color_masks[White] |= BitBoard(1 << index);
-                    }
-                    'b' => {
-                        self.piece_masks[(Black, Bishop)] |= BitBoard(1 << index);
-                        self.color_masks[Black] |= BitBoard(1 << index);
-                    }
-                    'B' => {
-                        self.piece_masks[(White, Bishop)] |= BitBoard(1 << index);
-                        self.color_masks[White] |= BitBoard(1 << index);
-                    }
-                    'r' => {
-                        self.piece_masks[(Black, Rook)] |= BitBoard(1 << index);
-                        self.color_masks[Black] |= BitBoard(1 << index);
-                    }
-                    'R' => {
-                        self.piece_masks[(White, Rook)] |= BitBoard(1 << index);
-                        self.color_masks[White] |= BitBoard(1 << index);
-                    }
-                    'q' => {
-                        self.piece_masks[(Black, Queen)] |= BitBoard(1 << index);
-                        self.color_masks[Black] |= BitBoard(1 << index);
-                    }
-                    'Q' => {
-                        self.piece_masks[(White, Queen)] |= BitBoard(1 << index);
-                        self.color_masks[White] |= BitBoard(1 << index);
-                    }
-                    'k' => {
-                        self.piece_masks[(Black, King)] |= BitBoard(1 << index);
-                        self.color_masks[Black] |= BitBoard(1 << index);
-                    }
-                    'K' => {
-                        self.piece_masks[(White, King)] |= BitBoard(1 << index);
-                        self.color_masks[White] |= BitBoard(1 << index);
-                    }
-                    'p' => {
-                        self.piece_masks[(Black, Pawn)] |= BitBoard(1 << index);
-                        self.color_masks[Black] |= BitBoard(1 << index);
-                    }
-                    'P' => {
-                        self.piece_masks[(White, Pawn)] |= BitBoard(1 << index);
-                        self.color_masks[White] |= BitBoard(1 << index);
-                    }
                     digit @ '1'..='8' => index += digit.to_digit(10).unwrap() as usize - 1,
-                    other => eprintln!("Unexpected character in FEN: {}", other),
+                    other => match PieceIndex::from_fen_char(other) {
+                        Some((color, piece)) => {
+                            self.piece_masks[(color, piece)] |= BitBoard(1 << index);
+                            self.color_masks[color] |= BitBoard(1 << index);
+                        }
+                        None => eprintln!("Unexpected character in FEN: {}", other),
+                    },
                 }
                 index += 1;
             }
@@ -154,26 +299,52 @@ impl ChessGame {
             .ok_or_else(|| String::from("Insufficient metadata for castling rights!"))?
         {
             "-" => self.castling_rights = CastlingRights([[false, false], [false, false]]),
-            other => other.chars().try_for_each(|chr| match chr {
-                'K' => {
-                    self.castling_rights[(White, Kingside)] = true;
-                    Ok(())
-                }
-                'k' => {
-                    self.castling_rights[(Black, Kingside)] = true;
-                    Ok(())
-                }
-                'Q' => {
-                    self.castling_rights[(White, Queenside)] = true;
-                    Ok(())
-                }
-                'q' => {
-                    self.castling_rights[(Black, Queenside)] = true;
-                    Ok(())
+            other => other.chars().try_for_each(|chr| {
+                let color = if chr.is_ascii_uppercase() {
+                    White
+                } else {
+                    Black
+                };
+                let back_rank = 7 * color as usize;
+                let king_file = self.piece_masks[(color, King)]
+                    .into_iter()
+                    .find(|sq| sq.rank() == back_rank)
+                    .map(|sq| sq.file());
+                let mut rooks = self.piece_masks[(color, Rook)]
+                    .into_iter()
+                    .filter(|sq| sq.rank() == back_rank)
+                    .map(|sq| sq.file());
+                // X-FEN K and Q refer to the outermost rook, Shredder-FEN names the rook's file
+                let (side, rook_file) = match (chr.to_ascii_uppercase(), king_file) {
+                    ('K', Some(king)) => (
+                        Kingside,
+                        rooks.filter(|&file| file > king).max().unwrap_or(7),
+                    ),
+                    ('Q', Some(king)) => (Queenside, rooks.find(|&file| file < king).unwrap_or(0)),
+                    ('K', None) => (Kingside, 7),
+                    ('Q', None) => (Queenside, 0),
+                    (file @ 'A'..='H', Some(king)) => {
+                        let file = file as usize - 'A' as usize;
+                        match file.cmp(&king) {
+                            std::cmp::Ordering::Greater => (Kingside, file),
+                            std::cmp::Ordering::Less => (Queenside, file),
+                            std::cmp::Ordering::Equal => {
+                                return Err(format!("Invalid castling character: {chr}"))
+                            }
+                        }
+                    }
+                    _ => return Err(format!("Invalid castling character: {chr}")),
+                };
+                self.castling_rights[(color, side)] = true;
+                self.castling_files[color].rooks[side] = rook_file;
+                if let Some(king) = king_file {
+                    self.castling_files[color].king = king;
                 }
-                _ => Err(format!("Invalid player character: {}", other)),
+                Ok(())
             })?,
         }
+        // castling from anywhere but the standard squares is only possible in Chess960
+        self.chess960 |= self.castling_files != [CastlingFiles::default(); 2];
 
         match lines
             .next()
@@ -205,11 +376,20 @@ impl ChessGame {
         self.halfmove_clock = lines
             .next()
             .ok_or_else(|| String::from("No halfmove clock!"))?
-            .parse::<u8>()?;
+            .parse::<u16>()?;
+        // some FENs leave out the fullmove number
+        self.fullmove_number = match lines.next() {
+            Some(number) => number.parse::<u16>()?,
+            None => 1,
+        };
 
         self.combined = self.color_masks[White] | self.color_masks[Black];
+        // like make_move, only keep the en passent square if a pawn can take on it
+        self.en_passent_mask &= self.pawn_attacks(self.current_player);
         let hash = self.zobrist_hash();
         self.hash = hash;
+        self.pawn_hash = self.zobrist_pawn_hash();
+        self.update_check_info();
 
         Ok(())
     }
@@ -230,19 +410,7 @@ impl ChessGame {
                             fen.push(char::from_digit(empty_counter, 10).unwrap());
                             empty_counter = 0;
                         }
-                        let mut letter = match piece {
-                            Pawn => 'p',
-                            Knight => 'n',
-                            Bishop => 'b',
-                            Rook => 'r',
-                            Queen => 'q',
-                            King => 'k',
-                            NoPiece => unreachable!(),
-                        };
-                        if self.color_at(square.into()) == White {
-                            letter = letter.to_ascii_uppercase();
-                        }
-                        fen.push(letter);
+                        fen.push(piece.to_fen_char(self.color_at(square.into())).unwrap());
                     }
                 }
             }
@@ -263,18 +431,21 @@ impl ChessGame {
         });
         fen.push(' ');
 
-        // castling rights
-        if self.castling_rights[(White, Kingside)] {
-            fen.push('K')
-        }
-        if self.castling_rights[(White, Queenside)] {
-            fen.push('Q')
-        }
-        if self.castling_rights[(Black, Kingside)] {
-            fen.push('k')
-        }
-        if self.castling_rights[(Black, Kingside)] {
-            fen.push('q')
+        // castling rights, as the rooks' files in Chess960
+        for color in [White, Black] {
+            for (side, letter) in [(Kingside, 'K'), (Queenside, 'Q')] {
+                if self.castling_rights[(color, side)] {
+                    let letter = if self.chess960 {
+                        (b'A' + self.castling_files[color].rooks[side] as u8) as char
+                    } else {
+                        letter
+                    };
+                    fen.push(match color {
+                        White => letter,
+                        Black => letter.to_ascii_lowercase(),
+                    });
+                }
+            }
         }
         if self.castling_rights == CastlingRights([[false, false], [false, false]]) {
             fen.push('-')
@@ -293,7 +464,7 @@ impl ChessGame {
         fen.push(' ');
 
         // fullmove number
-        fen.push_str(&(self.position_history.len() / 2).to_string());
+        fen.push_str(&self.fullmove_number.to_string());
 
         fen
     }
@@ -322,20 +493,156 @@ impl ChessGame {
     }
 
     #[inline]
-    pub fn halfmove_clock(&self) -> u8 {
+    pub fn castling_rights(&self) -> CastlingRights {
+        self.castling_rights
+    }
+
+    /// Start files of `color`'s king and castling rooks
+    pub fn castling_files(&self, color: ColorIndex) -> CastlingFiles {
+        self.castling_files[color]
+    }
+
+    /// Whether castling moves are written as the king taking its own rook, as UCI does
+    /// for Chess960. Set automatically by FENs with non-standard castling rooks
+    #[inline]
+    pub fn chess960(&self) -> bool {
+        self.chess960
+    }
+
+    /// Switch the castling move format, before making any moves.
+    /// Kept across [`ChessGame::set_from_fen`] so it can be set before loading a position
+    pub fn set_chess960(&mut self, chess960: bool) {
+        self.chess960 = chess960;
+    }
+
+    #[inline]
+    pub fn halfmove_clock(&self) -> u16 {
         self.halfmove_clock
     }
 
+    /// Starts at 1 and increases after each black move
+    #[inline]
+    pub fn fullmove_number(&self) -> u16 {
+        self.fullmove_number
+    }
+
     #[inline]
     pub fn position_history(&self) -> &[u64] {
         &self.position_history
     }
 
+    /// How many times the current position occurred before in the game.
+    /// Only positions since the last capture or pawn move can be repeated.
+    pub fn repetition_count(&self) -> usize {
+        self.position_history
+            .iter()
+            .rev()
+            .take(self.halfmove_clock as usize)
+            .filter(|&&p| p == self.hash)
+            .count()
+    }
+
+    /// Whether the current position has occurred three times, so a draw can be claimed
+    pub fn is_repetition(&self) -> bool {
+        self.repetition_count() >= 2
+    }
+
+    /// Whether fifty moves have passed without a capture or pawn move, so a draw can be claimed
+    pub fn can_claim_fifty_move(&self) -> bool {
+        self.halfmove_clock >= 100
+    }
+
+    /// Whether the game is drawn, or either side can claim a draw.
+    /// Doesn't check for stalemate.
+    pub fn is_draw(&self) -> bool {
+        self.can_claim_fifty_move() || self.is_repetition() || self.is_insufficient_material()
+    }
+
+    pub fn status(&self) -> GameStatus {
+        if self.legal_moves().is_empty() {
+            return if self.in_check(self.current_player) {
+                GameStatus::Checkmate(!self.current_player)
+            } else {
+                GameStatus::Stalemate
+            };
+        }
+        if self.is_insufficient_material() {
+            GameStatus::InsufficientMaterial
+        } else if self.can_claim_fifty_move() {
+            GameStatus::FiftyMoveDraw
+        } else if self.is_repetition() {
+            GameStatus::ThreefoldRepetition
+        } else {
+            GameStatus::Ongoing
+        }
+    }
+
+    /// Neither side can possibly checkmate: bare kings plus at most one minor piece,
+    /// or only bishops that all stand on the same colour complex
+    pub fn is_insufficient_material(&self) -> bool {
+        let knights = self.piece_masks[(White, Knight)] | self.piece_masks[(Black, Knight)];
+        let bishops = self.piece_masks[(White, Bishop)] | self.piece_masks[(Black, Bishop)];
+        let kings = self.piece_masks[(White, King)] | self.piece_masks[(Black, King)];
+        let minors = knights | bishops;
+        if (self.combined ^ kings) != minors {
+            return false;
+        }
+        minors.count_ones() <= 1
+            || (knights.is_empty()
+                && ((bishops & LIGHT_SQUARES).is_empty() || (bishops & DARK_SQUARES).is_empty()))
+    }
+
+    /// The result if the game has ended without either side having to claim it:
+    /// checkmate, stalemate, insufficient material, fivefold repetition
+    /// or 75 moves without a capture or pawn move
+    pub fn game_result(&self) -> Option<GameResult> {
+        if self.legal_moves().is_empty() {
+            return Some(
+                match (self.in_check(self.current_player), self.current_player) {
+                    (true, White) => GameResult::BlackWin,
+                    (true, Black) => GameResult::WhiteWin,
+                    (false, _) => GameResult::Draw,
+                },
+            );
+        }
+        if self.is_insufficient_material()
+            || self.repetition_count() >= 4
+            || self.halfmove_clock >= 150
+        {
+            return Some(GameResult::Draw);
+        }
+        None
+    }
+
     #[inline]
     pub fn hash(&self) -> u64 {
         self.hash
     }
 
+    /// Zobrist key of the pawns alone, see [`ChessGame::zobrist_pawn_hash`]
+    #[inline]
+    pub fn pawn_hash(&self) -> u64 {
+        self.pawn_hash
+    }
+
+    /// Zobrist key identifying the position, independent of move history
+    #[inline]
+    pub fn position_key(&self) -> u64 {
+        self.hash
+    }
+
+    /// Enemy pieces giving check to the side to move
+    #[inline]
+    pub fn checkers(&self) -> BitBoard {
+        self.checkers
+    }
+
+    /// Pieces of the side to move that are pinned to their king
+    #[inline]
+    pub fn pinned_pieces(&self) -> BitBoard {
+        self.pinned
+    }
+
     #[inline]
     pub fn piece_at(&self, square: Square) -> PieceIndex {
         let test = square.bitboard();
@@ -366,6 +673,18 @@ impl ChessGame {
         }
     }
 
+    /// Whether `move_` captures a piece, including en passent captures.
+    /// Checks the board as well as the move flags, so works for moves parsed from UCI.
+    pub fn is_capture(&self, move_: Move) -> bool {
+        // a Chess960 castling move lands on the king's own rook
+        if move_.capture() || (!move_.castling() && self.piece_at(move_.target()) != NoPiece) {
+            return true;
+        }
+        self.piece_at(move_.start()) == Pawn
+            && Some(move_.target()) == self.en_passent_square()
+            && move_.start().file() != move_.target().file()
+    }
+
     pub fn color_at(&self, square: Square) -> ColorIndex {
         if (self.color_masks[White] & square.bitboard()).is_not_empty() {
             White
@@ -471,6 +790,10 @@ impl ChessGame {
 
     fn king_attacks(&self, color: ColorIndex) -> BitBoard {
         let king = self.piece_masks[(color, King)];
+        // positions set from arbitrary FENs may be missing a king
+        if king.is_empty() {
+            return BitBoard::empty();
+        }
         lookup_king(king.first_square())
     }
 
@@ -514,10 +837,82 @@ impl ChessGame {
             | (lookup_king(target) & kings)
     }
 
+    /// Pieces attacking `square` with the current occupancy, as (white attackers, black attackers)
+    pub fn attackers_defenders(&self, square: Square) -> (BitBoard, BitBoard) {
+        let attackers = self.all_attacks_on(square, self.combined);
+        (
+            attackers & self.color_masks[White],
+            attackers & self.color_masks[Black],
+        )
+    }
+
     pub fn in_check(&self, color: ColorIndex) -> bool {
+        if color == self.current_player {
+            return self.checkers.is_not_empty();
+        }
+        // a side without a king can never be in check
         (self.all_attacks(!color, self.combined) & self.piece_masks[(color, King)]).is_not_empty()
     }
 
+    fn compute_checkers(&self, color: ColorIndex) -> BitBoard {
+        let king = self.piece_masks[(color, King)];
+        if king.is_empty() {
+            return BitBoard::empty();
+        }
+        let king_square = king.first_square();
+
+        (lookup_pawn_attack(king_square, color) & self.piece_masks[(!color, Pawn)])
+            | (lookup_knight(king_square) & self.piece_masks[(!color, Knight)])
+            | (lookup_bishop(king_square, self.combined)
+                & (self.piece_masks[(!color, Bishop)] | self.piece_masks[(!color, Queen)]))
+            | (lookup_rook(king_square, self.combined)
+                & (self.piece_masks[(!color, Rook)] | self.piece_masks[(!color, Queen)]))
+    }
+
+    fn compute_pinned(&self, color: ColorIndex) -> BitBoard {
+        let king = self.piece_masks[(color, King)];
+        if king.is_empty() {
+            return BitBoard::empty();
+        }
+        let king_square = king.first_square();
+
+        let pinners = ((self.piece_masks[(!color, Rook)] | self.piece_masks[(!color, Queen)])
+            & lookup_rook(king_square, self.color_masks[!color]))
+            | ((self.piece_masks[(!color, Bishop)] | self.piece_masks[(!color, Queen)])
+                & lookup_bishop(king_square, self.color_masks[!color]));
+
+        let mut pinned = BitBoard::empty();
+        for pinner_square in pinners {
+            let pin_ray = lookup_between(king_square, pinner_square) & self.color_masks[color];
+            if pin_ray.count_ones() == 1 {
+                pinned |= pin_ray;
+            }
+        }
+        pinned
+    }
+
+    /// Recompute the cached checkers and pinned pieces for the side to move
+    fn update_check_info(&mut self) {
+        self.checkers = self.compute_checkers(self.current_player);
+        self.pinned = self.compute_pinned(self.current_player);
+    }
+
+    #[cfg(feature = "debug-checks")]
+    fn validate_check_info(&self) {
+        assert_eq!(
+            self.checkers,
+            self.compute_checkers(self.current_player),
+            "stale checkers cache for FEN {}",
+            self.fen()
+        );
+        assert_eq!(
+            self.pinned,
+            self.compute_pinned(self.current_player),
+            "stale pinned pieces cache for FEN {}",
+            self.fen()
+        );
+    }
+
     pub fn is_pseudolegal(&self, start: Square, target: Square) -> bool {
         if start == target {
             return true;
@@ -574,36 +969,178 @@ impl ChessGame {
         }
     }
 
+    /// Castling move for `side`, from the king's square to its target in standard chess
+    /// and onto its own rook in Chess960
+    fn castling_move(&self, color: ColorIndex, side: CastlingIndex) -> Move {
+        let path = castling_path(color, side, self.castling_files[color]);
+        if self.chess960 {
+            Move::king_castle(path.king_start, path.rook_start)
+        } else {
+            Move::king_castle(path.king_start, path.king_target)
+        }
+    }
+
+    /// Whether `color` can castle to `side` when not in check, with `attacked` the squares
+    /// the other side attacks
+    fn can_castle(&self, color: ColorIndex, side: CastlingIndex, attacked: BitBoard) -> bool {
+        let path = castling_path(color, side, self.castling_files[color]);
+        self.castling_rights[(color, side)]
+            && (self.piece_masks[(color, King)] & path.king_start.bitboard()).is_not_empty()
+            && (self.piece_masks[(color, Rook)] & path.rook_start.bitboard()).is_not_empty()
+            && (self.combined & path.empty).is_empty()
+            && (attacked & path.safe).is_empty()
+            // in Chess960 the castling rook can shield the king's target along the back rank
+            && (!self.chess960
+                || (self.all_attacks_on(
+                    path.king_target,
+                    self.combined ^ path.rook_start.bitboard(),
+                ) & self.color_masks[!color])
+                    .is_empty())
+    }
+
+    /// Whether the move can be played in this position without leaving the king in check.
+    /// The move's flags have to match the position too, so moves from another position
+    /// (a hash collision in the transposition table, or a killer move) are rejected.
+    pub fn is_legal(&self, move_: Move) -> bool {
+        let start = move_.start();
+        let target = move_.target();
+        let color = self.current_player;
+        let piece = self.piece_at(start);
+        if move_.is_null() || piece == NoPiece || self.color_at(start) != color {
+            return false;
+        }
+
+        if move_.castling() {
+            let side = if target.file() > start.file() {
+                Kingside
+            } else {
+                Queenside
+            };
+            return piece == King
+                && move_.piece() == King
+                && move_.promotion() == NoPiece
+                && !move_.capture()
+                && move_.coords() == self.castling_move(color, side).coords()
+                && self.checkers.is_empty()
+                && self.can_castle(color, side, self.all_attacks(!color, self.combined));
+        }
+
+        if !self.is_pseudolegal(start, target) {
+            return false;
+        }
+        let promotes = piece == Pawn && target.rank() == !color as usize * 7;
+        let valid_promotion = match move_.promotion() {
+            NoPiece => !promotes,
+            Knight | Bishop | Rook | Queen => promotes,
+            _ => false,
+        };
+        let en_passent = piece == Pawn && self.en_passent_mask == target.bitboard();
+        let mut expected = Move::new(
+            start,
+            target,
+            piece,
+            move_.promotion(),
+            self.piece_at(target) != NoPiece || en_passent,
+            piece == Pawn && target.abs_diff(*start) == 16,
+            en_passent,
+            false,
+        );
+        // ordering scores don't matter
+        expected.score = move_.score;
+        if !valid_promotion || expected != move_ {
+            return false;
+        }
+
+        // find anything attacking the king once the move is made
+        let king_square = if piece == King {
+            target
+        } else {
+            self.piece_masks[(color, King)].first_square()
+        };
+        let mut occupied = (self.combined ^ start.bitboard()) | target.bitboard();
+        let mut enemies = self.color_masks[!color] & target.bitboard().inverse();
+        if en_passent {
+            let captured = target
+                .offset(0, if color == White { -1 } else { 1 })
+                .bitboard();
+            occupied ^= captured;
+            enemies ^= captured;
+        }
+        let diagonals = self.piece_masks[(!color, Bishop)] | self.piece_masks[(!color, Queen)];
+        let orthogonals = self.piece_masks[(!color, Rook)] | self.piece_masks[(!color, Queen)];
+        let attackers = (lookup_pawn_attack(king_square, color) & self.piece_masks[(!color, Pawn)])
+            | (lookup_knight(king_square) & self.piece_masks[(!color, Knight)])
+            | (lookup_king(king_square) & self.piece_masks[(!color, King)])
+            | (lookup_bishop(king_square, occupied) & diagonals)
+            | (lookup_rook(king_square, occupied) & orthogonals);
+        (attackers & enemies).is_empty()
+    }
+
+    /// All legal moves in the position, generated in a fixed order:
+    /// king moves, moves of pinned pieces, castling (kingside then queenside),
+    /// then pawn, knight, bishop, rook and queen moves.
+    /// Within each group moves follow square order of the moving pieces and their targets.
+    /// Use [`ChessGame::legal_moves_sorted`] when the order must not depend on the generator.
     pub fn legal_moves(&self) -> Vec<Move> {
         let mut moves = Vec::with_capacity(64);
+        self.generate_moves_into(&mut moves);
+        moves
+    }
+
+    /// Generate only captures, including en passent, and promotions into a reusable buffer.
+    /// When in check all evasions are generated instead.
+    pub fn generate_captures(&self, out: &mut Vec<Move>) {
+        out.clear();
+        self.generate_moves::<false>(out);
+    }
+
+    /// Generate the legal moves into a reusable buffer, replacing its contents
+    pub fn generate_moves_into(&self, out: &mut Vec<Move>) {
+        out.clear();
+        self.generate_moves::<true>(out);
+    }
+
+    /// Legal moves in a fixed size list, without allocating
+    pub fn legal_move_list(&self) -> MoveList {
+        let mut moves = MoveList::new();
+        self.generate_moves::<true>(&mut moves);
+        moves
+    }
+
+    fn generate_moves<const QUIETS: bool>(&self, moves: &mut impl MoveSink) {
         let color = self.current_player;
 
+        // squares pieces may move to, which are only captures unless we want quiets or are in check
+        let target_mask = if QUIETS || self.checkers.is_not_empty() {
+            BitBoard(0xFFFFFFFFFFFFFFFFu64)
+        } else {
+            self.color_masks[!color]
+        };
+        // pawns can still push to promote
+        let pawn_push_mask = target_mask | FIRST_RANK | EIGHTH_RANK;
+
         let king_square = self.piece_masks[(color, King)].first_square();
 
         // King moves
         let kingless_blocking_mask =
             (self.color_masks[color] ^ self.piece_masks[(color, King)]) | self.color_masks[!color];
         let attacked_squares = self.all_attacks(!color, kingless_blocking_mask);
-        let king_moves =
-            self.king_attacks(color) & (attacked_squares | self.color_masks[color]).inverse();
+        let king_moves = self.king_attacks(color)
+            & (attacked_squares | self.color_masks[color]).inverse()
+            & target_mask;
         for target in king_moves {
             let capture = (target.bitboard() & self.color_masks[!color]).is_not_empty();
             moves.push(Move::king_move(king_square, target, capture));
         }
 
         // Check evasions
-        let checkers = (lookup_pawn_attack(king_square, color) & self.piece_masks[(!color, Pawn)])
-            | (lookup_knight(king_square) & self.piece_masks[(!color, Knight)])
-            | (lookup_bishop(king_square, self.combined)
-                & (self.piece_masks[(!color, Bishop)] | self.piece_masks[(!color, Queen)]))
-            | (lookup_rook(king_square, self.combined)
-                & (self.piece_masks[(!color, Rook)] | self.piece_masks[(!color, Queen)]));
+        let checkers = self.checkers;
 
         let num_checkers = checkers.count_ones();
         // - Double Check
         // only king moves are legal in double+ check
         if num_checkers > 1 {
-            return moves;
+            return;
         }
 
         // mask of square a piece can capture on
@@ -634,29 +1171,34 @@ impl ChessGame {
                 push_mask = BitBoard::empty();
             }
         }
-        // Pinned pieces
-        let mut pinned_pieces = BitBoard::empty();
+        // Pinned pieces, cached for the side to move, so the pinners only need finding if
+        // there are any pins at all
+        let pinned_pieces = self.pinned;
+        let pin_candidates = if pinned_pieces.is_not_empty() {
+            self.color_masks[!color]
+        } else {
+            BitBoard::empty()
+        };
 
         let orthogonal_pin_rays = lookup_rook(king_square, self.color_masks[!color]);
         let pinning_orthogonals = (self.piece_masks[(!color, Rook)]
             | self.piece_masks[(!color, Queen)])
-            & orthogonal_pin_rays;
+            & orthogonal_pin_rays
+            & pin_candidates;
         for pinner_square in pinning_orthogonals {
             let pin_ray = lookup_between(king_square, pinner_square);
 
-            if (pin_ray & self.color_masks[color]).count_ones() == 1 {
+            if (pin_ray & pinned_pieces).is_not_empty() {
                 // there is only one piece on this ray so there is a pin
                 // we only need to generate moves for rooks, queens and pawn pushes in this case
 
-                // add any pinned piece to the mask
-                pinned_pieces |= pin_ray & self.color_masks[color];
-
                 let pinned_rook_or_queen =
                     pin_ray & (self.piece_masks[(color, Rook)] | self.piece_masks[(color, Queen)]);
                 if pinned_rook_or_queen.is_not_empty() {
                     let rook_square = pinned_rook_or_queen.first_square();
                     let rook_moves = (pin_ray | pinner_square.bitboard())
                         & (push_mask | capture_mask)
+                        & target_mask
                         & pinned_rook_or_queen.inverse();
                     for target in rook_moves {
                         let capture = target == pinner_square;
@@ -678,6 +1220,7 @@ impl ChessGame {
                     let mut pawn_moves = lookup_pawn_push(pawn_square, color)
                         & pin_ray
                         & push_mask
+                        & target_mask
                         & self.combined.inverse();
                     if pawn_moves.is_not_empty()
                         && ((color == White
@@ -709,23 +1252,22 @@ impl ChessGame {
         let diagonal_pin_rays = lookup_bishop(king_square, self.color_masks[!color]);
         let pinning_diagonals = (self.piece_masks[(!color, Bishop)]
             | self.piece_masks[(!color, Queen)])
-            & diagonal_pin_rays;
+            & diagonal_pin_rays
+            & pin_candidates;
         for pinner_square in pinning_diagonals {
             let pin_ray = lookup_between(king_square, pinner_square);
 
-            if (pin_ray & self.color_masks[color]).count_ones() == 1 {
+            if (pin_ray & pinned_pieces).is_not_empty() {
                 // there is only the king and one piece on this ray so there is a pin
                 // we only need to generate moves for bishops, queens and pawn captures in this case
 
-                // add any pinned piece to the mask
-                pinned_pieces |= pin_ray & self.color_masks[color];
-
                 let pinned_bishop_or_queen = pin_ray
                     & (self.piece_masks[(color, Bishop)] | self.piece_masks[(color, Queen)]);
                 if pinned_bishop_or_queen.is_not_empty() {
                     let bishop_square = pinned_bishop_or_queen.first_square();
                     let bishop_moves = (pin_ray | pinner_square.bitboard())
                         & (push_mask | capture_mask)
+                        & target_mask
                         & pinned_bishop_or_queen.inverse();
                     for target in bishop_moves {
                         let capture = target == pinner_square;
@@ -780,23 +1322,12 @@ impl ChessGame {
 
         // Other moves
         // Castling if not in check
-        if num_checkers == 0 {
-            let king = self.piece_masks[(color, King)];
-            if self.castling_rights[(color, Kingside)]
-                && (self.combined & (king << 1 | king << 2)).is_empty()
-                && (attacked_squares & (king << 1 | king << 2)).is_empty()
-            {
-                // generate castling kingside if rights remain, the way is clear and the squares aren't attacked
-                let start = king.first_square();
-                moves.push(Move::king_castle(start, start.offset(2, 0)));
-            }
-            if self.castling_rights[(color, Queenside)]
-                && ((self.combined) & (king >> 1 | king >> 2 | king >> 3)).is_empty()
-                && (attacked_squares & (king >> 1 | king >> 2)).is_empty()
-            {
-                // generate castling queenside if rights remain, the way is clear and the squares aren't attacked
-                let start = king.first_square();
-                moves.push(Move::king_castle(start, start.offset(-2, 0)));
+        if QUIETS && num_checkers == 0 {
+            for side in [Kingside, Queenside] {
+                // generate castling if rights remain, the way is clear and the squares aren't attacked
+                if self.can_castle(color, side, attacked_squares) {
+                    moves.push(self.castling_move(color, side));
+                }
             }
         }
         // Pawn moves
@@ -808,7 +1339,8 @@ impl ChessGame {
                 let pawn = pawn_square.bitboard();
 
                 // single pawn pushes
-                let pawn_push_one = (pawn << 8) & push_mask & (self.combined).inverse();
+                let pawn_push_one =
+                    (pawn << 8) & push_mask & pawn_push_mask & (self.combined).inverse();
                 if pawn_push_one.is_not_empty() {
                     let target: Square = pawn_push_one.first_square();
                     // promotions
@@ -826,7 +1358,8 @@ impl ChessGame {
                 let pawn_push_two = ((((pawn & SECOND_RANK) << 8) & (self.combined).inverse())
                     << 8)
                     & (self.combined).inverse()
-                    & push_mask;
+                    & push_mask
+                    & target_mask;
 
                 if pawn_push_two.is_not_empty() {
                     moves.push(Move::pawn_double_push(
@@ -894,7 +1427,8 @@ impl ChessGame {
                 let pawn = pawn_square.bitboard();
 
                 // single pawn pushes
-                let pawn_push_one = pawn >> 8 & push_mask & (self.combined).inverse();
+                let pawn_push_one =
+                    pawn >> 8 & push_mask & pawn_push_mask & (self.combined).inverse();
                 if pawn_push_one.is_not_empty() {
                     let target: Square = pawn_push_one.first_square();
                     // promotions
@@ -912,7 +1446,8 @@ impl ChessGame {
                 let pawn_push_two = ((((pawn & SEVENTH_RANK) >> 8) & (self.combined).inverse())
                     >> 8)
                     & (self.combined).inverse()
-                    & push_mask;
+                    & push_mask
+                    & target_mask;
                 if pawn_push_two.is_not_empty() {
                     moves.push(Move::pawn_double_push(
                         pawn_square,
@@ -969,7 +1504,8 @@ impl ChessGame {
         for knight_square in knights {
             let attacks = lookup_knight(knight_square)
                 & self.color_masks[color].inverse()
-                & (push_mask | capture_mask);
+                & (push_mask | capture_mask)
+                & target_mask;
             for target in attacks {
                 let capture = (self.color_masks[!color] & target.bitboard()).is_not_empty();
                 moves.push(Move::knight_move(knight_square, target, capture));
@@ -981,7 +1517,8 @@ impl ChessGame {
         for bishop_square in bishops {
             let attacks = lookup_bishop(bishop_square, self.combined)
                 & self.color_masks[color].inverse()
-                & (push_mask | capture_mask);
+                & (push_mask | capture_mask)
+                & target_mask;
             for target in attacks {
                 let capture = (self.color_masks[!color] & target.bitboard()).is_not_empty();
                 moves.push(Move::bishop_move(bishop_square, target, capture));
@@ -993,7 +1530,8 @@ impl ChessGame {
         for rook_square in rooks {
             let attacks = lookup_rook(rook_square, self.combined)
                 & self.color_masks[color].inverse()
-                & (push_mask | capture_mask);
+                & (push_mask | capture_mask)
+                & target_mask;
             for target in attacks {
                 let capture = (self.color_masks[!color] & target.bitboard()).is_not_empty();
                 moves.push(Move::rook_move(rook_square, target, capture));
@@ -1005,14 +1543,13 @@ impl ChessGame {
         for queen_square in queens {
             let attacks = lookup_queen(queen_square, self.combined)
                 & self.color_masks[color].inverse()
-                & (push_mask | capture_mask);
+                & (push_mask | capture_mask)
+                & target_mask;
             for target in attacks {
                 let capture = (self.color_masks[!color] & target.bitboard()).is_not_empty();
                 moves.push(Move::queen_move(queen_square, target, capture));
             }
         }
-
-        moves
     }
 
     pub fn make_move(&mut self, move_: Move) {
@@ -1023,6 +1560,9 @@ impl ChessGame {
 
         let captured = if move_.en_passent() {
             Pawn
+        } else if move_.castling() {
+            // a Chess960 castling move targets the king's own rook
+            NoPiece
         } else {
             self.piece_at(target)
         };
@@ -1038,6 +1578,10 @@ impl ChessGame {
             move_.castling(),
             self.castling_rights,
             self.halfmove_clock,
+            self.fullmove_number,
+            self.pawn_hash,
+            self.checkers,
+            self.pinned,
         ));
 
         // add the last position into the history
@@ -1048,27 +1592,30 @@ impl ChessGame {
 
         // Castling
         if move_.castling() {
-            let dx = *target as isize - *start as isize;
-            let (rook_start, rook_target) = if dx == 2 {
-                // Kingside
-                (target.offset(1, 0), target.offset(-1, 0))
+            let side = if target.file() > start.file() {
+                Kingside
             } else {
-                // Queenside
-                (target.offset(-2, 0), target.offset(1, 0))
+                Queenside
             };
+            let CastlingPath {
+                king_start,
+                king_target,
+                rook_start,
+                rook_target,
+                ..
+            } = castling_path(color, side, self.castling_files[color]);
 
             // update king position and hash
-            self.hash ^= zobrist_piece(King, color, start) ^ zobrist_piece(King, color, target);
-            self.piece_masks[(color, King)] ^= target.bitboard() | start.bitboard();
+            self.hash ^=
+                zobrist_piece(King, color, king_start) ^ zobrist_piece(King, color, king_target);
+            self.piece_masks[(color, King)] ^= king_start.bitboard() ^ king_target.bitboard();
             // update rook position and hash
             self.hash ^=
                 zobrist_piece(Rook, color, rook_start) ^ zobrist_piece(Rook, color, rook_target);
-            self.piece_masks[(color, Rook)] ^= rook_target.bitboard() | rook_start.bitboard();
-            // update color masks
-            self.color_masks[color] ^= start.bitboard()
-                | target.bitboard()
-                | rook_start.bitboard()
-                | rook_target.bitboard();
+            self.piece_masks[(color, Rook)] ^= rook_start.bitboard() ^ rook_target.bitboard();
+            // update color masks, in Chess960 the king and rook can swap squares or stay put
+            self.color_masks[color] &= (king_start.bitboard() | rook_start.bitboard()).inverse();
+            self.color_masks[color] |= king_target.bitboard() | rook_target.bitboard();
             // update castling rights
             self.hash ^= zobrist_castling(self.castling_rights);
             self.castling_rights[color] = [false, false];
@@ -1088,6 +1635,9 @@ impl ChessGame {
             };
             // remove piece from target square
             self.hash ^= zobrist_piece(captured, !color, cap_square);
+            if captured == Pawn {
+                self.pawn_hash ^= zobrist_piece(Pawn, !color, cap_square);
+            }
             self.piece_masks[(!color, captured)] ^= cap_square.bitboard();
             self.color_masks[!color] ^= cap_square.bitboard();
 
@@ -1107,14 +1657,16 @@ impl ChessGame {
             self.castling_rights[color] = [false, false];
             self.hash ^= zobrist_castling(self.castling_rights);
         } else if piece == Rook {
-            if self.castling_rights[(color, Kingside)] && *start as usize == 7 + 56 * color as usize
+            let rooks = self.castling_files[color].rooks;
+            if self.castling_rights[(color, Kingside)]
+                && *start as usize == rooks[Kingside] + 56 * color as usize
             {
                 // kingside rook has made first move
                 self.hash ^= zobrist_castling(self.castling_rights);
                 self.castling_rights[(color, Kingside)] = false;
                 self.hash ^= zobrist_castling(self.castling_rights);
             } else if self.castling_rights[(color, Queenside)]
-                && *start as usize == 56 * color as usize
+                && *start as usize == rooks[Queenside] + 56 * color as usize
             {
                 // queenside rook has made first move
                 self.hash ^= zobrist_castling(self.castling_rights);
@@ -1123,15 +1675,16 @@ impl ChessGame {
             }
         }
         if captured == Rook {
+            let rooks = self.castling_files[!color].rooks;
             if self.castling_rights[(!color, Kingside)]
-                && *target as usize == 7 + 56 * !color as usize
+                && *target as usize == rooks[Kingside] + 56 * !color as usize
             {
                 // kingside rook has been captured
                 self.hash ^= zobrist_castling(self.castling_rights);
                 self.castling_rights[(!color, Kingside)] = false;
                 self.hash ^= zobrist_castling(self.castling_rights);
             } else if self.castling_rights[(!color, Queenside)]
-                && *target as usize == 56 * !color as usize
+                && *target as usize == rooks[Queenside] + 56 * !color as usize
             {
                 // queenside rook has been captured
                 self.hash ^= zobrist_castling(self.castling_rights);
@@ -1149,6 +1702,8 @@ impl ChessGame {
 
         // pawn special cases
         if piece == Pawn {
+            self.pawn_hash ^=
+                zobrist_piece(Pawn, color, start) ^ zobrist_piece(Pawn, color, target);
             // en passent square
             if move_.double_pawn_push() {
                 let ep_square: Square = if color == White {
@@ -1166,6 +1721,7 @@ impl ChessGame {
             if move_.promotion() != NoPiece {
                 self.hash ^= zobrist_piece(Pawn, color, target)
                     ^ zobrist_piece(move_.promotion(), color, target);
+                self.pawn_hash ^= zobrist_piece(Pawn, color, target);
                 self.piece_masks[(color, Pawn)] ^= target.bitboard();
                 self.piece_masks[(color, move_.promotion())] |= target.bitboard();
             }
@@ -1173,6 +1729,10 @@ impl ChessGame {
             self.halfmove_clock = 0;
         }
 
+        if color == Black {
+            self.fullmove_number += 1;
+        }
+
         // swap players
         self.hash ^= zobrist_player();
         self.current_player = !self.current_player;
@@ -1180,9 +1740,40 @@ impl ChessGame {
         // update combined mask
         self.combined = self.color_masks[White] | self.color_masks[Black];
 
+        self.update_check_info();
+
+        #[cfg(feature = "debug-checks")]
+        if self.moved_into_check() {
+            let mut before = self.clone();
+            before.unmake_move();
+            panic!(
+                "{} leaves the king in check in FEN {}",
+                move_.coords(),
+                before.fen()
+            );
+        }
+
         // debug_assert!(self.hash == self.zobrist_hash());
     }
 
+    /// Whether the last move left the king of the side that made it in check, i.e. was illegal
+    pub fn moved_into_check(&self) -> bool {
+        self.in_check(!self.current_player)
+    }
+
+    /// Make a move and report what it captured and whether it gave check
+    pub fn make_move_info(&mut self, move_: Move) -> MoveInfo {
+        self.make_move(move_);
+        let unmove = self.unmove_history.last().unwrap();
+        MoveInfo {
+            captured: unmove.capture,
+            en_passent: unmove.en_passent,
+            castling: unmove.castling,
+            promotion: move_.promotion(),
+            check: self.in_check(self.current_player),
+        }
+    }
+
     pub fn unmake_move(&mut self) {
         self.current_player = !self.current_player;
 
@@ -1199,37 +1790,25 @@ impl ChessGame {
         }
 
         if unmove.castling {
-            if target.file() == 2 {
-                // queenside
-                self.piece_masks[(self.current_player, King)] ^=
-                    start.bitboard() | target.bitboard();
-
-                let rook_start: Square = target.offset(-2, 0);
-                let rook_target: Square = target.offset(1, 0);
-
-                self.piece_masks[(self.current_player, Rook)] ^=
-                    rook_start.bitboard() | rook_target.bitboard();
-
-                self.color_masks[self.current_player] ^= start.bitboard()
-                    | target.bitboard()
-                    | rook_start.bitboard()
-                    | rook_target.bitboard();
+            let side = if target.file() > start.file() {
+                Kingside
             } else {
-                // kingside
-                self.piece_masks[(self.current_player, King)] ^=
-                    start.bitboard() | target.bitboard();
-
-                let rook_start: Square = target.offset(1, 0);
-                let rook_target: Square = target.offset(-1, 0);
-
-                self.piece_masks[(self.current_player, Rook)] ^=
-                    rook_start.bitboard() | rook_target.bitboard();
-
-                self.color_masks[self.current_player] ^= start.bitboard()
-                    | target.bitboard()
-                    | rook_start.bitboard()
-                    | rook_target.bitboard();
-            }
+                Queenside
+            };
+            let color = self.current_player;
+            let CastlingPath {
+                king_start,
+                king_target,
+                rook_start,
+                rook_target,
+                ..
+            } = castling_path(color, side, self.castling_files[color]);
+
+            self.piece_masks[(color, King)] ^= king_start.bitboard() ^ king_target.bitboard();
+            self.piece_masks[(color, Rook)] ^= rook_start.bitboard() ^ rook_target.bitboard();
+
+            self.color_masks[color] &= (king_target.bitboard() | rook_target.bitboard()).inverse();
+            self.color_masks[color] |= king_start.bitboard() | rook_start.bitboard();
         } else {
             // move piece back to start
             self.piece_masks[(self.current_player, piece)] ^= start.bitboard() | target.bitboard();
@@ -1253,10 +1832,17 @@ impl ChessGame {
         self.castling_rights = unmove.castling_rights;
         self.en_passent_mask = unmove.en_passent_mask;
         self.hash = self.position_history.pop().unwrap();
+        self.pawn_hash = unmove.pawn_hash;
         self.halfmove_clock = unmove.halfmove_clock;
+        self.checkers = unmove.checkers;
+        self.pinned = unmove.pinned;
+        self.fullmove_number = unmove.fullmove_number;
 
         self.combined = self.color_masks[White] | self.color_masks[Black];
 
+        #[cfg(feature = "debug-checks")]
+        self.validate_check_info();
+
         // debug_assert!(self.hash == self.zobrist_hash());
     }
 
@@ -1271,6 +1857,10 @@ impl ChessGame {
             false,
             self.castling_rights,
             0,
+            self.fullmove_number,
+            self.pawn_hash,
+            self.checkers,
+            self.pinned,
         );
 
         self.unmove_history.push(unmove);
@@ -1284,6 +1874,8 @@ impl ChessGame {
         self.hash ^= zobrist_player();
         self.halfmove_clock += 1;
         self.current_player = !self.current_player;
+
+        self.update_check_info();
     }
 
     pub fn unmake_null_move(&mut self) {
@@ -1293,6 +1885,12 @@ impl ChessGame {
         self.current_player = !self.current_player;
         self.halfmove_clock -= 1;
         self.hash = self.position_history.pop().unwrap();
+        self.checkers = unmove.checkers;
+        self.pinned = unmove.pinned;
+        self.fullmove_number = unmove.fullmove_number;
+
+        #[cfg(feature = "debug-checks")]
+        self.validate_check_info();
     }
 
     pub fn zobrist_hash(&self) -> u64 {
@@ -1323,14 +1921,51 @@ impl ChessGame {
         hash
     }
 
+    /// XOR of the Zobrist keys of every pawn on the board. Pawn structure evaluation only
+    /// depends on the pawns, so this is the key it's cached under
+    pub fn zobrist_pawn_hash(&self) -> u64 {
+        let mut hash = 0u64;
+        for color in [White, Black] {
+            for square in self.piece_masks[(color, Pawn)] {
+                hash ^= zobrist_piece(Pawn, color, square);
+            }
+        }
+        hash
+    }
+
+    /// Legal captures, including en passent, and promotions.
+    /// Together with [`ChessGame::legal_quiets`] these make up all legal moves.
+    pub fn legal_captures(&self) -> Vec<Move> {
+        self.legal_moves()
+            .into_iter()
+            .filter(|&m| self.is_capture(m) || m.promotion() != NoPiece)
+            .collect()
+    }
+
+    /// Legal moves that neither capture nor promote, including castling
+    pub fn legal_quiets(&self) -> Vec<Move> {
+        self.legal_moves()
+            .into_iter()
+            .filter(|&m| !self.is_capture(m) && m.promotion() == NoPiece)
+            .collect()
+    }
+
+    /// All legal moves in a canonical order, by start square then target square,
+    /// with promotions ordered knight, bishop, rook, queen
+    pub fn legal_moves_sorted(&self) -> Vec<Move> {
+        let mut moves = self.legal_moves();
+        moves.sort_by_key(|m| (*m.start(), *m.target(), m.promotion() as u8));
+        moves
+    }
+
     pub fn perft(&mut self, depth: usize) -> usize {
         if depth == 1 {
-            return self.legal_moves().len();
+            return self.legal_move_list().len();
         } else if depth == 0 {
             return 1;
         }
 
-        let moves = self.legal_moves();
+        let moves = self.legal_move_list();
         let mut nodes = 0;
 
         for move_ in moves {
@@ -1354,26 +1989,74 @@ impl ChessGame {
             let nodes = self.perft(depth - 1);
             self.unmake_move();
             node_count += nodes;
-            println!(
-                "{}{}: {}",
-                move_.coords(),
-                match move_.promotion() {
-                    Knight => "=N",
-                    Bishop => "=B",
-                    Rook => "=R",
-                    Queen => "=Q",
-                    _ => "",
-                },
-                nodes
-            );
+            println!("{}: {}", move_.coords(), nodes);
         }
         println!("Moves: {}, Nodes: {}\n", move_count, node_count);
     }
 }
 
+/// Positions compare equal when the pieces, side to move, castling rights and en passent
+/// square match. Move history and the halfmove clock are ignored.
+impl PartialEq for ChessGame {
+    fn eq(&self, other: &Self) -> bool {
+        self.piece_masks == other.piece_masks
+            && self.color_masks == other.color_masks
+            && self.current_player == other.current_player
+            && self.castling_rights == other.castling_rights
+            && self.castling_files == other.castling_files
+            && self.en_passent_mask == other.en_passent_mask
+    }
+}
+
+impl Eq for ChessGame {}
+
+impl Hash for ChessGame {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.position_key().hash(state);
+    }
+}
+
+impl Display for ChessGame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f)?;
+        for rank in (0..8u8).rev() {
+            let pieces = (0..8u8)
+                .map(|file| {
+                    let square = Square::from(8 * rank + file);
+                    self.piece_at(square)
+                        .to_fen_char(self.color_at(square))
+                        .unwrap_or('.')
+                        .to_string()
+                })
+                .collect::<Vec<_>>();
+            writeln!(f, "{}  {}", rank + 1, pieces.join(" "))?;
+        }
+        writeln!(f, "\n   a b c d e f g h\n")?;
+
+        // castling rights are formatted the same as in the FEN
+        let fen = self.fen();
+        let castling = fen.split(' ').nth(2).unwrap_or("-");
+        writeln!(f, "Side to move: {:?}", self.current_player)?;
+        writeln!(f, "Castling: {castling}")?;
+        match self.en_passent_square() {
+            Some(sq) => writeln!(f, "En passent: {}", coord(sq))?,
+            None => writeln!(f, "En passent: -")?,
+        }
+        writeln!(f, "Halfmove clock: {}", self.halfmove_clock)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{chessgame::ChessGame, search::Search};
+    use std::{collections::HashSet, error::Error};
+
+    use crate::{
+        chessgame::{ChessGame, GameResult, GameStatus},
+        moves::Move,
+        search::Search,
+        types::{ColorIndex::*, PieceIndex::*},
+    };
+    use cheers_bitboards::Square;
 
     #[test]
     fn search_speed() -> Result<(), ()> {
@@ -1384,4 +2067,909 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn kingless_position() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+
+        // black has no king
+        game.set_from_fen("8/8/8/3q4/8/8/8/4K3 w - - 0 1")?;
+        assert!(game.king_attacks(Black).is_empty());
+        assert!(!game.in_check(White));
+        assert!(!game.in_check(Black));
+
+        // neither side has a king
+        game.set_from_fen("8/8/8/3q4/8/8/3R4/8 b - - 0 1")?;
+        assert!(game.king_attacks(White).is_empty());
+        assert!(game.king_attacks(Black).is_empty());
+        assert!(!game.in_check(White));
+        assert!(!game.in_check(Black));
+
+        Ok(())
+    }
+
+    fn assert_check_info(game: &mut ChessGame, depth: usize) {
+        let color = game.current_player();
+        assert_eq!(
+            game.checkers(),
+            game.compute_checkers(color),
+            "{}",
+            game.fen()
+        );
+        assert_eq!(
+            game.pinned_pieces(),
+            game.compute_pinned(color),
+            "{}",
+            game.fen()
+        );
+        if depth == 0 {
+            return;
+        }
+        for move_ in game.legal_moves() {
+            game.make_move(move_);
+            assert_check_info(game, depth - 1);
+            game.unmake_move();
+            assert_eq!(
+                game.checkers(),
+                game.compute_checkers(color),
+                "{}",
+                game.fen()
+            );
+            assert_eq!(
+                game.pinned_pieces(),
+                game.compute_pinned(color),
+                "{}",
+                game.fen()
+            );
+        }
+    }
+
+    #[test]
+    fn check_info_cache() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+        ];
+        for fen in fens {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            assert_check_info(&mut game, 3);
+
+            game.make_null_move();
+            assert_check_info(&mut game, 1);
+            game.unmake_null_move();
+            assert_check_info(&mut game, 1);
+        }
+
+        Ok(())
+    }
+
+    fn legal_move(game: &ChessGame, coords: &str) -> Move {
+        game.legal_moves()
+            .into_iter()
+            .find(|m| m.coords() == coords)
+            .unwrap()
+    }
+
+    #[test]
+    fn move_info() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("k7/8/8/3r4/8/8/3Q4/4K3 w - - 0 1")?;
+
+        let info = game.make_move_info(legal_move(&game, "d2d5"));
+        assert_eq!(info.captured, Rook);
+        assert!(info.check);
+        assert!(!info.en_passent && !info.castling);
+        assert_eq!(info.promotion, NoPiece);
+
+        game.set_from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1")?;
+        let info = game.make_move_info(legal_move(&game, "e5d6"));
+        assert_eq!(info.captured, Pawn);
+        assert!(info.en_passent);
+        assert!(!info.check);
+
+        Ok(())
+    }
+
+    #[test]
+    fn is_capture() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k3/8/8/3pP3/8/8/8/R3K3 w - d6 0 1")?;
+
+        // flags from the move generator
+        assert!(game.is_capture(legal_move(&game, "a1a8")));
+        assert!(game.is_capture(legal_move(&game, "e5d6")));
+        assert!(!game.is_capture(legal_move(&game, "e5e6")));
+
+        // moves without capture flags set
+        assert!(game.is_capture(Move::rook_move(Square::A1, Square::A8, false)));
+        assert!(game.is_capture(Move::pawn_push(Square::E5, Square::D6)));
+        assert!(!game.is_capture(Move::pawn_push(Square::E5, Square::E6)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn position_equality() -> Result<(), Box<dyn Error>> {
+        let mut a = ChessGame::new();
+        let mut b = ChessGame::new();
+
+        // same position reached by transposed move orders
+        for coords in ["g1f3", "g8f6", "b1c3"] {
+            let m = legal_move(&a, coords);
+            a.make_move(m);
+        }
+        for coords in ["b1c3", "g8f6", "g1f3"] {
+            let m = legal_move(&b, coords);
+            b.make_move(m);
+        }
+        assert!(a == b);
+        assert_eq!(a.position_key(), b.position_key());
+
+        let mut positions = HashSet::new();
+        positions.insert(a.clone());
+        assert!(positions.contains(&b));
+
+        let m = legal_move(&b, "b8c6");
+        b.make_move(m);
+        assert!(a != b);
+        assert!(!positions.contains(&b));
+        Ok(())
+    }
+
+    #[test]
+    fn attackers_defenders() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/4q2b/5n2/3p4/8/2NP4/4Q3/1B2RK2 w - - 0 1")?;
+
+        let (white, black) = game.attackers_defenders(Square::E4);
+        // the rook and bishop are blocked by their own pieces
+        assert!(white == Square::C3.bitboard() | Square::D3.bitboard() | Square::E2.bitboard());
+        assert!(
+            black
+                == Square::D5.bitboard()
+                    | Square::F6.bitboard()
+                    | Square::E7.bitboard()
+                    | Square::H7.bitboard()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn castling_paths() -> Result<(), Box<dyn Error>> {
+        let castles = |fen: &str| -> Result<Vec<String>, Box<dyn Error>> {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            Ok(game
+                .legal_moves()
+                .into_iter()
+                .filter(|m| m.castling())
+                .map(|m| m.coords())
+                .collect())
+        };
+
+        assert_eq!(
+            castles("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1")?,
+            ["e1g1", "e1c1"]
+        );
+        // f1 is attacked
+        assert_eq!(castles("4kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1")?, ["e1c1"]);
+        // b1 is attacked, but the king doesn't pass through it
+        assert_eq!(
+            castles("1r2k3/8/8/8/8/8/8/R3K2R w KQ - 0 1")?,
+            ["e1g1", "e1c1"]
+        );
+        // b1 is occupied
+        assert_eq!(castles("4k3/8/8/8/8/8/8/RN2K2R w KQ - 0 1")?, ["e1g1"]);
+        // d8 is attacked
+        assert_eq!(castles("r3k2r/8/8/8/8/8/8/3RK3 b kq - 0 1")?, ["e8g8"]);
+        Ok(())
+    }
+
+    #[test]
+    fn chess960_castling() -> Result<(), Box<dyn Error>> {
+        let castles = |fen: &str| -> Result<Vec<String>, Box<dyn Error>> {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            Ok(game
+                .legal_moves()
+                .into_iter()
+                .filter(|m| m.castling())
+                .map(|m| m.coords())
+                .collect())
+        };
+
+        // the king takes its own rook, even when it is already on its target square
+        assert_eq!(
+            castles("4k3/8/8/8/8/8/8/1R4KR w HB - 0 1")?,
+            ["g1h1", "g1b1"]
+        );
+        // the rook crosses the king's path on the queenside
+        assert_eq!(castles("4k3/8/8/8/8/8/8/1KR5 w C - 0 1")?, ["b1c1"]);
+        // c1 is only shielded by the castling rook
+        assert_eq!(
+            castles("4k3/8/8/8/8/8/8/qRK5 w B - 0 1")?,
+            Vec::<String>::new()
+        );
+        // the rook's target is attacked, which doesn't matter
+        assert_eq!(castles("3rk3/8/8/8/8/8/8/1RK5 w B - 0 1")?, ["c1b1"]);
+
+        // castling swaps the king and rook and unmakes cleanly
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/8/8/8/8/8/2RK4 w C - 0 1")?;
+        let before = game.clone();
+        game.make_move(legal_move(&game, "d1c1"));
+        assert_eq!(game.fen(), "4k3/8/8/8/8/8/8/2KR4 b - - 1 1");
+        assert_eq!(game.hash(), game.zobrist_hash());
+        game.unmake_move();
+        assert!(game == before && game.hash() == before.hash());
+        Ok(())
+    }
+
+    #[test]
+    fn chess960_perft() -> Result<(), Box<dyn Error>> {
+        for (fen, counts) in [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                [21, 528, 12189, 326672],
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                [21, 807, 18002, 667366],
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+                [20, 479, 10471, 273318],
+            ),
+            (
+                "1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9",
+                [28, 1120, 31058, 1171749],
+            ),
+            (
+                "qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9",
+                [29, 899, 26578, 824055],
+            ),
+        ] {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            assert!(game.chess960());
+            for (depth, &count) in counts.iter().enumerate() {
+                assert_eq!(game.perft(depth + 1), count, "{fen} depth {}", depth + 1);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn promotion_notation() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("8/4P3/8/8/8/8/k7/4K3 w - - 0 1")?;
+
+        let mut promotions = game
+            .legal_moves()
+            .into_iter()
+            .filter(|m| m.promotion() != NoPiece)
+            .map(|m| m.coords())
+            .collect::<Vec<_>>();
+        promotions.sort();
+        assert_eq!(promotions, ["e7e8b", "e7e8n", "e7e8q", "e7e8r"]);
+
+        // UCI moves parse back to the same promotion
+        for (coords, piece) in [
+            ("e7e8n", Knight),
+            ("e7e8b", Bishop),
+            ("e7e8r", Rook),
+            ("e7e8q", Queen),
+        ] {
+            let move_ = Move::from_pair(&game, coords);
+            assert_eq!(move_.promotion(), piece);
+            assert_eq!(move_.coords(), coords);
+        }
+        assert_eq!(Move::from_pair(&game, "e1d1").promotion(), NoPiece);
+        Ok(())
+    }
+
+    #[test]
+    fn sorted_legal_moves() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let sorted = game.legal_moves_sorted();
+        assert_eq!(sorted.len(), game.legal_moves().len());
+        assert!(sorted
+            .windows(2)
+            .all(|w| (*w[0].start(), *w[0].target()) <= (*w[1].start(), *w[1].target())));
+
+        // the same position set up again gives the same order
+        let mut other = ChessGame::new();
+        other.set_from_fen(game.fen())?;
+        let coords = |moves: Vec<Move>| moves.iter().map(|m| m.coords()).collect::<Vec<_>>();
+        assert_eq!(coords(sorted), coords(other.legal_moves_sorted()));
+        assert_eq!(
+            coords(game.legal_moves_sorted())[..4],
+            ["a1b1", "a1c1", "a1d1", "e1c1"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn position_round_trip() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQ - 3 1")?;
+        let restored = ChessGame::from_position(game.position());
+        assert_eq!(restored.fen(), game.fen());
+
+        // moves played before the conversion are dropped, the position is kept
+        for coords in ["e2a6", "e8g8", "a2a4"] {
+            game.make_move(Move::from_pair(&game, coords));
+        }
+        let position = game.position();
+        let restored = ChessGame::from_position(position);
+        assert!(restored == game);
+        assert_eq!(restored.hash(), game.hash());
+        assert!(restored.en_passent_square() == Some(Square::A3));
+        assert!(restored.position_history().is_empty());
+        assert_eq!(restored.legal_moves().len(), game.legal_moves().len());
+        Ok(())
+    }
+
+    #[test]
+    fn mirror() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w Kq - 0 1")?;
+        let mirrored = game.mirror();
+        assert_eq!(
+            mirrored.fen(),
+            "r3k2r/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b Qk - 0 1"
+        );
+        assert_eq!(mirrored.hash(), mirrored.zobrist_hash());
+        assert!(mirrored.mirror() == game);
+
+        // the checks are mirrored too
+        game.set_from_fen("4k3/8/8/8/8/8/4q3/4K3 w - - 0 1")?;
+        assert!(game.mirror().in_check(Black));
+        Ok(())
+    }
+
+    #[test]
+    fn incremental_pawn_hash() -> Result<(), Box<dyn Error>> {
+        // en passent, promotions and pawns captured by pieces
+        let fens = [
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ];
+        // xorshift, so the random games are the same every run
+        let mut rng = 0x2545f4914f6cdd1du64;
+        for fen in fens {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            for _ in 0..100 {
+                let moves = game.legal_moves();
+                if moves.is_empty() {
+                    break;
+                }
+                for &move_ in &moves {
+                    let before = game.pawn_hash();
+                    game.make_move(move_);
+                    assert_eq!(game.pawn_hash(), game.zobrist_pawn_hash(), "{}", game.fen());
+                    game.unmake_move();
+                    assert_eq!(game.pawn_hash(), before);
+                }
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                game.make_move(moves[rng as usize % moves.len()]);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn legal_moves_never_leave_king_in_check() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ];
+        // xorshift, so the random games are the same every run
+        let mut rng = 0x2545f4914f6cdd1du64;
+        for fen in fens {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            for _ in 0..200 {
+                let moves = game.legal_moves();
+                if moves.is_empty() {
+                    break;
+                }
+                for &move_ in &moves {
+                    game.make_move(move_);
+                    let illegal = game.moved_into_check();
+                    game.unmake_move();
+                    assert!(
+                        !illegal,
+                        "{} leaves the king in check in FEN {}",
+                        move_.coords(),
+                        game.fen()
+                    );
+                }
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                game.make_move(moves[rng as usize % moves.len()]);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fen_en_passent_square() -> Result<(), Box<dyn Error>> {
+        // no black pawn can take on e3
+        let mut game = ChessGame::new();
+        game.set_from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1")?;
+        assert!(game.en_passent_square().is_none());
+        assert_eq!(game.hash(), game.zobrist_hash());
+
+        // the same position reached by playing the move
+        let mut played = ChessGame::new();
+        played.make_move(legal_move(&played, "e2e4"));
+        assert_eq!(game.hash(), played.hash());
+
+        // d4 can take on e3
+        game.set_from_fen("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1")?;
+        assert!(game.en_passent_square() == Some(Square::E3));
+        assert_eq!(game.hash(), game.zobrist_hash());
+        assert!(game.legal_moves().iter().any(|m| m.en_passent()));
+        Ok(())
+    }
+
+    #[test]
+    fn automatic_draws() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        for _ in 0..3 {
+            for coords in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                game.make_move(legal_move(&game, coords));
+            }
+        }
+        // a threefold repetition has to be claimed
+        assert_eq!(game.repetition_count(), 3);
+        assert_eq!(game.game_result(), None);
+        for coords in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            game.make_move(legal_move(&game, coords));
+        }
+        assert_eq!(game.repetition_count(), 4);
+        assert_eq!(game.game_result(), Some(GameResult::Draw));
+
+        // positions before a pawn move can't repeat
+        game.make_move(legal_move(&game, "e2e4"));
+        assert_eq!(game.repetition_count(), 0);
+
+        game.set_from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 149 100")?;
+        assert_eq!(game.game_result(), None);
+        game.make_move(legal_move(&game, "a1a2"));
+        assert_eq!(game.game_result(), Some(GameResult::Draw));
+
+        // checkmate on the 75th move still counts
+        game.set_from_fen("4k3/8/4K3/8/8/8/8/R7 w - - 149 100")?;
+        game.make_move(legal_move(&game, "a1a8"));
+        assert_eq!(game.game_result(), Some(GameResult::WhiteWin));
+        Ok(())
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let mut game = ChessGame::new();
+        for coords in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            game.make_move(legal_move(&game, coords));
+        }
+        assert_eq!(game.repetition_count(), 1);
+        assert!(!game.is_repetition());
+        for coords in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            game.make_move(legal_move(&game, coords));
+        }
+        assert_eq!(game.repetition_count(), 2);
+        assert!(game.is_repetition());
+
+        // repetitions are only counted back to the last capture or pawn move
+        for coords in ["e2e4", "g8f6", "g1f3", "f6g8", "f3g1"] {
+            game.make_move(legal_move(&game, coords));
+        }
+        assert_eq!(game.repetition_count(), 1);
+        assert!(!game.is_repetition());
+    }
+
+    #[test]
+    fn insufficient_material() -> Result<(), Box<dyn Error>> {
+        let dead = [
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/3NK3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1K3 b - - 0 1",
+            "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/B1B1K3 w - - 0 1",
+        ];
+        let alive = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/R3K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/3QK3 w - - 0 1",
+            // bishops on opposite colours
+            "2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1",
+            "4kn2/8/8/8/8/8/8/3NK3 w - - 0 1",
+        ];
+        let mut game = ChessGame::new();
+        for fen in dead {
+            game.set_from_fen(fen)?;
+            assert!(game.is_insufficient_material(), "{fen}");
+            assert_eq!(game.game_result(), Some(GameResult::Draw), "{fen}");
+        }
+        for fen in alive {
+            game.set_from_fen(fen)?;
+            assert!(!game.is_insufficient_material(), "{fen}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn move_buffers() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "R6R/3Q4/1Q4Q1/4Q3/2Q4Q/Q4Q2/pp1Q4/kBNN1KB1 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K2q w - - 0 1",
+        ];
+        let mut game = ChessGame::new();
+        let mut buffer = vec![Move::null(); 300];
+        for fen in fens {
+            game.set_from_fen(fen)?;
+            let moves = game.legal_moves();
+            game.generate_moves_into(&mut buffer);
+            assert_eq!(buffer, moves);
+            let list = game.legal_move_list();
+            assert_eq!(&list[..], &moves[..]);
+            assert_eq!(list.into_iter().collect::<Vec<_>>(), moves);
+        }
+        // the most legal moves possible in any position
+        game.set_from_fen(fens[2])?;
+        assert_eq!(game.legal_move_list().len(), 218);
+        Ok(())
+    }
+
+    #[test]
+    fn quiets_and_captures_partition_moves() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            // en passent
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ];
+        for fen in fens {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            let quiets = game.legal_quiets();
+            let captures = game.legal_captures();
+            let moves = game.legal_moves();
+            assert_eq!(quiets.len() + captures.len(), moves.len(), "{fen}");
+            assert!(moves
+                .iter()
+                .all(|m| quiets.contains(m) != captures.contains(m)));
+
+            assert!(quiets
+                .iter()
+                .all(|m| !m.capture() && m.promotion() == NoPiece));
+            assert!(captures
+                .iter()
+                .all(|m| m.capture() || m.promotion() != NoPiece));
+        }
+
+        // castling is quiet, en passent is a capture
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        assert!(game.legal_quiets().iter().any(|m| m.castling()));
+        game.set_from_fen(fens[5])?;
+        assert!(game.legal_captures().iter().any(|m| m.en_passent()));
+        Ok(())
+    }
+
+    #[test]
+    fn generate_captures() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            // pinned pieces that can only capture along the pin
+            "4k3/8/8/1b6/8/3B4/4K2r/6R1 w - - 0 1",
+            "4k3/8/8/8/1r1P1K2/8/8/8 w - - 0 1",
+            // pinned pawn capturing the pinner, and promotions
+            "4k3/1P6/8/8/8/5q2/4P3/3K4 w - - 0 1",
+        ];
+        let mut game = ChessGame::new();
+        let mut captures = Vec::new();
+        for fen in fens {
+            game.set_from_fen(fen)?;
+            game.generate_captures(&mut captures);
+            // all evasions when in check
+            if game.in_check(game.current_player()) {
+                assert_eq!(captures, game.legal_moves(), "{fen}");
+            } else {
+                assert_eq!(captures, game.legal_captures(), "{fen}");
+            }
+        }
+
+        game.set_from_fen("4k3/8/8/8/8/8/3q4/4K3 w - - 0 1")?;
+        game.generate_captures(&mut captures);
+        assert_eq!(captures, game.legal_moves());
+        Ok(())
+    }
+
+    #[test]
+    fn is_legal() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            // en passent would expose the king along the rank
+            "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1",
+            "4k3/8/8/8/8/8/3q4/4K3 w - - 0 1",
+        ];
+        let mut game = ChessGame::new();
+        let mut candidates = Vec::new();
+        for fen in fens {
+            game.set_from_fen(fen)?;
+            candidates.extend(game.legal_moves());
+        }
+        // moves from every position, so most are wrong for any given one
+        for fen in fens {
+            game.set_from_fen(fen)?;
+            let legal = game.legal_moves();
+            for &move_ in &candidates {
+                assert_eq!(
+                    game.is_legal(move_),
+                    legal.contains(&move_),
+                    "{fen} {}",
+                    move_.coords()
+                );
+            }
+        }
+
+        // flags have to match the position
+        game.set_from_fen(fens[0])?;
+        assert!(game.is_legal(Move::pawn_double_push(Square::E2, Square::E4)));
+        assert!(!game.is_legal(Move::pawn_push(Square::E2, Square::E4)));
+        assert!(!game.is_legal(Move::knight_move(Square::G1, Square::F3, true)));
+        assert!(!game.is_legal(Move::bishop_move(Square::G1, Square::F3, false)));
+        assert!(!game.is_legal(Move::null()));
+        game.set_from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1")?;
+        assert!(game.is_legal(Move::pawn_push_promotion(Square::A7, Square::A8, Queen)));
+        assert!(!game.is_legal(Move::pawn_push(Square::A7, Square::A8)));
+        assert!(!game.is_legal(Move::pawn_push_promotion(Square::A7, Square::A8, King)));
+        Ok(())
+    }
+
+    #[test]
+    fn long_halfmove_clock() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 150 80")?;
+        assert_eq!(game.halfmove_clock(), 150);
+        assert_eq!(game.game_result(), Some(GameResult::Draw));
+
+        // the clock keeps counting past 255 without wrapping
+        game.set_from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 254 200")?;
+        for coords in ["a1a2", "e8d8", "a2a1"] {
+            game.make_move(legal_move(&game, coords));
+        }
+        assert_eq!(game.halfmove_clock(), 257);
+        assert_eq!(game.status(), GameStatus::FiftyMoveDraw);
+        assert!(game.fen().ends_with(" 257 201"));
+        game.unmake_move();
+        assert_eq!(game.halfmove_clock(), 256);
+        Ok(())
+    }
+
+    #[test]
+    fn draw_claims() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 99 80")?;
+        assert!(!game.can_claim_fifty_move());
+        assert!(!game.is_draw());
+        game.make_move(legal_move(&game, "a1a2"));
+        assert!(game.can_claim_fifty_move());
+        assert!(game.is_draw());
+        game.unmake_move();
+
+        // a capture resets the clock
+        game.set_from_fen("4k3/8/8/8/8/8/r7/R3K3 w - - 99 80")?;
+        game.make_move(legal_move(&game, "a1a2"));
+        assert!(!game.can_claim_fifty_move());
+        assert!(!game.is_draw());
+        // bare kings are a draw whatever the clock
+        game.set_from_fen("4k3/8/8/8/8/8/4r3/4K3 w - - 99 80")?;
+        game.make_move(legal_move(&game, "e1e2"));
+        assert!(!game.can_claim_fifty_move());
+        assert!(game.is_draw());
+
+        game = ChessGame::new();
+        for coords in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"] {
+            game.make_move(legal_move(&game, coords));
+            assert!(!game.is_draw());
+        }
+        game.make_move(legal_move(&game, "f6g8"));
+        assert!(game.is_draw());
+        Ok(())
+    }
+
+    #[test]
+    fn fen_castling_rights() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        for rights in [
+            "KQkq", "KQk", "KQq", "KQ", "Kkq", "Qkq", "kq", "k", "q", "K", "-",
+        ] {
+            let fen = format!("r3k2r/8/8/8/8/8/8/R3K2R w {rights} - 0 1");
+            game.set_from_fen(fen.as_str())?;
+            assert_eq!(game.fen().split(' ').nth(2), Some(rights));
+        }
+        assert!(!game.chess960());
+
+        // Shredder-FEN and X-FEN rook files, written back as Shredder-FEN in Chess960
+        for (fen, written) in [
+            ("rk2r3/8/8/8/8/8/8/RK2R3 w AEae - 0 1", "EAea"),
+            ("rk2r3/8/8/8/8/8/8/RK2R3 w KQkq - 0 1", "EAea"),
+            ("1r1k2rr/8/8/8/8/8/8/1R1K2RR w Kg - 0 1", "Hg"),
+        ] {
+            game.set_from_fen(fen)?;
+            assert!(game.chess960());
+            assert_eq!(game.fen().split(' ').nth(2), Some(written));
+        }
+
+        // standard positions keep their format unless Chess960 is switched on
+        game.set_chess960(true);
+        game.set_from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1")?;
+        assert_eq!(game.fen().split(' ').nth(2), Some("HAha"));
+        assert!(game.set_from_fen("4k3/8/8/8/8/8/8/4K2R w E - 0 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fen_fullmove_number() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        assert!(game.fen().ends_with(" 0 1"));
+        game.make_move(legal_move(&game, "e2e4"));
+        assert!(game.fen().ends_with(" 0 1"));
+        game.make_move(legal_move(&game, "e7e5"));
+        assert!(game.fen().ends_with(" 0 2"));
+
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 3 40")?;
+        game.make_move(legal_move(&game, "e1g1"));
+        assert_eq!(game.fullmove_number(), 40);
+        game.make_move(legal_move(&game, "e8c8"));
+        assert!(game.fen().ends_with(" 5 41"));
+        game.unmake_move();
+        game.unmake_move();
+        assert!(game.fen().ends_with(" 3 40"));
+        Ok(())
+    }
+
+    #[test]
+    fn fullmove_number_make_unmake() -> Result<(), Box<dyn Error>> {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 57";
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen)?;
+
+        // a long line of pseudo-random moves, walking every reply on the way
+        let mut rng = 0x9e3779b97f4a7c15u64;
+        let mut plies = 0;
+        while plies < 150 {
+            let moves = game.legal_moves();
+            if moves.is_empty() {
+                break;
+            }
+            let number = game.fullmove_number();
+            for &move_ in &moves {
+                game.make_move(move_);
+                game.unmake_move();
+                assert_eq!(game.fullmove_number(), number);
+            }
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            game.make_move(moves[rng as usize % moves.len()]);
+            plies += 1;
+        }
+        assert_eq!(game.fullmove_number(), 57 + (plies as u16).div_ceil(2));
+
+        for _ in 0..plies {
+            game.unmake_move();
+        }
+        assert_eq!(game.fullmove_number(), 57);
+        assert_eq!(game.fen(), fen);
+        Ok(())
+    }
+
+    #[test]
+    fn game_status() -> Result<(), Box<dyn Error>> {
+        let status = |fen: &str| -> Result<GameStatus, Box<dyn Error>> {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            Ok(game.status())
+        };
+        assert_eq!(
+            status("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")?,
+            GameStatus::Ongoing
+        );
+        // fool's mate
+        assert_eq!(
+            status("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3")?,
+            GameStatus::Checkmate(Black)
+        );
+        assert_eq!(
+            status("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1")?,
+            GameStatus::Stalemate
+        );
+        assert_eq!(
+            status("4k3/8/8/8/8/8/8/4K3 w - - 0 1")?,
+            GameStatus::InsufficientMaterial
+        );
+        assert_eq!(
+            status("4k3/8/8/8/8/8/8/3NK3 w - - 0 1")?,
+            GameStatus::InsufficientMaterial
+        );
+        assert_eq!(
+            status("4k3/8/8/8/8/8/8/R3K3 w - - 100 80")?,
+            GameStatus::FiftyMoveDraw
+        );
+        // mate on the move that reaches fifty moves still wins
+        assert_eq!(
+            status("R3k3/8/4K3/8/8/8/8/8 b - - 100 80")?,
+            GameStatus::Checkmate(White)
+        );
+
+        let mut game = ChessGame::new();
+        for coords in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"] {
+            game.make_move(legal_move(&game, coords));
+            assert_eq!(game.status(), GameStatus::Ongoing);
+        }
+        game.make_move(legal_move(&game, "f6g8"));
+        assert_eq!(game.status(), GameStatus::ThreefoldRepetition);
+        Ok(())
+    }
+
+    #[test]
+    fn display_board() -> Result<(), Box<dyn Error>> {
+        let start = "
+8  r n b q k b n r
+7  p p p p p p p p
+6  . . . . . . . .
+5  . . . . . . . .
+4  . . . . . . . .
+3  . . . . . . . .
+2  P P P P P P P P
+1  R N B Q K B N R
+
+   a b c d e f g h
+
+Side to move: White
+Castling: KQkq
+En passent: -
+Halfmove clock: 0
+";
+        assert_eq!(ChessGame::new().to_string(), start);
+
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/8/3pP3/8/8/8/4K2R w K d6 3 40")?;
+        let rendered = game.to_string();
+        assert!(rendered.contains("5  . . . p P . . ."));
+        assert!(rendered.contains("Castling: K\n"));
+        assert!(rendered.contains("En passent: d6\n"));
+        assert!(rendered.contains("Halfmove clock: 3\n"));
+        Ok(())
+    }
 }
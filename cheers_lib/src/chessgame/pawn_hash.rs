@@ -0,0 +1,88 @@
+use super::eval_types::EvalScore;
+
+/// Entries in a pawn hash table, 384KB in total at 24 bytes per entry
+pub const PAWN_HASH_ENTRIES: usize = 1 << 14;
+
+#[derive(Clone, Copy)]
+struct PawnHashEntry {
+    key: u64,
+    score: EvalScore,
+}
+
+/// Direct-mapped cache of pawn structure scores, keyed by [`ChessGame::pawn_hash`].
+/// Pawns rarely move between sibling nodes, so most lookups hit.
+/// Not shared between threads, every search thread has its own.
+///
+/// [`ChessGame::pawn_hash`]: super::ChessGame::pawn_hash
+#[derive(Clone)]
+pub struct PawnHashTable {
+    entries: Vec<Option<PawnHashEntry>>,
+    probes: usize,
+    hits: usize,
+}
+
+impl PawnHashTable {
+    pub fn new(entries: usize) -> Self {
+        Self {
+            entries: vec![None; entries.next_power_of_two()],
+            probes: 0,
+            hits: 0,
+        }
+    }
+
+    /// The cached score for the pawn hash, from white's point of view
+    pub fn get(&mut self, pawn_hash: u64) -> Option<EvalScore> {
+        self.probes += 1;
+        let index = self.index(pawn_hash);
+        match self.entries[index] {
+            Some(entry) if entry.key == pawn_hash => {
+                self.hits += 1;
+                Some(entry.score)
+            }
+            _ => None,
+        }
+    }
+
+    /// Store a score from white's point of view, always replacing the old entry
+    pub fn set(&mut self, pawn_hash: u64, score: EvalScore) {
+        let index = self.index(pawn_hash);
+        self.entries[index] = Some(PawnHashEntry {
+            key: pawn_hash,
+            score,
+        });
+    }
+
+    /// Empty every entry and reset the hit statistics
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+        self.probes = 0;
+        self.hits = 0;
+    }
+
+    pub fn probes(&self) -> usize {
+        self.probes
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Fraction of lookups that found a score, 0 before any lookups
+    pub fn hit_rate(&self) -> f64 {
+        if self.probes == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.probes as f64
+        }
+    }
+
+    fn index(&self, pawn_hash: u64) -> usize {
+        pawn_hash as usize & (self.entries.len() - 1)
+    }
+}
+
+impl Default for PawnHashTable {
+    fn default() -> Self {
+        Self::new(PAWN_HASH_ENTRIES)
+    }
+}
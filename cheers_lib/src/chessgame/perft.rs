@@ -0,0 +1,186 @@
+use std::thread;
+
+use super::ChessGame;
+
+/// Entries in a perft table by default, 32MB in total at 32 bytes per entry
+pub const PERFT_TABLE_ENTRIES: usize = 1 << 20;
+
+#[derive(Clone, Copy)]
+struct PerftEntry {
+    hash: u64,
+    depth: usize,
+    nodes: usize,
+}
+
+/// Direct-mapped cache of perft node counts, keyed by [`ChessGame::hash`] and the remaining
+/// depth, since the same position has different counts at different depths.
+/// Can be kept between calls on the same game, the counts don't depend on the move history.
+///
+/// [`ChessGame::hash`]: super::ChessGame::hash
+#[derive(Clone)]
+pub struct PerftTable {
+    entries: Vec<Option<PerftEntry>>,
+}
+
+impl PerftTable {
+    pub fn new(entries: usize) -> Self {
+        Self {
+            entries: vec![None; entries.next_power_of_two()],
+        }
+    }
+
+    pub fn get(&self, hash: u64, depth: usize) -> Option<usize> {
+        match self.entries[self.index(hash, depth)] {
+            Some(entry) if entry.hash == hash && entry.depth == depth => Some(entry.nodes),
+            _ => None,
+        }
+    }
+
+    /// Store a node count, always replacing the old entry
+    pub fn set(&mut self, hash: u64, depth: usize, nodes: usize) {
+        let index = self.index(hash, depth);
+        self.entries[index] = Some(PerftEntry { hash, depth, nodes });
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+
+    fn index(&self, hash: u64, depth: usize) -> usize {
+        // spread the depths of one position over different entries
+        (hash ^ depth as u64) as usize & (self.entries.len() - 1)
+    }
+}
+
+impl Default for PerftTable {
+    fn default() -> Self {
+        Self::new(PERFT_TABLE_ENTRIES)
+    }
+}
+
+impl ChessGame {
+    /// [`ChessGame::perft`] with subtrees counted once per transposition, looked up in `cache`
+    pub fn perft_hashed(&mut self, depth: usize, cache: &mut PerftTable) -> usize {
+        // counting the moves is cheaper than a lookup
+        if depth <= 1 {
+            return self.perft(depth);
+        }
+        if let Some(nodes) = cache.get(self.hash, depth) {
+            return nodes;
+        }
+
+        let moves = self.legal_move_list();
+        let mut nodes = 0;
+        for move_ in moves {
+            self.make_move(move_);
+            nodes += self.perft_hashed(depth - 1, cache);
+            self.unmake_move();
+        }
+        cache.set(self.hash, depth, nodes);
+        nodes
+    }
+
+    /// [`ChessGame::perft`] with the root moves dealt out round-robin to `threads` threads,
+    /// each counting its subtrees on its own copy of the game
+    pub fn perft_parallel(&self, depth: usize, threads: usize) -> usize {
+        if depth <= 1 {
+            return self.clone().perft(depth);
+        }
+        let moves = self.legal_moves();
+        let threads = threads.clamp(1, moves.len().max(1));
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|i| {
+                    let mut game = self.clone();
+                    let moves = &moves;
+                    scope.spawn(move || {
+                        let mut nodes = 0;
+                        for &move_ in moves.iter().skip(i).step_by(threads) {
+                            game.make_move(move_);
+                            nodes += game.perft(depth - 1);
+                            game.unmake_move();
+                        }
+                        nodes
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .sum()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    const PERFT_SUITE: &str = include_str!("../perftsuite.txt");
+
+    #[test]
+    fn perft_hashed() -> Result<(), Box<dyn Error>> {
+        // Kiwipete stops at depth 5, its 8 billion nodes at depth 6 take half a minute even
+        // with the cache
+        for (fen, counts) in [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                &[20, 400, 8902, 197281, 4865609, 119060324][..],
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                &[48, 2039, 97862, 4085603, 193690690],
+            ),
+        ] {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            let mut cache = PerftTable::default();
+            for (depth, &count) in counts.iter().enumerate() {
+                let depth = depth + 1;
+                // plain perft takes too long at the deepest depths, where the known counts
+                // stand in for it
+                if depth <= 4 {
+                    assert_eq!(game.perft(depth), count);
+                }
+                assert_eq!(
+                    game.perft_hashed(depth, &mut cache),
+                    count,
+                    "{fen} depth {depth}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn perft_parallel() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        assert_eq!(game.perft_parallel(0, 4), 1);
+        assert_eq!(game.perft_parallel(4, 4), game.perft(4));
+        // more threads than root moves
+        assert_eq!(game.perft_parallel(3, 64), game.perft(3));
+
+        // the suite positions that are quick to count to depth 6
+        let mut tested = 0;
+        for line in PERFT_SUITE.lines() {
+            let mut fields = line.split(';');
+            game.set_from_fen(fields.next().unwrap_or_default().trim())?;
+            let counts = fields
+                .map(|field| field.split(' ').nth(1).unwrap_or_default().trim().parse())
+                .collect::<Result<Vec<usize>, _>>()?;
+            if counts[5] > 3_000_000 {
+                continue;
+            }
+            for (depth, &count) in counts.iter().enumerate() {
+                let depth = depth + 1;
+                assert_eq!(game.perft(depth), count);
+                assert_eq!(game.perft_parallel(depth, 3), count, "{line}");
+            }
+            tested += 1;
+        }
+        assert!(tested > 10);
+        Ok(())
+    }
+}
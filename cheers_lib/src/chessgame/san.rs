@@ -0,0 +1,200 @@
+use std::{error::Error, fmt::Display};
+
+use crate::{
+    moves::{coord, Move},
+    types::{PieceIndex, PieceIndex::*},
+};
+
+use super::ChessGame;
+
+/// Why a SAN move couldn't be matched to a legal move
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SanError {
+    /// Not valid algebraic notation
+    Invalid(String),
+    /// No legal move matches
+    Illegal(String),
+    /// More than one legal move matches
+    Ambiguous(String),
+}
+
+impl Display for SanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SanError::Invalid(san) => write!(f, "Invalid SAN move: {san}"),
+            SanError::Illegal(san) => write!(f, "Illegal move: {san}"),
+            SanError::Ambiguous(san) => write!(f, "Ambiguous move: {san}"),
+        }
+    }
+}
+
+impl Error for SanError {}
+
+impl ChessGame {
+    /// Find the legal move described by `san`, e.g. `Nbd2`, `exd6 e.p.`, `O-O-O` or `fxg8=N+`.
+    /// Check, mate and annotation suffixes are ignored.
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let invalid = || SanError::Invalid(san.to_string());
+        let text = san.trim();
+        let text = text.strip_suffix("e.p.").unwrap_or(text).trim_end();
+        let text = text.trim_end_matches(['+', '#', '!', '?']);
+
+        let legal_moves = self.legal_moves();
+        let candidates: Vec<Move> = match text {
+            "O-O" | "0-0" | "O-O-O" | "0-0-0" => {
+                let kingside = text.len() == 3;
+                legal_moves
+                    .into_iter()
+                    .filter(|m| m.castling() && (m.target().file() > m.start().file()) == kingside)
+                    .collect()
+            }
+            _ => {
+                let (text, promotion) = match text.split_once('=') {
+                    Some((text, p)) => {
+                        let mut chars = p.chars();
+                        let promotion = match (chars.next(), chars.next()) {
+                            (Some(c), None) if c.is_ascii_uppercase() => {
+                                PieceIndex::from_fen_char(c).ok_or_else(invalid)?.1
+                            }
+                            _ => return Err(invalid()),
+                        };
+                        (text, promotion)
+                    }
+                    None => (text, NoPiece),
+                };
+
+                let piece = match text.chars().next() {
+                    Some(c) if c.is_ascii_uppercase() => {
+                        PieceIndex::from_fen_char(c).ok_or_else(invalid)?.1
+                    }
+                    Some(_) => Pawn,
+                    None => return Err(invalid()),
+                };
+                let text = if piece == Pawn { text } else { &text[1..] };
+                if text.len() < 2 || !text.is_ascii() {
+                    return Err(invalid());
+                }
+                let (disambiguation, target) = text.split_at(text.len() - 2);
+                if !is_coord(target) {
+                    return Err(invalid());
+                }
+                let capture = disambiguation.ends_with('x');
+                let disambiguation = disambiguation.strip_suffix('x').unwrap_or(disambiguation);
+                if disambiguation.len() > 2
+                    || !disambiguation
+                        .chars()
+                        .all(|c| matches!(c, 'a'..='h' | '1'..='8'))
+                    || (piece == Pawn && disambiguation.len() != capture as usize)
+                {
+                    return Err(invalid());
+                }
+
+                legal_moves
+                    .into_iter()
+                    .filter(|m| {
+                        let start = coord(m.start());
+                        m.piece() == piece
+                            && coord(m.target()) == target
+                            && m.promotion() == promotion
+                            && m.capture() == capture
+                            && disambiguation.chars().all(|c| start.contains(c))
+                    })
+                    .collect()
+            }
+        };
+
+        match candidates[..] {
+            [m] => Ok(m),
+            [] => Err(SanError::Illegal(san.to_string())),
+            _ => Err(SanError::Ambiguous(san.to_string())),
+        }
+    }
+}
+
+fn is_coord(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(
+        (chars.next(), chars.next()),
+        (Some('a'..='h'), Some('1'..='8'))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(fen: &str, san: &str) -> Result<String, SanError> {
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen).unwrap();
+        game.parse_san(san).map(|m| m.coords())
+    }
+
+    #[test]
+    fn parse_san() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(parse(start, "e4"), Ok("e2e4".into()));
+        assert_eq!(parse(start, "Nf3"), Ok("g1f3".into()));
+        assert_eq!(parse(start, "e5"), Err(SanError::Illegal("e5".into())));
+        assert_eq!(parse(start, "Ke2"), Err(SanError::Illegal("Ke2".into())));
+        for invalid in ["", "Zf3", "e9", "Nf3x", "e4=", "N", "O-O-O-O"] {
+            assert_eq!(
+                parse(start, invalid),
+                Err(SanError::Invalid(invalid.into())),
+                "{invalid}"
+            );
+        }
+
+        let fen = "r3k2r/8/8/3pP3/8/8/8/RN2K2R w KQkq d6 0 1";
+        assert_eq!(parse(fen, "exd6 e.p."), Ok("e5d6".into()));
+        assert_eq!(parse(fen, "exd6"), Ok("e5d6".into()));
+        assert_eq!(parse(fen, "O-O"), Ok("e1g1".into()));
+        assert_eq!(parse(fen, "O-O-O"), Err(SanError::Illegal("O-O-O".into())));
+        assert_eq!(parse(fen, "Nd2"), Ok("b1d2".into()));
+
+        // promotion with check
+        let fen = "5nk1/6P1/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(parse(fen, "gxf8=Q+"), Ok("g7f8q".into()));
+        assert_eq!(parse(fen, "gxf8=N"), Ok("g7f8n".into()));
+        assert_eq!(parse(fen, "gxf8"), Err(SanError::Illegal("gxf8".into())));
+    }
+
+    #[test]
+    fn san_disambiguation() {
+        let fen = "4k3/8/8/8/8/8/4K3/R1N3NR w - - 0 1";
+        assert_eq!(parse(fen, "Ne2"), Err(SanError::Illegal("Ne2".into())));
+        assert_eq!(parse(fen, "Nf3"), Ok("g1f3".into()));
+        assert_eq!(parse(fen, "Nd3"), Ok("c1d3".into()));
+
+        let fen = "4k3/8/8/8/8/8/4K3/R6R w - - 0 1";
+        assert_eq!(parse(fen, "Rd1"), Err(SanError::Ambiguous("Rd1".into())));
+        assert_eq!(parse(fen, "Rad1"), Ok("a1d1".into()));
+        assert_eq!(parse(fen, "Rbd1"), Err(SanError::Illegal("Rbd1".into())));
+
+        let fen = "4k3/8/8/N7/8/8/8/N1N1K3 w - - 0 1";
+        assert_eq!(parse(fen, "Nb3"), Err(SanError::Ambiguous("Nb3".into())));
+        assert_eq!(parse(fen, "Nab3"), Err(SanError::Ambiguous("Nab3".into())));
+        assert_eq!(parse(fen, "N1b3"), Err(SanError::Ambiguous("N1b3".into())));
+        assert_eq!(parse(fen, "N5b3"), Ok("a5b3".into()));
+        assert_eq!(parse(fen, "Ncb3"), Ok("c1b3".into()));
+        assert_eq!(parse(fen, "Na1b3"), Ok("a1b3".into()));
+
+        let fen = "4k3/8/8/8/Q1Q5/8/Q7/4K3 w - - 0 1";
+        assert_eq!(parse(fen, "Qab3"), Err(SanError::Ambiguous("Qab3".into())));
+        assert_eq!(parse(fen, "Q4b3"), Err(SanError::Ambiguous("Q4b3".into())));
+        assert_eq!(parse(fen, "Qa4b3"), Ok("a4b3".into()));
+
+        // the pinned knight can't move, so there is no ambiguity
+        let fen = "4k3/4r3/8/8/8/8/4N1N1/4K3 w - - 0 1";
+        assert_eq!(parse(fen, "Nf4"), Ok("g2f4".into()));
+    }
+
+    #[test]
+    fn san_round_trip() {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1")
+            .unwrap();
+        for move_ in game.legal_moves() {
+            assert_eq!(game.parse_san(&move_.to_san(&game)), Ok(move_));
+        }
+    }
+}
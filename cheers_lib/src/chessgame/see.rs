@@ -8,7 +8,15 @@ use super::ChessGame;
 
 pub const SEE_PIECE_VALUES: [i32; 7] = [100, 300, 300, 500, 900, 20000, 0];
 impl ChessGame {
+    /// Static exchange evaluation of a capture on the target square.
+    ///
+    /// The attackers are generated once, and x-ray attackers behind each capturing piece
+    /// are added as the exchange removes pieces from the board.
     pub fn see(&self, move_: Move) -> i32 {
+        // a Chess960 castling move targets the king's own rook, and can't lose material
+        if move_.castling() {
+            return 0;
+        }
         let target = move_.target();
         let mut swap_list = [0i32; 32];
 
@@ -51,32 +59,30 @@ impl ChessGame {
             // remove the attacker from the masks (perform the capture)
             occupied ^= attacker_mask;
 
-            // consider diagonal x-rays
-            if current_attacker == Pawn || current_attacker == Bishop || current_attacker == Queen {
+            // reveal x-rays behind the piece that just captured
+            if matches!(current_attacker, Pawn | Bishop | Queen | King) {
                 attackers |= lookup_bishop(target, occupied) & bishops;
             }
-            // consider orthogonal x-rays
-            if current_attacker == Rook || current_attacker == Queen {
+            if matches!(current_attacker, Rook | Queen | King) {
                 attackers |= lookup_rook(target, occupied) & rooks;
             }
 
             // remove used attacks
             attackers &= occupied;
-            if attackers.is_empty() {
+            let side_attackers = attackers & self.color_masks[color];
+            if side_attackers.is_empty() {
                 break;
             }
+
+            // recapture with the least valuable piece
             for p in PIECES {
-                if (attackers & self.piece_masks[(color, p)]).is_not_empty() {
+                let pieces = side_attackers & self.piece_masks[(color, p)];
+                if pieces.is_not_empty() {
                     current_attacker = p;
-                    attacker_mask = (attackers & self.piece_masks[(color, p)])
-                        .first_square()
-                        .bitboard();
+                    attacker_mask = pieces.first_square().bitboard();
                     break;
                 }
             }
-            if attacker_mask.is_empty() {
-                break;
-            }
             color = !color;
         }
 
@@ -93,7 +99,51 @@ impl ChessGame {
 mod tests {
     use std::error::Error;
 
-    use crate::{chessgame::ChessGame, moves::Move};
+    use super::SEE_PIECE_VALUES;
+    use crate::{
+        chessgame::ChessGame,
+        moves::Move,
+        types::{PieceIndex::*, PIECES},
+    };
+
+    /// Reference SEE that regenerates every attacker of the square after each capture
+    fn see_naive(game: &ChessGame, move_: Move) -> i32 {
+        let target = move_.target();
+        let mut gains = vec![SEE_PIECE_VALUES[game.piece_at(target)]];
+        let mut occupied = game.combined ^ move_.start().bitboard();
+        if move_.en_passent() {
+            occupied ^= game.en_passent_mask
+                | (game.en_passent_mask >> 8 << (16 * game.current_player as u8));
+            gains[0] = SEE_PIECE_VALUES[Pawn];
+        }
+        let mut on_square = move_.piece();
+        let mut color = !game.current_player;
+
+        loop {
+            let attackers =
+                game.all_attacks_on(target, occupied) & occupied & game.color_masks[color];
+            let Some(piece) = PIECES
+                .into_iter()
+                .find(|&p| (attackers & game.piece_masks[(color, p)]).is_not_empty())
+            else {
+                break;
+            };
+            gains.push(SEE_PIECE_VALUES[on_square] - gains.last().unwrap());
+            occupied ^= (attackers & game.piece_masks[(color, piece)])
+                .first_square()
+                .bitboard();
+            on_square = piece;
+            color = !color;
+        }
+
+        // either side can stop capturing when it is ahead
+        while gains.len() > 1 {
+            let last = gains.pop().unwrap();
+            let previous = gains.last_mut().unwrap();
+            *previous = -(last.max(-*previous));
+        }
+        gains[0]
+    }
 
     #[test]
     fn test_see() -> Result<(), Box<dyn Error>> {
@@ -127,4 +177,50 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn long_exchanges() -> Result<(), Box<dyn Error>> {
+        let test_cases = [
+            // pawns, knights, then queens with rooks x-raying behind them
+            ("3rk3/3q4/4pn2/3p4/4P3/2N5/3Q4/3RK3 w - - 0 1", "e4d5", 0),
+            // doubled rooks on both sides
+            ("3r3k/3r4/8/3p4/8/8/3R4/3R2K1 w - - 0 1", "d2d5", -400),
+            // the queen behind the rooks wins the last exchange
+            ("3r3k/3r4/8/3p4/8/3R4/3R4/3Q2K1 w - - 0 1", "d3d5", 100),
+        ];
+        for (fen, move_, score) in test_cases {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            let move_ = Move::from_pair(&game, move_);
+            assert_eq!(game.see(move_), score);
+            assert_eq!(see_naive(&game, move_), score);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn see_matches_naive() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "3rk3/3q4/4pn2/3p4/4P3/2N5/3Q4/3RK3 w - - 0 1",
+            "r1bq1rk1/pp2bppp/2n1pn2/2pp4/2PP4/2NBPN2/PP3PPP/R1BQ1RK1 w - - 0 1",
+            "2kr4/8/8/8/2pP4/8/3K4/5Q2 b - d3 0 1",
+            "8/8/8/2pk4/3P4/4P3/8/4K3 b - - 0 1",
+        ];
+        for fen in fens {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            for move_ in game.legal_moves() {
+                if game.is_capture(move_) {
+                    assert_eq!(
+                        game.see(move_),
+                        see_naive(&game, move_),
+                        "{fen} {}",
+                        move_.coords()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
 }
@@ -1,8 +1,13 @@
+pub mod book;
 pub mod chessgame;
 pub mod lookup_tables;
 pub mod moves;
+pub mod pgn;
+pub mod search;
+pub mod syzygy;
+pub mod time_manager;
 pub mod transposition_table;
+#[cfg(feature = "eval-tracing")]
+pub mod tuner;
 pub mod types;
 pub mod zobrist;
-pub mod search;
-
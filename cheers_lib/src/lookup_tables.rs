@@ -83,10 +83,20 @@ mod consts {
     pub const SEVENTH_RANK: BitBoard = BitBoard(0x00FF000000000000);
     pub const EIGHTH_RANK: BitBoard = BitBoard(0xFF00000000000000);
 
-    pub const LIGHT_SQUARES: BitBoard = BitBoard(0x5555555555555555);
-    pub const DARK_SQUARES: BitBoard = BitBoard(0xAAAAAAAAAAAAAAAA);
+    pub const LIGHT_SQUARES: BitBoard = BitBoard(0x55AA55AA55AA55AA);
+    pub const DARK_SQUARES: BitBoard = BitBoard(0xAA55AA55AA55AA55);
 
     pub const LONG_DIAGONALS: BitBoard = BitBoard(0x8142241818244281);
+
+    // central files on each side's half of the board
+    pub const WHITE_SPACE: BitBoard = BitBoard(0x000000003C3C3C00);
+    pub const BLACK_SPACE: BitBoard = BitBoard(0x003C3C3C00000000);
+
+    // edge squares deep in the enemy camp where minor pieces get trapped
+    pub const WHITE_TRAP_SQUARES: BitBoard =
+        A_FILE.bitor(H_FILE).bitand(SEVENTH_RANK.bitor(EIGHTH_RANK));
+    pub const BLACK_TRAP_SQUARES: BitBoard =
+        A_FILE.bitor(H_FILE).bitand(FIRST_RANK.bitor(SECOND_RANK));
 }
 pub use self::consts::*;
 
@@ -1,8 +1,12 @@
-use std::{fmt::Display, ops::Index};
+use std::{
+    fmt::Display,
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut, Index},
+};
 
 use crate::{
     chessgame::ChessGame,
-    types::{CastlingRights, PieceIndex, PieceIndex::*},
+    types::{CastlingFiles, CastlingIndex, CastlingRights, ColorIndex, PieceIndex, PieceIndex::*},
 };
 use cheers_bitboards::{BitBoard, Square};
 
@@ -116,7 +120,7 @@ impl Move {
 
     pub fn from_pair(boards: &ChessGame, xy: impl AsRef<str>) -> Self {
         let (x, yp) = xy.as_ref().trim().split_at(2);
-        let mut p = Pawn;
+        let mut p = NoPiece;
 
         let y = if yp.len() == 3 {
             p = match &yp[2..] {
@@ -133,15 +137,24 @@ impl Move {
         let start = square(x).into();
         let target = square(y).into();
         let piece = boards.piece_at(start);
+        let en_passent = piece == Pawn && Some(target) == boards.en_passent_square();
+        // Chess960 castling is written as the king taking its own rook
+        let castling = if boards.chess960() {
+            piece == King
+                && boards.piece_at(target) == Rook
+                && boards.color_at(target) == boards.color_at(start)
+        } else {
+            piece == King && (target).abs_diff(*start) == 2
+        };
         Self::new(
             start,
             target,
             piece,
             p,
-            boards.piece_at(target) != NoPiece,
+            (boards.piece_at(target) != NoPiece && !castling) || en_passent,
             piece == Pawn && (target).abs_diff(*start) == 16,
-            piece == Pawn && Some(target) == boards.en_passent_square(),
-            piece == King && (target).abs_diff(*start) == 2,
+            en_passent,
+            castling,
         )
     }
     pub fn pawn_push(start: Square, target: Square) -> Self {
@@ -237,6 +250,73 @@ impl Move {
     pub fn is_null(&self) -> bool {
         self.start() == self.target()
     }
+
+    /// Standard algebraic notation for the move, e.g. `Nbd7`, `exd6`, `O-O` or `e8=Q#`.
+    /// `game` is the position before the move is played.
+    pub fn to_san(&self, game: &ChessGame) -> String {
+        let mut san = if self.castling() {
+            if self.target().file() > self.start().file() {
+                String::from("O-O")
+            } else {
+                String::from("O-O-O")
+            }
+        } else {
+            let mut san = String::new();
+            let start = coord(self.start());
+            if self.piece() == Pawn {
+                if self.capture() {
+                    san.push_str(&start[..1]);
+                }
+            } else {
+                san.push(self.piece().to_fen_char(ColorIndex::White).unwrap());
+                // other pieces of the same type that can reach the target square
+                let others = game
+                    .legal_moves()
+                    .into_iter()
+                    .filter(|m| {
+                        m.piece() == self.piece()
+                            && m.target() == self.target()
+                            && m.start() != self.start()
+                    })
+                    .collect::<Vec<_>>();
+                if !others.is_empty() {
+                    if others
+                        .iter()
+                        .all(|m| m.start().file() != self.start().file())
+                    {
+                        san.push_str(&start[..1]);
+                    } else if others
+                        .iter()
+                        .all(|m| m.start().rank() != self.start().rank())
+                    {
+                        san.push_str(&start[1..]);
+                    } else {
+                        san.push_str(&start);
+                    }
+                }
+            }
+            if self.capture() {
+                san.push('x');
+            }
+            san.push_str(&coord(self.target()));
+            if let Some(promotion) = self.promotion().to_fen_char(ColorIndex::White) {
+                san.push('=');
+                san.push(promotion);
+            }
+            san
+        };
+
+        let mut after = game.clone();
+        after.make_move(*self);
+        if after.in_check(after.current_player()) {
+            san.push(if after.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        san
+    }
 }
 
 impl Default for Move {
@@ -273,7 +353,11 @@ pub struct UnMove {
     pub en_passent_mask: BitBoard,
     pub castling: bool,
     pub castling_rights: CastlingRights,
-    pub halfmove_clock: u8,
+    pub halfmove_clock: u16,
+    pub fullmove_number: u16,
+    pub pawn_hash: u64,
+    pub checkers: BitBoard,
+    pub pinned: BitBoard,
 }
 
 impl UnMove {
@@ -287,7 +371,11 @@ impl UnMove {
         en_passent_mask: BitBoard,
         castling: bool,
         castling_rights: CastlingRights,
-        halfmove_clock: u8,
+        halfmove_clock: u16,
+        fullmove_number: u16,
+        pawn_hash: u64,
+        checkers: BitBoard,
+        pinned: BitBoard,
     ) -> Self {
         Self {
             start,
@@ -299,8 +387,167 @@ impl UnMove {
             castling,
             castling_rights,
             halfmove_clock,
+            fullmove_number,
+            pawn_hash,
+            checkers,
+            pinned,
+        }
+    }
+}
+
+/// The squares involved in castling for one side of the board
+#[derive(Clone, Copy)]
+pub struct CastlingPath {
+    pub king_start: Square,
+    pub king_target: Square,
+    pub rook_start: Square,
+    pub rook_target: Square,
+    /// squares that must be empty apart from the castling king and rook
+    pub empty: BitBoard,
+    /// squares the king passes through, which must not be attacked
+    pub safe: BitBoard,
+}
+
+/// The kings and rooks start on `files`, and always end up on the same squares as in standard chess
+pub fn castling_path(color: ColorIndex, side: CastlingIndex, files: CastlingFiles) -> CastlingPath {
+    let back_rank = 56 * color as u8;
+    let (king_file, rook_file) = (files.king as u8, files.rooks[side] as u8);
+    let (king_target_file, rook_target_file) = match side {
+        CastlingIndex::Kingside => (6, 5),
+        CastlingIndex::Queenside => (2, 3),
+    };
+    let king_start = Square::from(back_rank + king_file);
+    let king_target = Square::from(back_rank + king_target_file);
+    let rook_start = Square::from(back_rank + rook_file);
+    let rook_target = Square::from(back_rank + rook_target_file);
+
+    // all squares between two squares on a rank, inclusive
+    let span = |a: Square, b: Square| {
+        let (lo, hi) = ((*a).min(*b) as u32, (*a).max(*b) as u32);
+        BitBoard((u64::MAX >> (63 - hi)) & (u64::MAX << lo))
+    };
+
+    let castlers = king_start.bitboard() | rook_start.bitboard();
+    CastlingPath {
+        king_start,
+        king_target,
+        rook_start,
+        rook_target,
+        empty: (span(king_start, king_target) | span(rook_start, rook_target)) & castlers.inverse(),
+        safe: span(king_start, king_target) & king_start.bitboard().inverse(),
+    }
+}
+
+/// Summary of a move's side effects, for UIs that don't track the unmove history
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MoveInfo {
+    pub captured: PieceIndex,
+    pub en_passent: bool,
+    pub castling: bool,
+    pub promotion: PieceIndex,
+    pub check: bool,
+}
+
+/// More than the most legal moves possible in any position
+pub const MAX_MOVES: usize = 256;
+
+/// Fixed capacity list of moves that lives on the stack
+#[derive(Clone, Copy)]
+pub struct MoveList {
+    // only the first `len` moves are initialised, so creating a list is free
+    moves: [MaybeUninit<Move>; MAX_MOVES],
+    len: usize,
+}
+
+impl MoveList {
+    pub fn new() -> Self {
+        Self {
+            moves: [const { MaybeUninit::uninit() }; MAX_MOVES],
+            len: 0,
         }
     }
+
+    #[inline]
+    pub fn push(&mut self, m: Move) {
+        self.moves[self.len].write(m);
+        self.len += 1;
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+
+    #[inline]
+    fn deref(&self) -> &[Move] {
+        unsafe { std::slice::from_raw_parts(self.moves.as_ptr() as *const Move, self.len) }
+    }
+}
+
+impl DerefMut for MoveList {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [Move] {
+        unsafe { std::slice::from_raw_parts_mut(self.moves.as_mut_ptr() as *mut Move, self.len) }
+    }
+}
+
+pub struct MoveListIntoIter {
+    list: MoveList,
+    index: usize,
+}
+
+impl Iterator for MoveListIntoIter {
+    type Item = Move;
+
+    #[inline]
+    fn next(&mut self) -> Option<Move> {
+        let m = self.list.get(self.index).copied();
+        self.index += 1;
+        m
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.list.len().saturating_sub(self.index);
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for MoveListIntoIter {}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = MoveListIntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        MoveListIntoIter {
+            list: self,
+            index: 0,
+        }
+    }
+}
+
+/// Somewhere the move generator can put moves
+pub(crate) trait MoveSink {
+    fn push(&mut self, m: Move);
+}
+
+impl MoveSink for Vec<Move> {
+    #[inline]
+    fn push(&mut self, m: Move) {
+        Vec::push(self, m)
+    }
+}
+
+impl MoveSink for MoveList {
+    #[inline]
+    fn push(&mut self, m: Move) {
+        MoveList::push(self, m)
+    }
 }
 
 pub fn pick_move(move_list: &mut [Move], current_index: usize) {
@@ -322,15 +569,24 @@ impl<const N: usize> KillerMoves<N> {
     pub fn new() -> Self {
         Self([[Move::null(); N]; 128])
     }
-    pub fn push(&mut self, m: Move, ply: usize) {
-        let mut moves = self.0[ply];
+    pub fn push(&mut self, mut m: Move, ply: usize) {
+        // killers are compared without their ordering score
+        m.score = 0;
+        let moves = &mut self.0[ply];
         if !moves.contains(&m) {
-            for i in (1..N).rev() {
-                moves[i] = moves[i - 1];
-            }
+            moves.rotate_right(1);
             moves[0] = m;
         }
     }
+
+    pub fn contains(&self, mut m: Move, ply: usize) -> bool {
+        m.score = 0;
+        self.0[ply].contains(&m)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
 }
 
 impl<const N: usize> Index<usize> for KillerMoves<N> {
@@ -346,3 +602,94 @@ impl<const N: usize> Default for KillerMoves<N> {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+
+    #[test]
+    fn killer_moves() {
+        let mut killers = KillerMoves::<2>::new();
+        let mut first = Move::knight_move(Square::G1, Square::F3, false);
+        let second = Move::knight_move(Square::B1, Square::C3, false);
+        let third = Move::knight_move(Square::G1, Square::H3, false);
+
+        first.score = 500;
+        killers.push(first, 3);
+        assert!(killers.contains(first, 3));
+        first.score = 0;
+        assert!(killers.contains(first, 3));
+        assert!(!killers.contains(first, 4));
+
+        // pushing an existing killer doesn't evict the other slot
+        killers.push(second, 3);
+        killers.push(first, 3);
+        assert!(killers.contains(second, 3));
+
+        // the oldest killer is evicted
+        killers.push(third, 3);
+        assert!(!killers.contains(first, 3));
+        assert!(killers.contains(second, 3) && killers.contains(third, 3));
+
+        killers.clear();
+        assert!(!killers.contains(third, 3));
+    }
+
+    #[test]
+    fn san() -> Result<(), Box<dyn Error>> {
+        let cases = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "g1f3",
+                "Nf3",
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "e2e4",
+                "e4",
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+                "e4d5",
+                "exd5",
+            ),
+            // en passent
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                "e5f6",
+                "exf6",
+            ),
+            // castling
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1", "O-O"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8", "O-O-O"),
+            // check and mate
+            ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8", "Ra8+"),
+            ("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "a1a8", "Ra8#"),
+            ("4k3/4q3/8/8/8/8/4Q3/4K3 w - - 0 1", "e2e7", "Qxe7+"),
+            // promotions
+            ("8/4P1k1/8/8/8/8/8/4K3 w - - 0 1", "e7e8q", "e8=Q"),
+            ("3r2k1/4Pppp/8/8/8/8/8/4K3 w - - 0 1", "e7d8q", "exd8=Q#"),
+            ("8/4P1k1/8/8/8/8/8/4K3 w - - 0 1", "e7e8n", "e8=N+"),
+            // disambiguation by file, rank and both
+            ("4k3/8/8/8/8/8/8/R1N1K1NR w - - 0 1", "g1e2", "Nge2"),
+            ("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "a1d1", "Rad1"),
+            ("4k3/R7/8/8/8/8/8/R3K3 w - - 0 1", "a1a4", "R1a4"),
+            ("4k3/8/8/8/Q1Q5/8/Q7/4K3 w - - 0 1", "a4b3", "Qa4b3"),
+            // a pinned knight doesn't need to be told apart
+            ("4k3/4r3/8/8/8/8/4N1N1/4K3 w - - 0 1", "g2f4", "Nf4"),
+        ];
+        let mut game = ChessGame::new();
+        for (fen, coords, expected) in cases {
+            game.set_from_fen(fen)?;
+            let move_ = game
+                .legal_moves()
+                .into_iter()
+                .find(|m| m.coords() == coords)
+                .ok_or(format!("{coords} is not legal in {fen}"))?;
+            assert_eq!(move_.to_san(&game), expected, "{fen}");
+        }
+        Ok(())
+    }
+}
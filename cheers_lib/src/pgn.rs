@@ -0,0 +1,281 @@
+use std::{error::Error, fmt::Display};
+
+use crate::{
+    chessgame::{ChessGame, GameResult, SanError},
+    moves::Move,
+    types::ColorIndex::*,
+};
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+/// Longest line of exported movetext, as recommended by the PGN standard
+const LINE_LENGTH: usize = 79;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PgnError {
+    /// A comment, variation or tag pair that is never closed
+    Unterminated(char),
+    /// A variation closed without being opened
+    Unmatched(char),
+    /// A move that couldn't be played, with its index in the game
+    Move(usize, SanError),
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgnError::Unterminated(c) => write!(f, "Unterminated '{c}' in PGN"),
+            PgnError::Unmatched(c) => write!(f, "Unmatched '{c}' in PGN"),
+            PgnError::Move(ply, err) => write!(f, "Move {}: {err}", ply + 1),
+        }
+    }
+}
+
+impl Error for PgnError {}
+
+/// Split the movetext into tokens, skipping tag pairs, comments and variations
+fn tokens(pgn: &str) -> Result<Vec<&str>, PgnError> {
+    let mut tokens = Vec::new();
+    let mut token_start = None;
+    let mut variation_depth = 0;
+    let mut closing = None;
+
+    for (i, c) in pgn.char_indices() {
+        if let Some(close) = closing {
+            if c == close {
+                closing = None;
+            }
+            continue;
+        }
+        let separator = c.is_whitespace() || "{;[()".contains(c);
+        if separator {
+            if let Some(start) = token_start.take() {
+                if variation_depth == 0 {
+                    tokens.push(&pgn[start..i]);
+                }
+            }
+        }
+        match c {
+            '{' => closing = Some('}'),
+            ';' => closing = Some('\n'),
+            '[' if variation_depth == 0 => closing = Some(']'),
+            '(' => variation_depth += 1,
+            ')' if variation_depth == 0 => return Err(PgnError::Unmatched(')')),
+            ')' => variation_depth -= 1,
+            _ if separator => {}
+            _ => {
+                token_start.get_or_insert(i);
+            }
+        }
+    }
+    if let Some(start) = token_start {
+        if variation_depth == 0 {
+            tokens.push(&pgn[start..]);
+        }
+    }
+    match (closing, variation_depth) {
+        (None | Some('\n'), 0) => Ok(tokens),
+        (Some('}'), _) => Err(PgnError::Unterminated('{')),
+        (Some(_), _) => Err(PgnError::Unterminated('[')),
+        (None, _) => Err(PgnError::Unterminated('(')),
+    }
+}
+
+/// Play the PGN movetext from `start`, up to the game result
+fn play_pgn(
+    pgn: &str,
+    start: &ChessGame,
+    mut on_move: impl FnMut(Move, &ChessGame),
+) -> Result<(), PgnError> {
+    let mut game = start.clone();
+    let mut ply = 0;
+    for token in tokens(pgn)? {
+        if RESULTS.contains(&token) {
+            break;
+        }
+        if token.starts_with('$') {
+            continue;
+        }
+        // move numbers, which may be attached to the move: `1.e4`, `12...Nf6`. The digits
+        // only count when followed by a dot, `0-0` is castling
+        let number_end = token.trim_start_matches(|c: char| c.is_ascii_digit());
+        let token = match number_end.strip_prefix('.') {
+            Some(rest) => rest.trim_start_matches('.'),
+            None => token,
+        };
+        if token.is_empty() {
+            continue;
+        }
+
+        let move_ = game
+            .parse_san(token)
+            .map_err(|err| PgnError::Move(ply, err))?;
+        game.make_move(move_);
+        on_move(move_, &game);
+        ply += 1;
+    }
+    Ok(())
+}
+
+/// The moves of a game in PGN, played from `start`
+pub fn moves_from_pgn(pgn: &str, start: &ChessGame) -> Result<Vec<Move>, PgnError> {
+    let mut moves = Vec::new();
+    play_pgn(pgn, start, |move_, _| moves.push(move_))?;
+    Ok(moves)
+}
+
+/// The position after each move of a game in PGN, played from `start`
+pub fn positions_from_pgn(pgn: &str, start: &ChessGame) -> Result<Vec<ChessGame>, PgnError> {
+    let mut positions = Vec::new();
+    play_pgn(pgn, start, |_, game| positions.push(game.clone()))?;
+    Ok(positions)
+}
+
+/// PGN movetext for the moves played from the start position, or from `start_fen`.
+/// A game starting from a FEN gets the `SetUp` and `FEN` tags.
+///
+/// Panics if `start_fen` is not a valid FEN.
+pub fn to_pgn(moves: &[Move], start_fen: Option<&str>) -> String {
+    let mut game = ChessGame::new();
+    let mut pgn = String::new();
+    if let Some(fen) = start_fen {
+        game.set_from_fen(fen).expect("invalid start FEN");
+        pgn.push_str(&format!("[SetUp \"1\"]\n[FEN \"{fen}\"]\n\n"));
+    }
+
+    let mut tokens = Vec::new();
+    for (i, &move_) in moves.iter().enumerate() {
+        let move_number = game.fullmove_number();
+        match game.current_player() {
+            White => tokens.push(format!("{move_number}.")),
+            Black if i == 0 => tokens.push(format!("{move_number}...")),
+            Black => {}
+        }
+        tokens.push(move_.to_san(&game));
+        game.make_move(move_);
+    }
+    tokens.push(
+        match game.game_result() {
+            Some(GameResult::WhiteWin) => "1-0",
+            Some(GameResult::BlackWin) => "0-1",
+            Some(GameResult::Draw) => "1/2-1/2",
+            None => "*",
+        }
+        .to_string(),
+    );
+
+    let mut line_length = 0;
+    for token in tokens {
+        if line_length > 0 && line_length + 1 + token.len() > LINE_LENGTH {
+            pgn.push('\n');
+            line_length = 0;
+        } else if line_length > 0 {
+            pgn.push(' ');
+            line_length += 1;
+        }
+        line_length += token.len();
+        pgn.push_str(&token);
+    }
+    pgn.push('\n');
+    pgn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERA_GAME: &str = r#"[Event "Paris"]
+[Site "Paris FRA"]
+[Date "1858.??.??"]
+[White "Paul Morphy"]
+[Black "Duke Karl / Count Isouard"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 d6 3. d4 Bg4 {This is a weak move already.--Fischer} 4. dxe5
+Bxf3 5. Qxf3 dxe5 6. Bc4 Nf6 7. Qb3 Qe7 8. Nc3 c6 9. Bg5 {Black is in what's
+like a zugzwang position here.} b5 $6 10. Nxb5! cxb5 11. Bxb5+ Nbd7 12. O-O-O
+Rd8 13. Rxd7 Rxd7 (13... Nxd7 14. Bxe7 Bxe7 15. Qb8+ Nxb8 16. Rd8#) 14. Rd1 Qe6
+15. Bxd7+ Nxd7 16. Qb8+ Nxb8 17. Rd8# 1-0"#;
+
+    #[test]
+    fn opera_game() -> Result<(), PgnError> {
+        let start = ChessGame::new();
+        let moves = moves_from_pgn(OPERA_GAME, &start)?;
+        assert_eq!(moves.len(), 33);
+        assert_eq!(moves[0].coords(), "e2e4");
+        assert_eq!(moves[22].coords(), "e1c1");
+        assert_eq!(moves[32].coords(), "d1d8");
+
+        let positions = positions_from_pgn(OPERA_GAME, &start)?;
+        assert_eq!(positions.len(), moves.len());
+        assert_eq!(
+            positions[9].fen(),
+            "rn1qkbnr/ppp2ppp/8/4p3/4P3/5Q2/PPP2PPP/RNB1KB1R w KQkq - 0 6"
+        );
+        assert!(positions.last().unwrap().legal_moves().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn movetext() -> Result<(), PgnError> {
+        let start = ChessGame::new();
+        let coords = |pgn| -> Result<Vec<String>, PgnError> {
+            Ok(moves_from_pgn(pgn, &start)?
+                .iter()
+                .map(|m| m.coords())
+                .collect())
+        };
+        assert_eq!(coords("1.e4 e5 2.Nf3")?, ["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(
+            coords("1. e4 ; line comment Nf3\n1... e5 (1... c5 (1... e6)) $1 *")?,
+            ["e2e4", "e7e5"]
+        );
+        // nothing after the result is read
+        assert_eq!(coords("1. d4 d5 1/2-1/2 2. Qxd5")?, ["d2d4", "d7d5"]);
+        assert_eq!(coords("")?, Vec::<String>::new());
+        // castling written with zeros isn't a move number
+        assert_eq!(
+            coords("1. e4 e5 2. Nf3 Nf6 3. Bc4 Bc5 4. 0-0 d6 5.d3 0-0")?,
+            ["e2e4", "e7e5", "g1f3", "g8f6", "f1c4", "f8c5", "e1g1", "d7d6", "d2d3", "e8g8"]
+        );
+
+        assert_eq!(coords("1. e4 {unclosed"), Err(PgnError::Unterminated('{')));
+        assert_eq!(coords("1. e4 (1. d4"), Err(PgnError::Unterminated('(')));
+        assert_eq!(coords("1. e4 1... d4) e5"), Err(PgnError::Unmatched(')')));
+        assert_eq!(coords("1. e4 (1. d4)) e5"), Err(PgnError::Unmatched(')')));
+        assert_eq!(
+            coords("1. e4 e5 2. Ke3"),
+            Err(PgnError::Move(2, SanError::Illegal("Ke3".into())))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pgn_round_trip() -> Result<(), PgnError> {
+        let start = ChessGame::new();
+        let moves = moves_from_pgn(OPERA_GAME, &start)?;
+        let pgn = to_pgn(&moves, None);
+        assert_eq!(
+            pgn,
+            "1. e4 e5 2. Nf3 d6 3. d4 Bg4 4. dxe5 Bxf3 5. Qxf3 dxe5 6. Bc4 Nf6 7. Qb3 Qe7 8.
+Nc3 c6 9. Bg5 b5 10. Nxb5 cxb5 11. Bxb5+ Nbd7 12. O-O-O Rd8 13. Rxd7 Rxd7 14.
+Rd1 Qe6 15. Bxd7+ Nxd7 16. Qb8+ Nxb8 17. Rd8# 1-0
+"
+        );
+        assert!(pgn.lines().all(|line| line.len() <= LINE_LENGTH));
+        assert_eq!(moves_from_pgn(&pgn, &start)?, moves);
+
+        // starting from a position with black to move
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 3 20";
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen).unwrap();
+        let moves = moves_from_pgn("20... O-O 21. Rxa8 Rxa8", &game)?;
+        let pgn = to_pgn(&moves, Some(fen));
+        assert_eq!(
+            pgn,
+            format!("[SetUp \"1\"]\n[FEN \"{fen}\"]\n\n20... O-O 21. Rxa8 Rxa8 *\n")
+        );
+        let movetext = pgn.split("\n\n").nth(1).unwrap();
+        assert_eq!(moves_from_pgn(movetext, &game)?, moves);
+        Ok(())
+    }
+}
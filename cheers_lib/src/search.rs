@@ -1,12 +1,22 @@
-use std::{fmt::Display, sync::atomic::*};
+use std::{
+    fmt::Display,
+    sync::{atomic::*, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "search-trace")]
+use std::{io::Write, path::PathBuf};
 
 use cheers_pregen::LMR;
 
 use crate::moves::{pick_move, KillerMoves};
+use crate::syzygy::{Tablebase, Wdl};
+use crate::time_manager::TimeManager;
 use crate::transposition_table::{NodeType::*, TranspositionTable};
 use crate::{
     chessgame::{
         eval_types::{GamePhase::*, TraceTarget},
+        see::SEE_PIECE_VALUES,
         *,
     },
     moves::Move,
@@ -18,11 +28,92 @@ pub static TIME_ELAPSED: AtomicBool = AtomicBool::new(false);
 pub static SEARCH_COMPLETE: AtomicBool = AtomicBool::new(false);
 pub static NODE_COUNT: AtomicUsize = AtomicUsize::new(0);
 pub static NPS_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Pawn hash table lookups and hits over every finished search, for benchmarking
+pub static PAWN_HASH_PROBES: AtomicUsize = AtomicUsize::new(0);
+pub static PAWN_HASH_HITS: AtomicUsize = AtomicUsize::new(0);
+/// Positions scored by the endgame tablebases in the current search
+pub static TB_HITS: AtomicUsize = AtomicUsize::new(0);
 
 const MINUS_INF: i32 = i32::MIN + 1;
 const INF: i32 = i32::MAX - 1;
 
 pub const PV_MAX_LEN: usize = 16;
+const CURRLINE_INTERVAL: usize = 1 << 20;
+/// Nodes between checks of the search deadline
+const TIME_CHECK_INTERVAL: usize = 1024;
+const PROBCUT_MIN_DEPTH: i32 = 5;
+const PROBCUT_MARGIN: i32 = 200;
+const PROBCUT_REDUCTION: i32 = 4;
+const DELTA_MARGIN: i32 = 200;
+/// Lazy SMP helpers skip the depths `d` where `(d + phase) / size` is odd, with a different
+/// size and phase for each helper, so they spread out over the depths instead of all
+/// searching the same one
+const HELPER_SKIP_SIZE: [usize; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const HELPER_SKIP_PHASE: [usize; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+const NULL_MOVE_REDUCTION: i32 = 2;
+/// Deepest remaining depth at which reverse futility pruning is tried
+const RFP_MAX_DEPTH: i32 = 6;
+/// Static eval margin over beta needed per ply of remaining depth to prune
+const RFP_MARGIN: i32 = 80;
+/// History scores are halved once any of them passes this
+const HISTORY_MAX: i32 = 2_000;
+/// Scores this close to `CHECKMATE_SCORE` are mates
+const MATE_SCORE_RANGE: i32 = 1000;
+const ASPIRATION_MIN_DEPTH: i32 = 4;
+const ASPIRATION_WINDOW: i32 = 25;
+/// Windows wider than this are opened up completely
+const ASPIRATION_MAX_WINDOW: i32 = 1000;
+/// Score of a tablebase win, below the mate scores so a found mate is still preferred
+const TB_WIN_SCORE: i32 = CHECKMATE_SCORE - 2 * MATE_SCORE_RANGE;
+
+/// Whether a score is a forced mate for either side
+pub fn is_mate_score(score: i32) -> bool {
+    (CHECKMATE_SCORE - MATE_SCORE_RANGE + 1..=CHECKMATE_SCORE).contains(&score.saturating_abs())
+}
+
+/// Moves until mate for a mate score, negative when the side to move is getting mated
+pub fn mate_in(score: i32) -> i32 {
+    let plies = CHECKMATE_SCORE - score.abs();
+    if score > 0 {
+        (plies + 1) / 2
+    } else {
+        -(plies + 1) / 2
+    }
+}
+
+/// Format a score for UCI info output as either `cp <x>` or `mate <y>`
+pub fn uci_score(score: i32) -> String {
+    if is_mate_score(score) {
+        format!("mate {}", mate_in(score))
+    } else {
+        format!("cp {score}")
+    }
+}
+
+/// Whether a score is a tablebase win or loss, which count down with the ply like mates
+fn is_tb_score(score: i32) -> bool {
+    (TB_WIN_SCORE - MATE_SCORE_RANGE + 1..=TB_WIN_SCORE).contains(&score.saturating_abs())
+}
+
+/// Mate and tablebase scores are relative to the root during search but stored relative to the
+/// node in the transposition table, so they stay correct when the position is reached at
+/// another ply
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if is_mate_score(score) || is_tb_score(score) {
+        score + score.signum() * ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if is_mate_score(score) || is_tb_score(score) {
+        score - score.signum() * ply as i32
+    } else {
+        score
+    }
+}
+
 #[derive(Copy, Clone, Default, Debug)]
 pub struct PrincipalVariation {
     pub len: usize,
@@ -51,8 +142,62 @@ impl Display for PrincipalVariation {
 pub struct Search {
     pub game: ChessGame,
     transposition_table: TranspositionTable,
+    /// Pawn structure cache, one per search thread
+    pawn_table: PawnHashTable,
+    /// Endgame tablebases, shared between threads
+    tablebase: Option<Arc<Tablebase>>,
     killer_moves: KillerMoves<2>,
-    history_tables: [[[i32; 64]; 6]; 2],
+    /// Quiet cutoff history, indexed by color, start square and target square
+    history_tables: [[[i32; 64]; 64]; 2],
+    /// Quiet move that last caused a cutoff in reply to each move, indexed by the
+    /// replied-to move's start and target square
+    countermoves: [[Option<Move>; 64]; 64],
+    /// Each iteration removes `1 / 2^history_decay` of every history score
+    history_decay: u32,
+    root_moves: Vec<Move>,
+    /// Length of the game's position history when the search started
+    root_history_len: usize,
+    contempt: [i32; 2],
+    refutations: Vec<PrincipalVariation>,
+    current_line: PrincipalVariation,
+    show_refutations: bool,
+    show_currline: bool,
+    analyse_mode: bool,
+    /// Keep searching until stopped, even with a forced result
+    infinite: bool,
+    probcut: bool,
+    reverse_futility: bool,
+    /// Search moves after the first with a null window, see [`Search::pvs`]
+    pvs: bool,
+    /// Search each iteration with a window around the last score
+    aspiration: bool,
+    /// Threads searching in parallel, including this one
+    threads: usize,
+    /// Number of best lines to search and report, each excluding the first moves of the others
+    multi_pv: usize,
+    /// Lazy SMP helper number, 0 for the main search. Helper results are only shared through
+    /// the transposition table
+    helper: usize,
+    /// Nodes searched by all the helpers of the last search
+    helper_nodes: Arc<AtomicUsize>,
+    /// Nodes searched by this instance, unlike the global NODE_COUNT
+    nodes: usize,
+    /// Parameters for the static evaluation, the tuned [`EVAL_PARAMS`] unless replaced
+    eval_params: EvalParams,
+    /// File to write the search tree to, and the maximum ply to record
+    #[cfg(feature = "search-trace")]
+    tree_trace: Option<(PathBuf, usize)>,
+    #[cfg(feature = "search-trace")]
+    tree_nodes: Vec<String>,
+    max_time: Option<Duration>,
+    /// When the clock started, `None` while pondering
+    clock_start: Option<Instant>,
+    deadline: Option<Instant>,
+    timed_out: bool,
+    /// Set by [`Search::stop`], shared between clones of the search
+    stopped: Arc<AtomicBool>,
+    /// Searching on the opponent's time until [`Search::ponderhit`], shared between clones
+    pondering: Arc<AtomicBool>,
     pub max_depth: Option<usize>,
     pub max_nodes: Option<usize>,
     pub max_time_ms: Option<usize>,
@@ -63,10 +208,43 @@ pub struct Search {
 impl Search {
     pub fn new(game: ChessGame) -> Self {
         Self {
+            root_history_len: game.position_history().len(),
             game,
             transposition_table: TranspositionTable::new(0),
+            pawn_table: PawnHashTable::default(),
+            tablebase: None,
             killer_moves: KillerMoves::new(),
-            history_tables: [[[0; 64]; 6]; 2],
+            history_tables: [[[0; 64]; 64]; 2],
+            countermoves: [[None; 64]; 64],
+            history_decay: 2,
+            root_moves: Vec::new(),
+            contempt: [0, 0],
+            refutations: Vec::new(),
+            current_line: PrincipalVariation::new(),
+            show_refutations: false,
+            show_currline: false,
+            analyse_mode: false,
+            infinite: false,
+            probcut: true,
+            reverse_futility: true,
+            pvs: true,
+            aspiration: true,
+            threads: 1,
+            multi_pv: 1,
+            helper: 0,
+            helper_nodes: Arc::new(AtomicUsize::new(0)),
+            nodes: 0,
+            eval_params: EVAL_PARAMS,
+            #[cfg(feature = "search-trace")]
+            tree_trace: None,
+            #[cfg(feature = "search-trace")]
+            tree_nodes: Vec::new(),
+            max_time: None,
+            clock_start: None,
+            deadline: None,
+            timed_out: false,
+            stopped: Arc::new(AtomicBool::new(false)),
+            pondering: Arc::new(AtomicBool::new(false)),
             max_depth: None,
             max_nodes: None,
             max_time_ms: None,
@@ -80,15 +258,73 @@ impl Search {
         self
     }
 
+    /// Share a transposition table between searches
+    pub fn transposition_table(mut self, transposition_table: TranspositionTable) -> Self {
+        self.transposition_table = transposition_table;
+        self
+    }
+
+    /// Probe endgame tablebases in search and keep to the moves that hold the best
+    /// tablebase result at the root
+    pub fn tablebase(mut self, tablebase: Arc<Tablebase>) -> Self {
+        self.tablebase = Some(tablebase);
+        self
+    }
+
     pub fn max_depth(mut self, depth: usize) -> Self {
         self.max_depth = Some(depth);
         self
     }
 
+    /// Stop searching after `max_time`, with the result of the last completed iteration
+    pub fn max_time(mut self, max_time: Duration) -> Self {
+        self.max_time = Some(max_time);
+        self
+    }
+
+    /// Stop a running search of this instance or any clone of it, keeping the result of the last
+    /// completed iteration. Stopping before a search starts ends it after the first iterations.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Search on the opponent's time, expecting the move that led to the position. The clock
+    /// doesn't start and the search doesn't finish on its own until [`Search::ponderhit`]
+    /// confirms the move, after which it goes on as a normal search with everything it found
+    pub fn ponder(mut self, ponder: bool) -> Self {
+        self.pondering = Arc::new(AtomicBool::new(ponder));
+        self
+    }
+
+    /// The opponent played the expected move, so a pondering search of this instance or any
+    /// clone of it starts its clock
+    pub fn ponderhit(&self) {
+        self.pondering.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.pondering.load(Ordering::Relaxed)
+    }
+
+    /// Stop searching after `nodes` nodes, with the result of the last completed iteration
     pub fn max_nodes(mut self, nodes: usize) -> Self {
         self.max_nodes = Some(nodes);
-        unimplemented!("Max nodes is currently unsupported!");
-        #[allow(unreachable_code)]
+        self
+    }
+
+    /// Contempt for draws in the opening and endgame, in centipawns.
+    /// Positive values make the engine avoid draws.
+    pub fn contempt(mut self, opening: i32, endgame: i32) -> Self {
+        self.contempt = [opening, endgame];
+        self
+    }
+
+    /// Evaluate positions with `eval_params` instead of the tuned parameters,
+    /// e.g. to play them against each other
+    pub fn eval_params(mut self, eval_params: EvalParams) -> Self {
+        self.eval_params = eval_params;
+        // cached pawn structure scores belong to the old parameters
+        self.pawn_table.clear();
         self
     }
 
@@ -97,51 +333,600 @@ impl Search {
         self
     }
 
+    /// Report the refutation of each root move after every iteration
+    pub fn show_refutations(mut self, show: bool) -> Self {
+        self.show_refutations = show;
+        self
+    }
+
+    /// Periodically report the line currently being searched
+    pub fn show_currline(mut self, show: bool) -> Self {
+        self.show_currline = show;
+        self
+    }
+
+    /// Analysis mode favours exact results over saving time:
+    /// - contempt is ignored, so draws score as draws
+    /// - the search doesn't stop early after finding a forced mate or draw
+    /// - the time limit isn't shortened when there is only one legal move
+    pub fn analyse_mode(mut self, analyse_mode: bool) -> Self {
+        self.analyse_mode = analyse_mode;
+        self
+    }
+
+    /// Age the history at the start of every iteration by removing `1 / 2^shift` of each
+    /// score, so a shift of 0 clears it
+    pub fn history_decay(mut self, shift: u32) -> Self {
+        self.history_decay = shift.min(31);
+        self
+    }
+
+    pub fn infinite(mut self, infinite: bool) -> Self {
+        self.infinite = infinite;
+        self
+    }
+
+    /// Prune nodes where a good capture beats beta by a margin at reduced depth
+    pub fn probcut(mut self, probcut: bool) -> Self {
+        self.probcut = probcut;
+        self
+    }
+
+    pub fn reverse_futility(mut self, reverse_futility: bool) -> Self {
+        self.reverse_futility = reverse_futility;
+        self
+    }
+
+    /// Principal variation search: moves after the first are searched with a null window
+    /// and only re-searched with the full window when they fail high. Disabling it also
+    /// disables late move reductions, which rely on the null window
+    pub fn pvs(mut self, pvs: bool) -> Self {
+        self.pvs = pvs;
+        self
+    }
+
+    /// Search with `threads` threads sharing the transposition table (Lazy SMP)
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Search the `k` best lines instead of only the best one. The first move of each line is
+    /// excluded from the search for the next, so fewer lines are found with fewer legal moves
+    pub fn multi_pv(mut self, k: usize) -> Self {
+        self.multi_pv = k.max(1);
+        self
+    }
+
+    pub fn aspiration(mut self, aspiration: bool) -> Self {
+        self.aspiration = aspiration;
+        self
+    }
+
+    /// Write every node up to `max_ply` to `path` when the search finishes, one per line as
+    /// `ply depth move alpha beta score node_type`, in the order the nodes were entered
+    #[cfg(feature = "search-trace")]
+    pub fn trace_tree(mut self, path: impl Into<PathBuf>, max_ply: usize) -> Self {
+        self.tree_trace = Some((path.into(), max_ply));
+        self
+    }
+
+    #[cfg(feature = "search-trace")]
+    fn write_tree_trace(&self) -> std::io::Result<()> {
+        if let Some((path, _)) = &self.tree_trace {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            for node in &self.tree_nodes {
+                writeln!(file, "{node}")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_analysing(&self) -> bool {
+        self.analyse_mode
+    }
+
+    /// The line currently being searched, starting from the root
+    pub fn current_line(&self) -> &PrincipalVariation {
+        &self.current_line
+    }
+
+    /// UCI `info refutation` lines for the root moves refuted in the last iteration
+    fn refutation_info(&self) -> Vec<String> {
+        self.refutations
+            .iter()
+            .map(|line| format!("info refutation {line}"))
+            .collect()
+    }
+
     pub fn search(&self) -> (i32, PrincipalVariation) {
-        let mut last_score = i32::MIN;
-        let mut last_pv = PrincipalVariation::new();
+        self.search_lines().swap_remove(0)
+    }
+
+    /// The best lines and their scores, best first, see [`Search::multi_pv`].
+    /// There is always at least one, even without any legal moves
+    pub fn search_lines(&self) -> Vec<(i32, PrincipalVariation)> {
+        if self.max_depth == Some(0) {
+            return vec![self.static_search()];
+        }
+
+        self.transposition_table.new_search();
+        if self.threads <= 1 {
+            return self.iterative_deepening();
+        }
+
+        // Lazy SMP: helpers search the same position without limits, sharing the transposition
+        // table, until the main search finishes
+        let helper_stop = Arc::new(AtomicBool::new(false));
+        self.helper_nodes.store(0, Ordering::Relaxed);
+        thread::scope(|scope| {
+            for i in 1..self.threads {
+                let mut helper = self.clone();
+                helper.helper = i;
+                helper.stopped = helper_stop.clone();
+                helper.output = false;
+                helper.infinite = true;
+                helper.multi_pv = 1;
+                helper.max_depth = None;
+                helper.max_nodes = None;
+                helper.max_time = None;
+                helper.max_time_ms = None;
+                helper.abort_time_ms = None;
+                scope.spawn(move || helper.iterative_deepening());
+            }
+            let result = self.iterative_deepening();
+            helper_stop.store(true, Ordering::Relaxed);
+            result
+        })
+    }
+
+    fn iterative_deepening(&self) -> Vec<(i32, PrincipalVariation)> {
+        let mut last_lines = vec![(i32::MIN, PrincipalVariation::new())];
+        let mut last_depth = 0;
+
+        let mut time_manager = self
+            .max_time_ms
+            .map(|soft| TimeManager::new(soft, self.abort_time_ms.unwrap_or(soft).max(soft)));
 
         let mut search = self.clone();
+        search.killer_moves.clear();
+        search.root_history_len = search.game.position_history().len();
+        search.clock_start = None;
+        search.deadline = None;
+        search.start_clock();
+        // moves that throw away the tablebase result aren't searched at all
+        if let Some(moves) = self
+            .tablebase
+            .as_ref()
+            .and_then(|tablebase| tablebase.root_moves(&search.game))
+        {
+            search.root_moves = moves;
+        }
+        // every line needs its own root move list to exclude moves from
+        if self.multi_pv > 1 && search.root_moves.is_empty() {
+            search.root_moves = search.game.legal_moves();
+        }
         for i in 0.. {
-            let mut pv = PrincipalVariation::new();
-            let score = search.negamax(MINUS_INF, INF, i as i32, 0, Move::null(), &mut pv);
-            if ABORT_SEARCH.load(Ordering::Relaxed) && i > 1 {
+            if self.helper > 0 && i > 1 {
+                let pattern = (self.helper - 1) % HELPER_SKIP_SIZE.len();
+                if ((i + HELPER_SKIP_PHASE[pattern]) / HELPER_SKIP_SIZE[pattern]) % 2 == 1 {
+                    continue;
+                }
+            }
+            search.age_history();
+            let lines = search.search_root_lines(i as i32, &last_lines);
+            if search.interrupted() && i > 1 {
                 // can't trust results from a partial search
                 break;
             }
 
             // we can trust the results from the previous search
             if self.output {
-                println!(
-                    "info depth {i} score cp {score} pv {pv} nodes {}",
-                    NODE_COUNT.load(Ordering::Relaxed)
-                )
+                for (j, (score, pv)) in lines.iter().enumerate() {
+                    let multipv = if self.multi_pv > 1 {
+                        format!(" multipv {}", j + 1)
+                    } else {
+                        String::new()
+                    };
+                    println!(
+                        "info depth {i}{multipv} score {} pv {} nodes {} hashfull {} tbhits {}",
+                        uci_score(*score),
+                        search.extend_pv(pv),
+                        NODE_COUNT.load(Ordering::Relaxed),
+                        self.transposition_table.hashfull(),
+                        TB_HITS.load(Ordering::Relaxed)
+                    );
+                }
+                if self.show_refutations {
+                    for info in search.refutation_info() {
+                        println!("{info}");
+                    }
+                }
             };
 
-            last_pv = pv;
-            last_score = score;
-            // terminate search if we are hinted to do so
-            if TIME_ELAPSED.load(Ordering::Relaxed) && i > 1 {
-                break;
+            let (score, pv) = lines[0];
+            last_lines = lines;
+            last_depth = i;
+            if self.multi_pv <= 1 {
+                search.order_root_moves(pv.moves[0]);
             }
-
-            // terminate search at max depth or with forced mate/draw
-            if let Some(max_depth) = self.max_depth {
-                if i == max_depth {
-                    ABORT_SEARCH.store(false, Ordering::Relaxed);
+            search.start_clock();
+            if let Some(clock_start) = search.clock_start {
+                // terminate search if we are hinted to do so
+                if TIME_ELAPSED.load(Ordering::Relaxed) && i > 1 {
+                    break;
+                }
+                // or if the time manager thinks another iteration isn't worth it
+                if let Some(time_manager) = &mut time_manager {
+                    time_manager.update(pv.moves[0], score);
+                    let elapsed_ms = clock_start.elapsed().as_millis() as usize;
+                    if i > 1 && time_manager.should_stop(elapsed_ms) {
+                        break;
+                    }
+                }
+                if search
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline)
+                {
                     break;
                 }
             }
-            if i > pv.len + 10 && pv.len != PV_MAX_LEN {
+
+            // terminate search at max depth or with forced mate/draw, but a pondering search
+            // can't report its move before the opponent has played
+            let max_depth_reached = self.max_depth.is_some_and(|max_depth| i >= max_depth);
+            let forced_result =
+                !self.analyse_mode && !self.infinite && i > pv.len + 10 && pv.len != PV_MAX_LEN;
+            if max_depth_reached || forced_result {
+                search.wait_for_ponderhit();
                 ABORT_SEARCH.store(false, Ordering::Relaxed);
                 break;
             }
         }
+        // never report an illegal best move, even if the search didn't produce one
+        self.ensure_legal_pv(&mut last_lines[0].1);
+
+        PAWN_HASH_PROBES.fetch_add(search.pawn_table.probes(), Ordering::Relaxed);
+        PAWN_HASH_HITS.fetch_add(search.pawn_table.hits(), Ordering::Relaxed);
+
+        // helpers share one stop flag, so it's left for the main search to reset
+        if self.helper > 0 {
+            self.helper_nodes.fetch_add(search.nodes, Ordering::Relaxed);
+            return last_lines;
+        }
+        self.stopped.store(false, Ordering::Relaxed);
+
+        let (last_score, last_pv) = last_lines[0];
+        search.store_pv(&last_pv, last_score, last_depth as i32);
+        #[cfg(feature = "search-trace")]
+        if let Err(e) = search.write_tree_trace() {
+            eprintln!("Failed to write search trace: {e}");
+        }
+        SEARCH_COMPLETE.store(true, Ordering::Relaxed);
+        last_lines
+    }
+
+    /// Search the root to `depth` for each of the best lines, with the root moves of each
+    /// search excluding the first moves of the lines before it. The root moves end up
+    /// ordered by line, then by score
+    fn search_root_lines(
+        &mut self,
+        depth: i32,
+        last_lines: &[(i32, PrincipalVariation)],
+    ) -> Vec<(i32, PrincipalVariation)> {
+        let last_score = |j: usize| last_lines.get(j).map_or(i32::MIN, |&(score, _)| score);
+        // a depth 0 search is only quiescence, which doesn't use the root moves,
+        // and without any root moves there is only the checkmate or stalemate score
+        if self.multi_pv <= 1 || depth == 0 || self.root_moves.is_empty() {
+            let mut pv = PrincipalVariation::new();
+            let score = self.aspiration_search(depth, last_score(0), &mut pv);
+            return vec![(score, pv)];
+        }
+
+        let mut lines = Vec::with_capacity(self.multi_pv);
+        let mut line_moves = Vec::with_capacity(self.multi_pv);
+        while lines.len() < self.multi_pv && !self.root_moves.is_empty() {
+            let mut pv = PrincipalVariation::new();
+            let score = self.aspiration_search(depth, last_score(lines.len()), &mut pv);
+            if self.interrupted() || pv.len == 0 {
+                // a partial first line is still better than none
+                if lines.is_empty() {
+                    lines.push((score, pv));
+                }
+                break;
+            }
+            self.order_root_moves(pv.moves[0]);
+            line_moves.push(self.root_moves.remove(0));
+            lines.push((score, pv));
+        }
+        line_moves.append(&mut self.root_moves);
+        self.root_moves = line_moves;
+        lines
+    }
+
+    /// Replace an empty PV, or one starting with an illegal move, by the first legal move
+    fn ensure_legal_pv(&self, pv: &mut PrincipalVariation) {
+        let legal_moves = self.game.legal_moves();
+        let pv_legal = pv.len > 0
+            && legal_moves.iter().any(|m| {
+                m.start() == pv.moves[0].start()
+                    && m.target() == pv.moves[0].target()
+                    && m.promotion() == pv.moves[0].promotion()
+            });
+        if !pv_legal {
+            if let Some(&fallback) = legal_moves.first() {
+                if self.output {
+                    println!(
+                        "info string no legal move from search, falling back to {}",
+                        fallback.coords()
+                    );
+                }
+                *pv = PrincipalVariation::new();
+                pv.moves[0] = fallback;
+                pv.len = 1;
+            }
+        }
+    }
+
+    /// Whether the search has been stopped or run out of time or nodes
+    fn interrupted(&self) -> bool {
+        ABORT_SEARCH.load(Ordering::Relaxed)
+            || self.stopped.load(Ordering::Relaxed)
+            || self.timed_out
+            || self.out_of_nodes()
+    }
+
+    /// Static evaluation of the position without searching, not even quiescence.
+    /// The best move is the one with the best static evaluation afterwards.
+    fn static_search(&self) -> (i32, PrincipalVariation) {
+        let (score, _) = self.game.evaluate::<()>();
+        if self.output {
+            println!("info depth 0 score {}", uci_score(score));
+        }
+
+        let mut game = self.game.clone();
+        let mut best_score = MINUS_INF;
+        let mut pv = PrincipalVariation::new();
+        for move_ in self.game.legal_moves() {
+            game.make_move(move_);
+            let (move_score, _) = game.evaluate::<()>();
+            game.unmake_move();
+            if -move_score > best_score {
+                best_score = -move_score;
+                pv.moves[0] = move_;
+                pv.len = 1;
+            }
+        }
         SEARCH_COMPLETE.store(true, Ordering::Relaxed);
-        (last_score, last_pv)
+        (score, pv)
+    }
+
+    /// Write the PV into the transposition table as exact entries,
+    /// so a following search of the expected position starts warm
+    fn store_pv(&mut self, pv: &PrincipalVariation, score: i32, depth: i32) {
+        let mut score = score;
+        for (ply, &move_) in pv.moves.iter().take(pv.len).enumerate() {
+            self.transposition_table.insert(
+                self.game.hash(),
+                move_,
+                (depth - ply as i32).max(0) as i8,
+                score_to_tt(score, ply),
+                Exact,
+                None,
+            );
+            self.game.make_move(move_);
+            score = -score;
+        }
+        for _ in 0..pv.len {
+            self.game.unmake_move();
+        }
+    }
+
+    /// Search the root with a narrow window around the last iteration's score,
+    /// widening it on the side that fails until the score falls inside
+    fn aspiration_search(
+        &mut self,
+        depth: i32,
+        last_score: i32,
+        pv: &mut PrincipalVariation,
+    ) -> i32 {
+        if !self.aspiration || depth < ASPIRATION_MIN_DEPTH || is_mate_score(last_score) {
+            return self.negamax(MINUS_INF, INF, depth, 0, Move::null(), pv);
+        }
+
+        let mut delta = ASPIRATION_WINDOW;
+        let mut alpha = last_score - delta;
+        let mut beta = last_score + delta;
+        loop {
+            let score = self.negamax(alpha, beta, depth, 0, Move::null(), pv);
+            if self.interrupted() {
+                return score;
+            }
+
+            delta *= 2;
+            if score <= alpha {
+                alpha = if delta > ASPIRATION_MAX_WINDOW {
+                    MINUS_INF
+                } else {
+                    (score - delta).max(MINUS_INF)
+                };
+            } else if score >= beta {
+                beta = if delta > ASPIRATION_MAX_WINDOW {
+                    INF
+                } else {
+                    (score + delta).min(INF)
+                };
+            } else {
+                return score;
+            }
+        }
+    }
+
+    /// Reconstruct the principal variation by following best moves in the transposition table
+    /// from the root, stopping at a missing or illegal move or a repeated position
+    pub fn principal_variation(&self) -> Vec<Move> {
+        self.tt_line(self.game.clone(), PV_MAX_LEN)
+    }
+
+    fn tt_line(&self, mut game: ChessGame, max_len: usize) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let mut seen = vec![game.hash()];
+        while moves.len() < max_len {
+            let Some(move_) = self
+                .transposition_table
+                .get(game.hash())
+                .and_then(|entry| entry.best_move(&game))
+            else {
+                break;
+            };
+            game.make_move(move_);
+            if seen.contains(&game.hash()) {
+                break;
+            }
+            seen.push(game.hash());
+            moves.push(move_);
+        }
+        moves
+    }
+
+    /// Fill in the end of a PV cut short by transposition table cutoffs
+    fn extend_pv(&self, pv: &PrincipalVariation) -> PrincipalVariation {
+        let mut game = self.game.clone();
+        for &move_ in &pv.moves[..pv.len] {
+            game.make_move(move_);
+        }
+        let mut extended = *pv;
+        for move_ in self.tt_line(game, PV_MAX_LEN - pv.len) {
+            extended.moves[extended.len] = move_;
+            extended.len += 1;
+        }
+        extended
     }
 
+    /// Sort the root moves by their scores from the last iteration, with the best move first
+    fn order_root_moves(&mut self, best_move: Move) {
+        self.root_moves.sort_by_key(|m| -m.score);
+        if let Some(i) = self.root_moves.iter().position(|m| {
+            m.start() == best_move.start()
+                && m.target() == best_move.target()
+                && m.promotion() == best_move.promotion()
+        }) {
+            self.root_moves[..=i].rotate_right(1);
+        }
+    }
+
+    /// Start the clock and the `max_time` deadline, unless still pondering
+    fn start_clock(&mut self) {
+        if self.clock_start.is_none() && !self.is_pondering() {
+            let now = Instant::now();
+            self.clock_start = Some(now);
+            self.deadline = self.max_time.map(|max_time| now + max_time);
+        }
+    }
+
+    fn wait_for_ponderhit(&self) {
+        while self.is_pondering()
+            && !ABORT_SEARCH.load(Ordering::Relaxed)
+            && !self.stopped.load(Ordering::Relaxed)
+        {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Whether the deadline set by `max_time` has passed, only checking the clock every few nodes
+    fn out_of_time(&mut self) -> bool {
+        if !self.timed_out && self.nodes.is_multiple_of(TIME_CHECK_INTERVAL) {
+            self.start_clock();
+            self.timed_out = self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        }
+        self.timed_out
+    }
+
+    fn out_of_nodes(&self) -> bool {
+        self.max_nodes
+            .is_some_and(|max_nodes| self.nodes >= max_nodes)
+    }
+
+    /// Draw score from the perspective of the side to move, tapered by game phase
+    fn draw_score(&self, ply: usize) -> i32 {
+        if self.analyse_mode {
+            return DRAW_SCORE;
+        }
+        let phase = self.game.game_phase();
+        let contempt =
+            (self.contempt[Midgame] * (256 - phase) + self.contempt[Endgame] * phase) / 256;
+        // the engine is to move on even plies
+        if ply.is_multiple_of(2) {
+            DRAW_SCORE - contempt
+        } else {
+            DRAW_SCORE + contempt
+        }
+    }
+
+    /// Whether the current position should be scored as a repetition draw.
+    /// A position already visited within the search can be repeated again, so a single
+    /// repeat is enough, but positions from the game before the search need a real threefold.
+    fn is_repetition(&self) -> bool {
+        let hash = self.game.hash();
+        let history = self.game.position_history();
+        // only positions since the last capture or pawn move with the same side to move can match
+        let reversible = history
+            .len()
+            .saturating_sub(self.game.halfmove_clock() as usize);
+        let mut game_repeats = 0;
+        for i in (reversible..history.len().saturating_sub(1))
+            .rev()
+            .step_by(2)
+        {
+            if history[i] == hash {
+                if i >= self.root_history_len {
+                    return true;
+                }
+                game_repeats += 1;
+            }
+        }
+        game_repeats >= 2
+    }
+
+    #[inline(always)]
     fn negamax(
+        &mut self,
+        alpha: i32,
+        beta: i32,
+        depth: i32,
+        ply: usize,
+        last_move: Move,
+        pv: &mut PrincipalVariation,
+    ) -> i32 {
+        #[cfg(feature = "search-trace")]
+        if self
+            .tree_trace
+            .as_ref()
+            .is_some_and(|(_, max_ply)| ply < *max_ply)
+        {
+            // reserve the slot first so nodes are written in the order they were entered
+            let index = self.tree_nodes.len();
+            self.tree_nodes.push(String::new());
+            let score = self.negamax_node(alpha, beta, depth, ply, last_move, pv);
+            let node_type = if score >= beta {
+                "LowerBound"
+            } else if score <= alpha {
+                "UpperBound"
+            } else {
+                "Exact"
+            };
+            self.tree_nodes[index] = format!(
+                "{ply} {depth} {} {alpha} {beta} {score} {node_type}",
+                last_move.coords()
+            );
+            return score;
+        }
+        self.negamax_node(alpha, beta, depth, ply, last_move, pv)
+    }
+
+    fn negamax_node(
         &mut self,
         mut alpha: i32,
         beta: i32,
@@ -151,96 +936,207 @@ impl Search {
         pv: &mut PrincipalVariation,
     ) -> i32 {
         // terminate search early
-        if ABORT_SEARCH.load(Ordering::Relaxed) && depth > 1 {
+        if (ABORT_SEARCH.load(Ordering::Relaxed)
+            || self.stopped.load(Ordering::Relaxed)
+            || self.out_of_time()
+            || self.out_of_nodes())
+            && depth > 1
+        {
             return 0;
         }
 
-        // check extension before quiescence
         let in_check = self.game.in_check(self.game.current_player());
+
+        // check 50 move, insufficient material and repetition draws before dropping into
+        // quiescence so repeats at the horizon are seen, the root needs a move regardless
+        if ply != 0
+            && ((self.game.can_claim_fifty_move()
+                // checkmate on the last move still wins
+                && !(in_check && self.game.legal_moves().is_empty()))
+                || self.game.is_insufficient_material()
+                || self.is_repetition())
+        {
+            // exact score so we must reset the pv
+            pv.len = 0;
+            return self.draw_score(ply);
+        }
+
+        // check extension before quiescence
         let depth = if in_check { depth + 1 } else { depth };
 
         // quiescence search at full depth
         if depth == 0 {
             // exact score so we must reset the pv
             pv.len = 0;
-            let score = self.quiesce(alpha, beta, 0, last_move, EVAL_PARAMS);
+            let score = self.quiesce(alpha, beta, 0, last_move, self.eval_params);
             // self.transposition_table
             //     .set(self.hash, Move::null(), depth as i8, score, Exact);
             return score;
         }
 
-        NODE_COUNT.fetch_add(1, Ordering::Relaxed);
+        let nodes = NODE_COUNT.fetch_add(1, Ordering::Relaxed);
         NPS_COUNT.fetch_add(1, Ordering::Relaxed);
+        self.nodes += 1;
 
-        // check 50 move and repetition draws
-        if self.game.halfmove_clock() == 100
-            || self
-                .game
-                .position_history()
-                .iter()
-                .filter(|&&p| p == self.game.hash())
-                .count()
-                == 2
-        {
-            // exact score so we must reset the pv
-            pv.len = 0;
-            return DRAW_SCORE;
+        if self.show_currline && self.output && nodes.is_multiple_of(CURRLINE_INTERVAL) {
+            println!("info currline 1 {}", self.current_line);
         }
 
         let mut line = PrincipalVariation::new();
 
         // transposition table lookup
         let mut tt_move = Move::null();
+        let mut tt_eval = None;
         if let Some(tt_entry) = self.transposition_table.get(self.game.hash()) {
             // prune on exact score/beta cutoff with equal/higher depth, unless we are at the root
+            let tt_score = score_from_tt(tt_entry.score, ply);
             if tt_entry.depth as i32 >= depth
                 && ply != 0
                 && (tt_entry.node_type == Exact
-                    || (tt_entry.node_type == LowerBound && tt_entry.score >= beta)
-                    || (tt_entry.node_type == UpperBound && tt_entry.score <= alpha))
+                    || (tt_entry.node_type == LowerBound && tt_score >= beta)
+                    || (tt_entry.node_type == UpperBound && tt_score <= alpha))
             {
                 // exact score (?) so we must reset the pv
                 pv.len = 0;
-                return tt_entry.score;
-            }
-
-            tt_move = Move::new(
-                tt_entry.move_start,
-                tt_entry.move_target,
-                self.game.piece_at(tt_entry.move_start),
-                tt_entry.promotion,
-                tt_entry.en_passent_capture || self.game.piece_at(tt_entry.move_target) != NoPiece,
-                tt_entry.double_pawn_push,
-                tt_entry.en_passent_capture,
-                tt_entry.castling,
-            );
-        }
+                return tt_score;
+            }
 
-        let pv_node = alpha != beta - 1;
+            // a move from a hash collision is ignored and the moves are ordered as usual
+            tt_move = tt_entry.best_move(&self.game).unwrap_or_default();
+            tt_eval = tt_entry.static_eval;
+        }
+
+        // tablebase results are exact once a capture or pawn move resets the fifty move counter
+        if ply != 0 && self.game.halfmove_clock() == 0 {
+            if let Some(wdl) = self
+                .tablebase
+                .as_ref()
+                .and_then(|tablebase| tablebase.probe_wdl(&self.game))
+            {
+                TB_HITS.fetch_add(1, Ordering::Relaxed);
+                let score = match wdl {
+                    Wdl::Win => TB_WIN_SCORE - ply as i32,
+                    Wdl::Loss => -TB_WIN_SCORE + ply as i32,
+                    // the fifty move rule draws everything else
+                    _ => self.draw_score(ply),
+                };
+                self.transposition_table.set(
+                    self.game.hash(),
+                    Move::null(),
+                    depth as i8,
+                    score_to_tt(score, ply),
+                    Exact,
+                    None,
+                );
+                pv.len = 0;
+                return score;
+            }
+        }
+
+        let pv_node = alpha != beta - 1;
+
+        // Reverse futility pruning
+        if self.reverse_futility && ply != 0 {
+            if let Some(score) =
+                self.reverse_futility_score(beta, depth, pv_node, in_check, tt_eval)
+            {
+                return score;
+            }
+        }
 
         // Null move pruning
-        // don't search the null move when in check or only down to pawn/kings
+        // don't search the null move when in check, in PV nodes, twice in a row
+        // or when only down to pawns/kings where zugzwang is likely
         if depth >= 3
-            && !self.game.in_check(self.game.current_player())
+            && !pv_node
+            && !in_check
+            && ply != 0
+            && !last_move.is_null()
             && self.game.has_non_pawn_material(self.game.current_player())
         {
             self.game.make_null_move();
             let null_score = -self.negamax(
                 -beta,
                 -beta + 1,
-                depth - 3,
+                depth - 1 - NULL_MOVE_REDUCTION,
                 ply + 1,
                 Move::null(),
                 &mut line,
             );
             self.game.unmake_null_move();
 
+            // the null move can't prove a mate, so fail high with beta
             if null_score >= beta {
-                return null_score;
+                return beta;
             }
         }
 
-        let mut moves = self.game.legal_moves();
+        // ProbCut
+        // a capture that beats beta by a margin at reduced depth will very likely beat beta at full depth
+        if self.probcut
+            && !pv_node
+            && !in_check
+            && depth >= PROBCUT_MIN_DEPTH
+            && beta.abs() < CHECKMATE_SCORE - MATE_SCORE_RANGE
+        {
+            let probcut_beta = beta + PROBCUT_MARGIN;
+            let mut captures = self
+                .game
+                .legal_moves()
+                .into_iter()
+                .filter(|&m| m.capture())
+                .map(|mut m| {
+                    m.score = self.game.see(m);
+                    m
+                })
+                .filter(|m| m.score >= 0)
+                .collect::<Vec<_>>();
+            for i in 0..captures.len() {
+                pick_move(&mut captures, i);
+                let move_ = captures[i];
+
+                self.game.make_move(move_);
+                // cheap quiescence check before the reduced search
+                let mut score =
+                    -self.quiesce(-probcut_beta, -probcut_beta + 1, 0, move_, self.eval_params);
+                if score >= probcut_beta {
+                    score = -self.negamax(
+                        -probcut_beta,
+                        -probcut_beta + 1,
+                        depth - PROBCUT_REDUCTION,
+                        ply + 1,
+                        move_,
+                        &mut line,
+                    );
+                }
+                self.game.unmake_move();
+
+                if score >= probcut_beta {
+                    self.transposition_table.set(
+                        self.game.hash(),
+                        move_,
+                        (depth - PROBCUT_REDUCTION + 1) as i8,
+                        score_to_tt(score, ply),
+                        LowerBound,
+                        None,
+                    );
+                    return score;
+                }
+            }
+        }
+
+        // reuse the root move ordering from the previous iteration
+        let reuse_root_moves = ply == 0 && !self.root_moves.is_empty();
+        let mut moves = if reuse_root_moves {
+            // keep the existing order when picking moves
+            let mut moves = self.root_moves.clone();
+            for (i, m) in moves.iter_mut().enumerate() {
+                m.score = -(i as i32);
+            }
+            moves
+        } else {
+            self.game.legal_moves()
+        };
 
         if moves.is_empty() {
             // exact score, so we must reset the pv
@@ -250,38 +1146,18 @@ impl Search {
                 return -(CHECKMATE_SCORE - ply as i32);
             } else {
                 // stalemate
-                return DRAW_SCORE;
+                return self.draw_score(ply);
             }
         }
 
-        moves.iter_mut().for_each(|mut m| {
-            // try the transposition table move early
-            if m.start() == tt_move.start() && m.target() == tt_move.target() {
-                m.score += 100_000;
-            } else if m.capture() {
-                // winning captures first, then equal, then quiets, then losing
-                let see = self.game.see(*m);
-                if see < 0 {
-                    m.score -= 50_000 - see;
-                } else {
-                    m.score += 50_000 + see;
-                }
-            }
-            // order queen and rook promotions ahead of quiet moves
-            else if m.promotion() == Queen || m.promotion() == Rook {
-                m.score += 10_000 + EVAL_PARAMS.piece_values[(Midgame, m.promotion())];
-            } else {
-                // quiet killer moves get sorted before other quiet moves
-                if self.killer_moves[ply.min(127)].contains(&m) {
-                    m.score += 5_000;
-                }
-                // quiet moves get ordered by their history heuristic
-                m.score += self.history_tables[self.game.current_player()][m.piece()]
-                    [*m.target() as usize];
-            }
-        });
+        if !reuse_root_moves {
+            self.score_moves(&mut moves, Some(tt_move), ply, last_move);
+        }
         // make sure the reported best move is at least legal
         let mut best_move = *moves.first().unwrap();
+        if ply == 0 {
+            self.refutations.clear();
+        }
 
         for i in 0..moves.len() {
             pick_move(&mut moves, i);
@@ -297,15 +1173,29 @@ impl Search {
             }
 
             self.game.make_move(move_);
+            if ply < PV_MAX_LEN {
+                self.current_line.moves[ply] = move_;
+                self.current_line.len = ply + 1;
+            }
+            line.len = 0;
             let mut score = MINUS_INF;
             // reduced-depth null-window search on most moves outside of PV nodes
-            let full_depth = if depth > 2 && i > 0 && ply != 0 {
+            let full_depth = if !self.pvs {
+                false
+            } else if depth > 2 && i > 0 && ply != 0 {
                 // reductions and extensions
                 let reduction = {
                     let mut r = 0;
 
-                    // Late Move Reduction (LMR)
-                    if !move_.capture() && move_.promotion() != Queen && !in_check {
+                    // Late Move Reduction (LMR) on quiet moves,
+                    // leaving killers and moves that give check at full depth
+                    let gives_check = self.game.in_check(self.game.current_player());
+                    if !move_.capture()
+                        && move_.promotion() == NoPiece
+                        && !in_check
+                        && !gives_check
+                        && !self.killer_moves.contains(move_, ply.min(127))
+                    {
                         r += LMR[(depth as usize).min(31)][i.min(31)]
                     }
 
@@ -324,36 +1214,52 @@ impl Search {
                 score = -self.negamax(-alpha - 1, -alpha, depth - 1, ply + 1, move_, &mut line);
             }
 
-            // full-depth, full-window search on first move in PV nodes and reduced moves that improve alpha
-            if pv_node && (i == 0 || (score > alpha && score < beta)) {
+            // full-depth, full-window search on first move in PV nodes and reduced moves that improve alpha,
+            // or on every move without PVS
+            if !self.pvs || (pv_node && (i == 0 || (score > alpha && score < beta))) {
                 score = -self.negamax(-beta, -alpha, depth - 1, ply + 1, move_, &mut line);
             }
 
             self.game.unmake_move();
+            self.current_line.len = ply.min(PV_MAX_LEN);
+            if ply == 0 {
+                moves[i].score = score;
+                // the reply that made this move fail low refutes it
+                if score <= alpha && line.len > 0 {
+                    let mut refutation = PrincipalVariation::new();
+                    refutation.moves[0] = move_;
+                    refutation.moves[1] = line.moves[0];
+                    refutation.len = 2;
+                    self.refutations.push(refutation);
+                }
+            }
             if score >= beta {
+                if ply == 0 {
+                    // the moves after the cutoff were never searched, their pick scores
+                    // can't be ranked against search scores so they stay at the back
+                    for m in &mut moves[i + 1..] {
+                        m.score = MINUS_INF;
+                    }
+                    self.root_moves = moves;
+                }
                 self.transposition_table.set(
                     self.game.hash(),
                     move_,
                     depth as i8,
-                    beta,
+                    score_to_tt(beta, ply),
                     LowerBound,
+                    None,
                 );
                 if !move_.capture() {
-                    self.history_tables[self.game.current_player()][move_.piece()]
-                        [*move_.target() as usize] += depth * depth;
-                    if self.history_tables[self.game.current_player()][move_.piece()]
-                        [move_.target()]
-                        > 2_000
-                    {
-                        self.history_tables[self.game.current_player()]
-                            .iter_mut()
-                            .flatten()
-                            .for_each(|h| *h >>= 1);
-                    }
+                    self.update_history(move_, depth);
                     if move_.promotion() == NoPiece {
                         self.killer_moves.push(move_, ply.min(127));
+                        self.update_countermove(last_move, move_);
                     }
                 }
+                // the cutoff move refutes the parent's move
+                pv.moves[0] = move_;
+                pv.len = 1;
                 return score;
             }
             if score > alpha {
@@ -366,11 +1272,118 @@ impl Search {
                 best_move = move_;
             }
         }
-        self.transposition_table
-            .set(self.game.hash(), best_move, depth as i8, alpha, UpperBound);
+        if ply == 0 {
+            self.root_moves = moves;
+        }
+        self.transposition_table.set(
+            self.game.hash(),
+            best_move,
+            depth as i8,
+            score_to_tt(alpha, ply),
+            UpperBound,
+            None,
+        );
         alpha
     }
 
+    /// Reverse futility pruning: a shallow node whose static eval beats beta by a margin for each
+    /// ply of remaining depth is very unlikely to fall below beta, so it fails high with the
+    /// static eval. Never used in PV nodes or in check, where the static eval can't be trusted
+    fn reverse_futility_score(
+        &mut self,
+        beta: i32,
+        depth: i32,
+        pv_node: bool,
+        in_check: bool,
+        static_eval: Option<i32>,
+    ) -> Option<i32> {
+        if depth > RFP_MAX_DEPTH || pv_node || in_check || is_mate_score(beta) {
+            return None;
+        }
+        let static_eval = static_eval.unwrap_or_else(|| {
+            self.game
+                .evaluate_with_pawn_table::<()>(&mut self.pawn_table, &self.eval_params)
+                .0
+        });
+        (static_eval - RFP_MARGIN * depth >= beta).then_some(static_eval)
+    }
+
+    fn history(&self, move_: Move) -> i32 {
+        self.history_tables[self.game.current_player()][*move_.start() as usize]
+            [*move_.target() as usize]
+    }
+
+    /// Reward a quiet move that caused a beta cutoff
+    fn update_history(&mut self, move_: Move, depth: i32) {
+        let table = &mut self.history_tables[self.game.current_player()];
+        let entry = &mut table[*move_.start() as usize][*move_.target() as usize];
+        *entry += depth * depth;
+        if *entry > HISTORY_MAX {
+            table.iter_mut().flatten().for_each(|h| *h >>= 1);
+        }
+    }
+
+    /// Whether `move_` is the last quiet move that refuted `last_move`
+    fn is_countermove(&self, mut move_: Move, last_move: Move) -> bool {
+        // countermoves are compared without their ordering score
+        move_.score = 0;
+        self.countermoves[*last_move.start() as usize][*last_move.target() as usize] == Some(move_)
+    }
+
+    fn update_countermove(&mut self, last_move: Move, mut move_: Move) {
+        if last_move.is_null() {
+            return;
+        }
+        move_.score = 0;
+        self.countermoves[*last_move.start() as usize][*last_move.target() as usize] = Some(move_);
+    }
+
+    fn age_history(&mut self) {
+        let decay = self.history_decay;
+        self.history_tables
+            .iter_mut()
+            .flatten()
+            .flatten()
+            .for_each(|h| *h -= *h >> decay);
+    }
+
+    /// Score moves for ordering: the TT move first, then winning and equal captures by
+    /// MVV-LVA, promotions, killers, the countermove to `last_move` and quiets by history,
+    /// and losing captures last
+    fn score_moves(&self, moves: &mut [Move], tt_move: Option<Move>, ply: usize, last_move: Move) {
+        for m in moves.iter_mut() {
+            if Some(*m) == tt_move {
+                m.score += 100_000;
+            } else if m.capture() {
+                let see = self.game.see(*m);
+                if see < 0 {
+                    m.score -= 50_000 - see;
+                } else {
+                    let victim = if m.en_passent() {
+                        Pawn
+                    } else {
+                        self.game.piece_at(m.target())
+                    };
+                    // most valuable victim, then least valuable attacker
+                    m.score += 50_000 + 10 * SEE_PIECE_VALUES[victim] - m.piece() as i32;
+                }
+            }
+            // order queen and rook promotions ahead of quiet moves
+            else if m.promotion() == Queen || m.promotion() == Rook {
+                m.score += 10_000 + EVAL_PARAMS.piece_values[(Midgame, m.promotion())];
+            } else {
+                // quiet killer moves get sorted before other quiet moves
+                if self.killer_moves.contains(*m, ply.min(127)) {
+                    m.score += 5_000;
+                } else if self.is_countermove(*m, last_move) {
+                    m.score += 4_000;
+                }
+                // quiet moves get ordered by their history heuristic
+                m.score += self.history(*m);
+            }
+        }
+    }
+
     pub fn quiesce(
         &mut self,
         alpha: i32,
@@ -393,8 +1406,23 @@ impl Search {
     ) -> (i32, T) {
         NODE_COUNT.fetch_add(1, Ordering::Relaxed);
         NPS_COUNT.fetch_add(1, Ordering::Relaxed);
+        self.nodes += 1;
 
-        let (stand_pat_score, mut best_trace) = self.game.evaluate::<T>();
+        // TT isn't used in tracing eval
+        let tt_entry = if T::TRACING {
+            None
+        } else {
+            self.transposition_table.get(self.game.hash())
+        };
+
+        // reuse a cached static eval if there is one
+        let (stand_pat_score, mut best_trace) = match tt_entry.as_ref().and_then(|e| e.static_eval)
+        {
+            Some(eval) => (eval, T::default()),
+            None => self
+                .game
+                .evaluate_with_pawn_table::<T>(&mut self.pawn_table, &eval_params),
+        };
 
         if stand_pat_score >= beta {
             return (beta, best_trace);
@@ -403,49 +1431,50 @@ impl Search {
 
         // transposition table lookup
         let mut tt_move = Move::null();
-        if !T::TRACING {
-            if let Some(tt_entry) = self.transposition_table.get(self.game.hash()) {
-                if tt_entry.depth as i32 >= depth
-                    && (tt_entry.node_type == Exact
-                        || (tt_entry.node_type == LowerBound && tt_entry.score >= beta)
-                        || (tt_entry.node_type == UpperBound && tt_entry.score <= alpha))
-                {
-                    // TT isn't used in tracing eval so we can return a blank trace
-                    return (tt_entry.score, T::default());
-                }
-                tt_move = Move::new(
-                    tt_entry.move_start,
-                    tt_entry.move_target,
-                    self.game.piece_at(tt_entry.move_start),
-                    tt_entry.promotion,
-                    tt_entry.en_passent_capture
-                        || self.game.piece_at(tt_entry.move_target) != NoPiece,
-                    tt_entry.double_pawn_push,
-                    tt_entry.en_passent_capture,
-                    tt_entry.castling,
-                );
+        if let Some(tt_entry) = tt_entry {
+            // mate and tablebase scores are stored relative to the node, which isn't known here
+            if tt_entry.depth as i32 >= depth
+                && !is_mate_score(tt_entry.score)
+                && !is_tb_score(tt_entry.score)
+                && (tt_entry.node_type == Exact
+                    || (tt_entry.node_type == LowerBound && tt_entry.score >= beta)
+                    || (tt_entry.node_type == UpperBound && tt_entry.score <= alpha))
+            {
+                return (tt_entry.score, T::default());
             }
+            tt_move = tt_entry.best_move(&self.game).unwrap_or_default();
         }
-        let mut moves: Vec<Move> = self
-            .game
-            .legal_moves()
+        let in_check = self.game.in_check(self.game.current_player());
+        let mut moves = Vec::with_capacity(32);
+        self.game.generate_captures(&mut moves);
+        let mut moves: Vec<Move> = moves
             .into_iter()
             .filter(|m| m.capture())
-            .map(|mut m| {
-                // try the transposition table move early
-                if m.start() == tt_move.start() && m.target() == tt_move.target() {
-                    m.score += 10_000;
+            // delta pruning: skip captures that can't raise alpha even with a margin
+            .filter(|m| {
+                let captured = if m.en_passent() {
+                    Pawn
+                } else {
+                    self.game.piece_at(m.target())
+                };
+                in_check
+                    || m.promotion() != NoPiece
+                    || stand_pat_score + SEE_PIECE_VALUES[captured] + DELTA_MARGIN > alpha
+            })
+            .filter_map(|mut m| {
+                // captures that lose material can't improve on standing pat
+                let see = self.game.see(m);
+                if see < 0 && !in_check {
+                    return None;
                 }
 
-                let see = self.game.see(m);
-                if see < 0 {
-                    m.score -= 2000 - see
-                } else {
-                    m.score += 2000 + see;
+                // try the transposition table move early
+                if m == tt_move {
+                    m.score += 10_000;
                 }
-                m
+                m.score += 2000 + see;
+                Some(m)
             })
-            .filter(|m| m.score > 0)
             .collect();
 
         let mut best_move = Move::null();
@@ -459,13 +1488,15 @@ impl Search {
             score = -score;
             self.game.unmake_move();
             if score >= beta {
-                if !T::TRACING {
+                // mate and tablebase scores can't be made relative to the node without the ply
+                if !T::TRACING && !is_mate_score(beta) && !is_tb_score(beta) {
                     self.transposition_table.set(
                         self.game.hash(),
                         move_,
                         depth as i8,
                         beta,
                         LowerBound,
+                        Some(stand_pat_score),
                     );
                 }
                 return (beta, trace);
@@ -476,15 +1507,898 @@ impl Search {
                 best_move = move_;
             }
         }
-        if !T::TRACING {
+        if !T::TRACING && !is_mate_score(alpha) && !is_tb_score(alpha) {
             self.transposition_table.set(
                 self.game.hash(),
                 best_move,
                 depth as i8,
                 alpha,
                 UpperBound,
+                Some(stand_pat_score),
             );
         }
         (alpha, best_trace)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+    use cheers_bitboards::Square;
+
+    #[test]
+    fn root_move_ordering() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let mut search = Search::new(game).tt_size_mb(1);
+
+        let mut last_best = None;
+        for depth in 1..=5 {
+            let mut pv = PrincipalVariation::new();
+            search.negamax(MINUS_INF, INF, depth, 0, Move::null(), &mut pv);
+
+            // the first move searched at this depth is the previous iteration's best move
+            if let Some(last_best) = last_best {
+                assert_eq!(search.root_moves[0].coords(), last_best);
+            }
+            search.order_root_moves(pv.moves[0]);
+            assert_eq!(search.root_moves[0].coords(), pv.moves[0].coords());
+            last_best = Some(pv.moves[0].coords());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn root_cutoff_ordering() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let mut search = Search::new(game).tt_size_mb(1);
+
+        let mut pv = PrincipalVariation::new();
+        search.negamax(MINUS_INF, INF, 3, 0, Move::null(), &mut pv);
+        search.order_root_moves(pv.moves[0]);
+        let order: Vec<_> = search.root_moves.iter().map(|m| m.coords()).collect();
+
+        // a beta far below the real score cuts off after the first move
+        let mut pv = PrincipalVariation::new();
+        search.negamax(MINUS_INF, -10000, 3, 0, Move::null(), &mut pv);
+        assert!(search.root_moves[1..].iter().all(|m| m.score == MINUS_INF));
+
+        // the unsearched moves keep their order behind the searched one
+        search.order_root_moves(pv.moves[0]);
+        let reordered: Vec<_> = search.root_moves.iter().map(|m| m.coords()).collect();
+        assert_eq!(order, reordered);
+        Ok(())
+    }
+
+    #[test]
+    fn quiescence_mate_window() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/8/3q4/4P3/8/8/4K3 w - - 0 1")?;
+        let mut search = Search::new(game.clone()).tt_size_mb(1);
+
+        // failing low against a mate score stores nothing, the score depends on the ply
+        let alpha = CHECKMATE_SCORE - 10;
+        assert_eq!(
+            search.quiesce(alpha, alpha + 1, 0, Move::null(), EVAL_PARAMS),
+            alpha
+        );
+        assert!(search.transposition_table.get(game.hash()).is_none());
+
+        // an ordinary window is stored
+        search.quiesce(-100, 100, 0, Move::null(), EVAL_PARAMS);
+        assert!(search.transposition_table.get(game.hash()).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn quiescence() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        // the hanging queen is picked up instead of standing pat
+        game.set_from_fen("4k3/8/8/3q4/4P3/8/8/4K3 w - - 0 1")?;
+        let static_eval = game.evaluate::<()>().0;
+        let mut search = Search::new(game).tt_size_mb(1);
+        let score = search.quiesce(MINUS_INF, INF, 0, Move::null(), EVAL_PARAMS);
+        assert!(score > static_eval + 700);
+
+        // a pawn capture can't bring a queen down side back to alpha
+        let mut game = ChessGame::new();
+        game.set_from_fen("3qk3/8/8/3p4/4P3/8/8/4K3 w - - 0 1")?;
+        let mut search = Search::new(game).tt_size_mb(1);
+        let score = search.quiesce(-100, 100, 0, Move::null(), EVAL_PARAMS);
+        assert_eq!((score, search.nodes), (-100, 1));
+
+        // a rook taking a defended pawn loses material and isn't searched
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/3p4/2p5/8/8/2R5/4K3 w - - 0 1")?;
+        let mut search = Search::new(game).tt_size_mb(1);
+        search.quiesce(MINUS_INF, INF, 0, Move::null(), EVAL_PARAMS);
+        assert_eq!(search.nodes, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn mvv_lva_ordering() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/2r1q3/3P4/8/1b2N3/Q7/4K3 w - - 0 1")?;
+        let search = Search::new(game.clone());
+        let mut moves = game.legal_moves();
+        search.score_moves(&mut moves, None, 0, Move::null());
+        moves.sort_by_key(|m| -m.score);
+        let order: Vec<String> = moves.iter().take(3).map(|m| m.coords()).collect();
+        assert_eq!(order, ["d5e6", "d5c6", "a2b3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn history_ordering() {
+        let game = ChessGame::new();
+        let mut search = Search::new(game.clone()).history_decay(1);
+        let cutoff = Move::from_pair(&game, "b2b3");
+        for _ in 0..3 {
+            search.update_history(cutoff, 4);
+        }
+
+        let mut moves = game.legal_moves();
+        search.score_moves(&mut moves, None, 0, Move::null());
+        moves.sort_by_key(|m| -m.score);
+        assert_eq!(moves[0].coords(), "b2b3");
+        assert_eq!(search.history(cutoff), 48);
+
+        // scores are halved rather than saturating
+        for _ in 0..200 {
+            search.update_history(cutoff, 4);
+        }
+        assert!(search.history(cutoff) <= HISTORY_MAX);
+
+        search.age_history();
+        assert!(search.history(cutoff) <= HISTORY_MAX / 2);
+        search.history_decay = 0;
+        search.age_history();
+        assert_eq!(search.history(cutoff), 0);
+    }
+
+    #[test]
+    fn countermove_ordering() {
+        let mut game = ChessGame::new();
+        let e4 = Move::from_pair(&game, "e2e4");
+        game.make_move(e4);
+        let mut search = Search::new(game.clone());
+        let reply = Move::from_pair(&game, "c7c5");
+        search.update_countermove(e4, reply);
+        assert!(search.is_countermove(reply, e4));
+        assert!(!search.is_countermove(reply, Move::from_pair(&ChessGame::new(), "d2d4")));
+
+        let mut moves = game.legal_moves();
+        search.score_moves(&mut moves, None, 0, e4);
+        moves.sort_by_key(|m| -m.score);
+        assert_eq!(moves[0].coords(), "c7c5");
+
+        // killers still come first
+        let killer = Move::from_pair(&game, "e7e5");
+        search.killer_moves.push(killer, 0);
+        let mut moves = game.legal_moves();
+        search.score_moves(&mut moves, None, 0, e4);
+        moves.sort_by_key(|m| -m.score);
+        assert_eq!(moves[0].coords(), "e7e5");
+        assert_eq!(moves[1].coords(), "c7c5");
+
+        // there's nothing to reply to after a null move
+        search.update_countermove(Move::null(), killer);
+        assert!(!search.is_countermove(killer, Move::null()));
+    }
+
+    #[test]
+    fn null_move_zugzwang() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        // mutual zugzwang around the d-pawns, only the spare h-pawn tempo wins
+        game.set_from_fen("8/8/8/2Kp4/3Pk3/8/7P/8 w - - 0 1")?;
+        assert!(!game.has_non_pawn_material(game.current_player()));
+        let search = Search::new(game).tt_size_mb(1).max_depth(10);
+        let (score, pv) = search.search();
+        assert!(["h2h3", "h2h4"].contains(&pv.moves[0].coords().as_str()));
+        assert!(score > 100);
+        Ok(())
+    }
+
+    #[test]
+    fn pvs_matches_full_window() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+            "2r3k1/pp3ppp/4p3/3pP3/3P4/P3B3/1P3PPP/2R3K1 w - - 0 25",
+            "8/8/p1p5/1p5p/1P5p/8/PPP2K1p/4R1rk w - - 0 1",
+        ];
+        for fen in fens {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            for depth in 1..=3 {
+                // ProbCut and reverse futility pruning only run in null-window nodes, which the
+                // full-window search doesn't have, so they are the one intended difference
+                let search = |pvs| {
+                    let mut search = Search::new(game.clone())
+                        .tt_size_mb(1)
+                        .probcut(false)
+                        .reverse_futility(false)
+                        .pvs(pvs);
+                    let mut pv = PrincipalVariation::new();
+                    search.negamax(MINUS_INF, INF, depth, 0, Move::null(), &mut pv)
+                };
+                assert_eq!(search(true), search(false), "{fen} depth {depth}");
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tactics() -> Result<(), Box<dyn Error>> {
+        // positions from Win at Chess, which reductions must not hide
+        let positions = [
+            (
+                "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1",
+                "g3g6",
+            ),
+            (
+                "r1bq2rk/pp3pbp/2p1p1pQ/7P/3P4/2PB1N2/PP3PP1/R3K2R w KQ - 0 1",
+                "h6h7",
+            ),
+            (
+                "rnbqkb1r/pppp1ppp/8/4P3/6n1/7P/PPPNPPP1/R1BQKBNR b KQkq - 0 1",
+                "g4e3",
+            ),
+            (
+                "r4q1k/p2bR1rp/2p2Q1N/5p2/5p2/2P5/PP3PPP/R5K1 w - - 0 1",
+                "e7f7",
+            ),
+        ];
+        for (fen, best_move) in positions {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            let search = Search::new(game).tt_size_mb(1).max_depth(6);
+            let (_, pv) = search.search();
+            assert_eq!(pv.moves[0].coords(), best_move, "{fen}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn principal_variation_from_tt() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let search = Search::new(game.clone()).tt_size_mb(1).max_depth(6);
+        let (_, pv) = search.search();
+
+        // the searched PV is written to the TT, so the walk reproduces it
+        let line = search.principal_variation();
+        assert!(line.len() >= pv.len);
+        for (move_, pv_move) in line.iter().zip(&pv.moves[..pv.len]) {
+            assert_eq!(move_.coords(), pv_move.coords());
+        }
+        for move_ in line {
+            assert!(game.is_legal(move_));
+            game.make_move(move_);
+        }
+
+        // an empty table has no PV
+        search.transposition_table.clear();
+        assert!(search.principal_variation().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn phase_contempt() -> Result<(), Box<dyn Error>> {
+        let search = Search::new(ChessGame::new()).contempt(50, 0);
+        assert_eq!(search.draw_score(0), -50);
+        assert_eq!(search.draw_score(1), 50);
+
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/pppp4/8/8/8/8/4PPPP/4K3 w - - 0 1")?;
+        let search = Search::new(game).contempt(50, 0);
+        assert_eq!(search.draw_score(0), DRAW_SCORE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn contempt_avoids_repetition() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        for coords in "g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8".split(' ') {
+            game.make_move(Move::from_pair(&game, coords));
+        }
+
+        // Nf3 reaches the same position for the third time, which the engine takes
+        // when a draw is welcome and avoids when it is playing for a win
+        let best_move = |contempt: i32| {
+            let (_, pv) = Search::new(game.clone())
+                .tt_size_mb(1)
+                .max_depth(4)
+                .contempt(contempt, contempt)
+                .search();
+            pv.moves[0].coords()
+        };
+        assert_eq!(best_move(-100), "g1f3");
+        assert_ne!(best_move(100), "g1f3");
+        Ok(())
+    }
+
+    #[test]
+    fn multi_pv() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        // taking the queen is better than taking the rook, which is better than anything else
+        game.set_from_fen("4k3/8/8/3q1r2/4P3/8/8/4K3 w - - 0 1")?;
+        let lines = Search::new(game)
+            .tt_size_mb(1)
+            .max_depth(4)
+            .multi_pv(3)
+            .search_lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].1.moves[0].coords(), "e4d5");
+        assert_eq!(lines[1].1.moves[0].coords(), "e4f5");
+        assert!(lines[0].0 > lines[1].0 && lines[1].0 > lines[2].0);
+
+        // only one legal move, so only one line
+        let mut game = ChessGame::new();
+        game.set_from_fen("7k/8/8/8/8/8/5q2/K7 w - - 0 1")?;
+        let lines = Search::new(game)
+            .tt_size_mb(1)
+            .max_depth(4)
+            .multi_pv(3)
+            .search_lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].1.moves[0].coords(), "a1b1");
+        Ok(())
+    }
+
+    #[test]
+    fn refutations() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/4p3/8/8/8/8/3QK3 w - - 0 1")?;
+        let mut search = Search::new(game).tt_size_mb(1).show_refutations(true);
+
+        let mut pv = PrincipalVariation::new();
+        search.negamax(MINUS_INF, INF, 3, 0, Move::null(), &mut pv);
+
+        let info = search.refutation_info();
+        assert!(info.iter().all(|l| l.starts_with("info refutation ")));
+        // hanging the queen is refuted by taking it
+        assert!(info.contains(&String::from("info refutation d1d5 e6d5")));
+        Ok(())
+    }
+
+    #[test]
+    fn pv_stored_in_tt() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let search = Search::new(game.clone()).tt_size_mb(1).max_depth(5);
+        let (_, pv) = search.search();
+        assert!(pv.len > 0);
+
+        for &move_ in pv.moves.iter().take(pv.len) {
+            let entry = search
+                .transposition_table
+                .get(game.hash())
+                .ok_or("PV position missing from TT")?;
+            assert!(entry.node_type == Exact);
+            assert!(entry.move_start == move_.start() && entry.move_target == move_.target());
+            game.make_move(move_);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn analyse_mode_ignores_contempt() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        // black is stalemated
+        game.set_from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1")?;
+
+        let search = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_depth(2)
+            .contempt(50, 50);
+        assert_eq!(search.search().0, DRAW_SCORE - 50);
+
+        let search = search.analyse_mode(true);
+        assert_eq!(search.search().0, DRAW_SCORE);
+        Ok(())
+    }
+
+    #[test]
+    fn legal_bestmove() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+
+        let fallback = game.legal_moves()[0];
+
+        // a depth 0 search doesn't search any moves, but still has a best move
+        let (_, pv) = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_depth(0)
+            .search();
+        assert_eq!(pv.len, 1);
+        assert!(game.legal_moves().contains(&pv.moves[0]));
+
+        // running out of time right after the depth 0 iteration, which is only quiescence,
+        // leaves an empty PV, so the fallback is used
+        let (_, pv) = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_time(Duration::ZERO)
+            .search();
+        assert_eq!(pv.len, 1);
+        assert!(pv.moves[0] == fallback);
+
+        // a PV starting with a move from another position is replaced as well
+        let search = Search::new(game.clone()).tt_size_mb(1);
+        let mut other = ChessGame::new();
+        other.set_from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1")?;
+        let mut pv = PrincipalVariation::new();
+        pv.moves[0] = Move::from_pair(&other, "h1h8");
+        pv.moves[1] = Move::from_pair(&game, "e8g8");
+        pv.len = 2;
+        assert!(!game.is_legal(pv.moves[0]));
+        search.ensure_legal_pv(&mut pv);
+        assert_eq!(pv.len, 1);
+        assert!(pv.moves[0] == fallback);
+
+        // a legal PV is kept
+        let mut pv = PrincipalVariation::new();
+        pv.moves[0] = Move::from_pair(&game, "e2a6");
+        pv.len = 1;
+        search.ensure_legal_pv(&mut pv);
+        assert_eq!(pv.moves[0].coords(), "e2a6");
+        Ok(())
+    }
+
+    #[test]
+    fn game_history_repetition() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("7k/8/8/8/8/8/8/1Q5K w - - 0 1")?;
+        for coords in "b1c1 h8g8 c1b1 g8h8 b1c1 h8g8 c1b1 g8h8 b1c1".split(' ') {
+            game.make_move(Move::from_pair(&game, coords));
+        }
+
+        // Kg8 reaches the same position for the third time
+        let (score, pv) = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_depth(3)
+            .search();
+        assert_eq!(pv.moves[0].coords(), "h8g8");
+        assert_eq!(score, DRAW_SCORE);
+
+        // without the game's history black is just lost
+        let mut fresh = ChessGame::new();
+        fresh.set_from_fen(game.fen())?;
+        let (score, _) = Search::new(fresh).tt_size_mb(1).max_depth(3).search();
+        assert!(score < DRAW_SCORE - 500);
+        Ok(())
+    }
+
+    #[test]
+    fn static_eval_at_depth_0() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        // white to move can take a free queen
+        game.set_from_fen("4k3/8/8/3q4/4P3/8/8/4K3 w - - 0 1")?;
+        let (score, pv) = Search::new(game.clone()).max_depth(0).search();
+        assert_eq!(score, game.evaluate::<()>().0);
+        assert_eq!(pv.moves[0].coords(), "e4d5");
+        Ok(())
+    }
+
+    #[test]
+    fn probcut() -> Result<(), Box<dyn Error>> {
+        // taking the loose queen wins far more than the margin over beta. Reverse futility
+        // pruning would cut the replies off either way, so it is left out of the comparison
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1")?;
+        let null_window_search = |probcut| {
+            let mut search = Search::new(game.clone())
+                .tt_size_mb(1)
+                .probcut(probcut)
+                .reverse_futility(false);
+            let mut pv = PrincipalVariation::new();
+            let score = search.negamax(-1, 0, PROBCUT_MIN_DEPTH, 1, Move::null(), &mut pv);
+            (score, search)
+        };
+
+        let (score, search) = null_window_search(false);
+        assert!(score >= 0);
+        let nodes = search.nodes;
+
+        // the reduced search of the capture is enough to cut off
+        let (score, search) = null_window_search(true);
+        assert!(score >= PROBCUT_MARGIN);
+        assert!(search.nodes < nodes, "{} >= {nodes}", search.nodes);
+        let entry = search
+            .transposition_table
+            .get(game.hash())
+            .ok_or("no ProbCut entry")?;
+        assert!(entry.node_type == LowerBound);
+        assert_eq!(
+            entry.depth as i32,
+            PROBCUT_MIN_DEPTH - PROBCUT_REDUCTION + 1
+        );
+        assert_eq!(
+            entry.best_move(&game).map(|m| m.coords()).as_deref(),
+            Some("d2d5")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reverse_futility() -> Result<(), Box<dyn Error>> {
+        // a queen up is far above beta
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1")?;
+        let mut search = Search::new(game);
+        let eval = search.game.evaluate::<()>().0;
+        assert_eq!(
+            search.reverse_futility_score(0, 2, false, false, None),
+            Some(eval)
+        );
+        assert_eq!(
+            search.reverse_futility_score(0, 2, false, false, Some(300)),
+            Some(300)
+        );
+        assert_eq!(search.reverse_futility_score(0, 2, true, false, None), None);
+        assert_eq!(
+            search.reverse_futility_score(0, RFP_MAX_DEPTH + 1, false, false, None),
+            None
+        );
+        assert_eq!(
+            search.reverse_futility_score(eval, 1, false, false, None),
+            None
+        );
+
+        // still a rook up, but in check
+        let mut game = ChessGame::new();
+        game.set_from_fen("4k3/8/8/8/8/8/8/3QK2r w - - 0 1")?;
+        let mut search = Search::new(game);
+        assert!(search.game.evaluate::<()>().0 - RFP_MARGIN > 0);
+        assert_eq!(search.reverse_futility_score(0, 1, false, true, None), None);
+        assert_eq!(search.reverse_futility_score(0, 1, true, true, None), None);
+        Ok(())
+    }
+
+    #[cfg(feature = "search-trace")]
+    #[test]
+    fn search_tree_trace() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join("cheers_search_tree_trace.txt");
+        Search::new(ChessGame::new())
+            .tt_size_mb(1)
+            .max_depth(2)
+            .trace_tree(&path, 4)
+            .search();
+
+        let trace = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        let nodes = trace.lines().collect::<Vec<_>>();
+        assert!(!nodes.is_empty());
+        for node in &nodes {
+            let fields = node.split(' ').collect::<Vec<_>>();
+            assert_eq!(fields.len(), 7, "{node}");
+            let ply: usize = fields[0].parse()?;
+            assert!(ply < 4);
+            for number in [fields[1], fields[3], fields[4], fields[5]] {
+                number.parse::<i32>()?;
+            }
+            assert!(["Exact", "LowerBound", "UpperBound"].contains(&fields[6]));
+        }
+        // each iteration starts at the root
+        let roots = nodes.iter().filter(|n| n.starts_with("0 ")).count();
+        assert_eq!(roots, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn max_time() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+
+        // the deadline can't pass early, but a busy machine can be slow to notice it
+        let start = Instant::now();
+        let (_, pv) = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_time(Duration::from_millis(200))
+            .search();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(game
+            .legal_moves()
+            .iter()
+            .any(|m| m.coords() == pv.moves[0].coords()));
+
+        // a search far too deep to finish is cut off by the deadline
+        let mut search = Search::new(game)
+            .tt_size_mb(1)
+            .max_time(Duration::from_millis(50));
+        search.start_clock();
+        let mut pv = PrincipalVariation::new();
+        search.negamax(MINUS_INF, INF, 30, 0, Move::null(), &mut pv);
+        assert!(search.timed_out);
+        Ok(())
+    }
+
+    #[test]
+    fn ponder() -> Result<(), Box<dyn Error>> {
+        let game = ChessGame::new();
+        let search = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_time(Duration::from_millis(10))
+            .ponder(true);
+
+        // the clock only starts once the expected move is played
+        let mut clock = search.clone();
+        clock.start_clock();
+        assert!(clock.deadline.is_none());
+
+        // without a clock the search only ends after the ponderhit
+        let handle = {
+            let search = search.clone();
+            std::thread::spawn(move || search.search())
+        };
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!handle.is_finished());
+        search.ponderhit();
+        let (_, pv) = handle.join().unwrap();
+        assert!(game.is_legal(pv.moves[0]));
+
+        clock.start_clock();
+        assert!(clock.deadline.is_some());
+
+        // reaching the maximum depth waits for the opponent, unless stopped
+        let search = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_depth(1)
+            .ponder(true);
+        let handle = {
+            let search = search.clone();
+            std::thread::spawn(move || search.search())
+        };
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!handle.is_finished());
+        search.stop();
+        let (_, pv) = handle.join().unwrap();
+        assert!(game.is_legal(pv.moves[0]));
+        assert!(search.is_pondering());
+        Ok(())
+    }
+
+    #[test]
+    fn stop() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let search = Search::new(game.clone()).tt_size_mb(1);
+
+        // without limits only the stop flag ends the search
+        let start = Instant::now();
+        let handle = {
+            let search = search.clone();
+            std::thread::spawn(move || search.search())
+        };
+        std::thread::sleep(Duration::from_millis(200));
+        search.stop();
+        let (_, pv) = handle.join().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(1000));
+        assert!(game.is_legal(pv.moves[0]));
+
+        // the flag is cleared for the next search
+        assert!(!search.stopped.load(Ordering::Relaxed));
+        Ok(())
+    }
+
+    #[test]
+    fn max_nodes() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let (_, pv) = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_nodes(20_000)
+            .search();
+        assert!(game.is_legal(pv.moves[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn mate_scores() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("k7/8/2K5/8/8/8/8/7R w - - 0 1")?;
+        let (score, pv) = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_depth(6)
+            .search();
+        assert_eq!(score, CHECKMATE_SCORE - 3);
+        assert_eq!(
+            (mate_in(score), uci_score(score)),
+            (2, "mate 2".to_string())
+        );
+
+        // one ply deeper the defender is mated a ply sooner
+        game.make_move(pv.moves[0]);
+        let (score, _) = Search::new(game).tt_size_mb(1).max_depth(6).search();
+        assert_eq!(score, -(CHECKMATE_SCORE - 2));
+        assert_eq!(uci_score(score), "mate -1");
+
+        assert!(!is_mate_score(INF) && !is_mate_score(MINUS_INF));
+
+        // mate and tablebase scores are stored counted from the node, other scores as they are
+        for score in [CHECKMATE_SCORE - 7, -(TB_WIN_SCORE - 7), 35] {
+            assert_eq!(score_from_tt(score_to_tt(score, 5), 5), score);
+        }
+        // the same position reached 2 plies closer to the root is won 2 plies sooner
+        assert_eq!(score_to_tt(TB_WIN_SCORE - 5, 5), TB_WIN_SCORE);
+        assert_eq!(score_from_tt(TB_WIN_SCORE, 3), TB_WIN_SCORE - 3);
+        assert_eq!(
+            score_from_tt(score_to_tt(-(TB_WIN_SCORE - 5), 5), 3),
+            -(TB_WIN_SCORE - 3)
+        );
+        assert_eq!(score_to_tt(35, 5), 35);
+        assert!(!is_tb_score(CHECKMATE_SCORE - 7) && !is_mate_score(TB_WIN_SCORE));
+        assert_eq!(uci_score(-35), "cp -35");
+        Ok(())
+    }
+
+    #[test]
+    fn aspiration_matches_full_window() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+            "2r3k1/pp3ppp/4p3/3pP3/3P4/P3B3/1P3PPP/2R3K1 w - - 0 25",
+            "r1b1k2r/ppppnppp/2n2q2/2b5/3NP3/2P1B3/PP3PPP/RN1QKB1R w KQkq - 0 1",
+        ];
+        for fen in fens {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            let search = |aspiration| {
+                Search::new(game.clone())
+                    .tt_size_mb(1)
+                    .max_depth(6)
+                    .aspiration(aspiration)
+                    .search()
+            };
+            let (score, pv) = search(true);
+            let (full_score, full_pv) = search(false);
+            assert_eq!(score, full_score, "{fen}");
+            assert_eq!(pv.moves[0].coords(), full_pv.moves[0].coords(), "{fen}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lazy_smp() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let search = Search::new(game.clone())
+            .tt_size_mb(1)
+            .threads(4)
+            .max_depth(6);
+        let (score, pv) = search.search();
+        assert!(game.is_legal(pv.moves[0]));
+        assert!(score.abs() < 1000);
+        assert!(search.helper_nodes.load(Ordering::Relaxed) > 0);
+
+        // the helpers are stopped, and the next search starts as usual
+        assert!(!search.stopped.load(Ordering::Relaxed));
+        let (_, pv) = search.search();
+        assert!(game.is_legal(pv.moves[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn tt_hash_collision() -> Result<(), Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        game.set_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")?;
+        let search = Search::new(game.clone()).tt_size_mb(1).max_depth(4);
+
+        // entries left by positions with the same hash, none legal here
+        let bogus = [
+            Move::rook_move(Square::A1, Square::A5, false),
+            Move::pawn_capture(Square::E4, Square::D5),
+            Move::king_castle(Square::E8, Square::G8),
+            Move::knight_move(Square::B6, Square::C4, false),
+            Move::pawn_double_push(Square::E2, Square::E4),
+        ];
+        for move_ in bogus {
+            assert!(!game.is_legal(move_));
+            search
+                .transposition_table
+                .insert(game.hash(), move_, 0, 0, LowerBound, None);
+            let entry = search.transposition_table.get(game.hash()).unwrap();
+            assert!(entry.best_move(&game).is_none());
+        }
+        let legal = game.legal_moves()[0];
+        search
+            .transposition_table
+            .insert(game.hash(), legal, 0, 0, LowerBound, None);
+        let entry = search.transposition_table.get(game.hash()).unwrap();
+        assert_eq!(entry.best_move(&game), Some(legal));
+
+        // the search still only plays legal moves with a bogus entry for the root
+        search
+            .transposition_table
+            .insert(game.hash(), bogus[3], 0, 0, LowerBound, None);
+        let (_, pv) = search.search();
+        let mut replay = game.clone();
+        for &move_ in &pv.moves[..pv.len] {
+            assert!(replay.is_legal(move_), "{}", move_.coords());
+            replay.make_move(move_);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn draws_in_search() -> Result<(), Box<dyn Error>> {
+        let fens = [
+            // every move reaches the fifty move limit
+            "4k3/8/8/8/8/8/8/R3K3 w - - 99 80",
+            // nothing left to mate with
+            "4k3/8/8/8/8/8/8/3NK3 w - - 0 1",
+        ];
+        for fen in fens {
+            let mut game = ChessGame::new();
+            game.set_from_fen(fen)?;
+            let (score, _) = Search::new(game).tt_size_mb(1).max_depth(4).search();
+            assert_eq!(score, 0, "{fen}");
+        }
+
+        // mate on the last move before the fifty move limit still counts
+        let mut game = ChessGame::new();
+        game.set_from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 99 80")?;
+        let (score, pv) = Search::new(game).tt_size_mb(1).max_depth(4).search();
+        assert_eq!(pv.moves[0].coords(), "a1a8");
+        assert_eq!(mate_in(score), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn perpetual_check() -> Result<(), Box<dyn Error>> {
+        // white is lost unless Qh4+ and Qd8+ repeat forever
+        let mut game = ChessGame::new();
+        game.set_from_fen("3Q4/5ppk/8/5P2/8/8/qrr3PP/6K1 w - - 1 2")?;
+        let (score, _) = Search::new(game.clone())
+            .tt_size_mb(1)
+            .max_depth(1)
+            .search();
+        assert!(score < DRAW_SCORE - 500);
+
+        // the repetition is only reached once inside the search
+        let (score, pv) = Search::new(game).tt_size_mb(1).max_depth(6).search();
+        assert_eq!(pv.moves[0].coords(), "d8h4");
+        assert_eq!(score, DRAW_SCORE);
+        Ok(())
+    }
+
+    #[test]
+    fn tablebase_scores() -> Result<(), Box<dyn Error>> {
+        use crate::syzygy::tests::{single_value_table, temp_dir};
+        use std::fs;
+
+        // a table claiming that every KQvK position loses for white, so taking the rook
+        // is avoided once the table is probed after the capture
+        let dir = temp_dir("search-tablebase")?;
+        fs::write(
+            dir.join("KQvK.rtbw"),
+            single_value_table(&[6, 5, 14], false, [0, 4]),
+        )?;
+        let tablebase = Arc::new(Tablebase::new(dir.to_str().unwrap()));
+        let mut game = ChessGame::new();
+        game.set_from_fen("k7/8/8/8/8/8/1r6/KQ6 w - - 0 1")?;
+
+        let (_, pv) = Search::new(game.clone())
+            .max_depth(3)
+            .tt_size_mb(1)
+            .search();
+        assert!(game.is_capture(pv.moves[0]));
+
+        TB_HITS.store(0, Ordering::Relaxed);
+        let (score, pv) = Search::new(game.clone())
+            .max_depth(3)
+            .tt_size_mb(1)
+            .tablebase(tablebase)
+            .search();
+        assert!(!game.is_capture(pv.moves[0]));
+        assert!(score > -TB_WIN_SCORE);
+        assert!(TB_HITS.load(Ordering::Relaxed) > 0);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
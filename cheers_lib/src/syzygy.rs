@@ -0,0 +1,1207 @@
+//! Syzygy endgame tablebase probing.
+//!
+//! Only the WDL tables (`.rtbw`) are read so far: the search scores the positions they cover as
+//! wins, draws or losses, and the root keeps to the moves that hold on to the best result.
+//! The DTZ tables (`.rtbz`) aren't read, so converting a won endgame is left to the search.
+//!
+//! The file layout and the position index follow the probing code of Stockfish and Fathom.
+
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Neg,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use cheers_bitboards::{BitBoard, Square};
+
+use crate::{
+    chessgame::ChessGame,
+    lookup_tables::lookup_king,
+    moves::Move,
+    types::{
+        ColorIndex,
+        PieceIndex::{self, *},
+    },
+};
+
+/// Most pieces in a table, including the kings
+pub const TB_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const WDL_EXTENSION: &str = "rtbw";
+
+/// Table header flags
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+/// Compression flag for a table that stores one value for every position
+const SINGLE_VALUE: u8 = 128;
+
+/// Result of a tablebase position for the side to move. Cursed wins and blessed losses are
+/// wins and losses that take too long, so the fifty move rule turns them into draws
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    /// Tables store the result as 0 for a loss up to 4 for a win
+    fn from_value(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Wdl::Loss),
+            1 => Some(Wdl::BlessedLoss),
+            2 => Some(Wdl::Draw),
+            3 => Some(Wdl::CursedWin),
+            4 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+}
+
+/// Pieces of each type for both sides, indexed by color and piece
+type MaterialCounts = [[u8; 6]; 2];
+
+fn material_key(counts: &MaterialCounts) -> u64 {
+    let mut key = 0;
+    for (color, pieces) in counts.iter().enumerate() {
+        for (piece, &count) in pieces.iter().enumerate() {
+            key |= (count as u64) << (4 * (6 * color + piece));
+        }
+    }
+    key
+}
+
+fn game_material(game: &ChessGame) -> MaterialCounts {
+    let masks = game.piece_masks();
+    let mut counts = [[0; 6]; 2];
+    for (color, pieces) in counts.iter_mut().enumerate() {
+        for (piece, count) in pieces.iter_mut().enumerate() {
+            *count = masks.0[color][piece].count_ones() as u8;
+        }
+    }
+    counts
+}
+
+/// Material of a table from its file name, e.g. `KRPvKR`, white first
+fn parse_table_name(name: &str) -> Option<MaterialCounts> {
+    let (white, black) = name.split_once('v')?;
+    let mut counts = [[0; 6]; 2];
+    for (pieces, side) in counts.iter_mut().zip([white, black]) {
+        for c in side.chars() {
+            let piece = match c {
+                'P' => Pawn,
+                'N' => Knight,
+                'B' => Bishop,
+                'R' => Rook,
+                'Q' => Queen,
+                'K' => King,
+                _ => return None,
+            };
+            pieces[piece as usize] += 1;
+        }
+        if pieces[King as usize] != 1 {
+            return None;
+        }
+    }
+    Some(counts)
+}
+
+/// Piece codes used by the tables: 1 to 6 for a white pawn up to a white king, 9 to 14 for black
+fn piece_code(color: ColorIndex, piece: PieceIndex) -> u8 {
+    (piece as u8 + 1) | (color as u8) << 3
+}
+
+/// Rank minus file: negative below the a1-h8 diagonal and positive above it
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+/// Square numberings that make up the position index
+struct Encoding {
+    /// a2-h7 to 0..47, highest for the pawn nearest the edge and then the lowest rank
+    map_pawns: [usize; 64],
+    /// Squares below the a1-h8 diagonal to 0..27
+    map_b1h1h7: [usize; 64],
+    /// The a1-d1-d4 triangle to 0..9, with the diagonal last
+    map_a1d1d4: [usize; 64],
+    /// The 462 legal placements of two kings with the first in the a1-d1-d4 triangle
+    map_kk: [[usize; 64]; 10],
+    /// Ways to choose k elements from n, indexed by k and n
+    binomial: [[u64; 64]; TB_PIECES],
+    /// Index of the leading pawn, by the number of leading pawns and the square
+    lead_pawn_idx: [[u64; 64]; TB_PIECES],
+    /// Placements of the leading pawns, by the number of leading pawns and the file
+    lead_pawns_size: [[u64; 4]; TB_PIECES],
+}
+
+impl Encoding {
+    fn new() -> Self {
+        let mut encoding = Encoding {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; TB_PIECES],
+            lead_pawn_idx: [[0; 64]; TB_PIECES],
+            lead_pawns_size: [[0; 4]; TB_PIECES],
+        };
+
+        let mut code = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                encoding.map_b1h1h7[square] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = Vec::new();
+        code = 0;
+        for square in 0..=*Square::D4 as usize {
+            if square % 8 > 3 {
+                continue;
+            }
+            match off_diagonal(square) {
+                off if off < 0 => {
+                    encoding.map_a1d1d4[square] = code;
+                    code += 1;
+                }
+                0 => diagonal.push(square),
+                _ => {}
+            }
+        }
+        for square in diagonal {
+            encoding.map_a1d1d4[square] = code;
+            code += 1;
+        }
+
+        // a first king on the diagonal puts the second one on or below it,
+        // and placements with both kings on the diagonal come last
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for index in 0..10 {
+            for first in 0..=*Square::D4 as usize {
+                // b1 is the only square in the triangle numbered 0
+                if encoding.map_a1d1d4[first] != index
+                    || (index == 0 && first != *Square::B1 as usize)
+                {
+                    continue;
+                }
+                let first_square = Square::from(first);
+                for second in 0..64 {
+                    if ((lookup_king(first_square) | first_square.bitboard())
+                        & Square::from(second).bitboard())
+                    .is_not_empty()
+                    {
+                        continue;
+                    }
+                    match (off_diagonal(first), off_diagonal(second)) {
+                        (0, off) if off > 0 => {}
+                        (0, 0) => both_on_diagonal.push((index, second)),
+                        _ => {
+                            encoding.map_kk[index][second] = code;
+                            code += 1;
+                        }
+                    }
+                }
+            }
+        }
+        for (index, second) in both_on_diagonal {
+            encoding.map_kk[index][second] = code;
+            code += 1;
+        }
+
+        encoding.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..TB_PIECES.min(n + 1) {
+                encoding.binomial[k][n] = if k > 0 {
+                    encoding.binomial[k - 1][n - 1]
+                } else {
+                    0
+                } + if k < n {
+                    encoding.binomial[k][n - 1]
+                } else {
+                    0
+                };
+            }
+        }
+
+        // every rank the leading pawn moves up takes two squares on the edge files from the others
+        let mut available = 47;
+        for lead_pawns in 1..=5 {
+            for file in 0..4 {
+                let mut index = 0;
+                for rank in 1..7 {
+                    let square = 8 * rank + file;
+                    if lead_pawns == 1 {
+                        encoding.map_pawns[square] = available;
+                        encoding.map_pawns[square ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    encoding.lead_pawn_idx[lead_pawns][square] = index;
+                    index += encoding.binomial[lead_pawns - 1][encoding.map_pawns[square]];
+                }
+                encoding.lead_pawns_size[lead_pawns][file] = index;
+            }
+        }
+
+        encoding
+    }
+}
+
+fn encoding() -> &'static Encoding {
+    static ENCODING: OnceLock<Encoding> = OnceLock::new();
+    ENCODING.get_or_init(Encoding::new)
+}
+
+fn read_u16_le(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn read_u32_le(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u32_be(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u64_be(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+/// One compressed subtable, for a side to move and the file of the leading pawn.
+/// Positions are read from a canonical Huffman code over symbols that each expand
+/// into a run of results. Offsets are into the table's file
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    /// Shortest code length, or the result of a single value table
+    min_sym_len: u8,
+    block_size: usize,
+    /// Positions between entries of the sparse index
+    span: usize,
+    num_blocks: usize,
+    lowest_sym: usize,
+    btree: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_lengths: usize,
+    block_lengths_size: usize,
+    data: usize,
+    /// Smallest code of each length, left aligned
+    base64: Vec<u64>,
+    /// Results a symbol expands into, minus 1
+    symlen: Vec<u8>,
+    /// Piece codes in the order they are encoded
+    pieces: [u8; TB_PIECES],
+    /// Multiplier of each group of pieces in the index, and the table size after the last group
+    group_idx: [u64; TB_PIECES + 1],
+    /// Pieces in each group, zero terminated
+    group_len: [usize; TB_PIECES + 1],
+}
+
+impl PairsData {
+    fn set_groups(&mut self, entry: &WdlEntry, order: [u8; 2], file: usize) {
+        let encoding = encoding();
+
+        // the leading group is the leading pawns, three unique pieces or both kings,
+        // followed by groups of identical pieces
+        let mut first_len: i32 = if entry.has_pawns {
+            0
+        } else if entry.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        let mut n = 0;
+        self.group_len[0] = 1;
+        for i in 1..entry.piece_count {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        // the groups are encoded in a per-table order, with the leading group at order[0]
+        // and the remaining pawns at order[1]
+        let pawns_on_both_sides = entry.has_pawns && entry.pawn_count[1] > 0;
+        let mut next = if pawns_on_both_sides { 2 } else { 1 };
+        let mut free_squares = 64
+            - self.group_len[0]
+            - if pawns_on_both_sides {
+                self.group_len[1]
+            } else {
+                0
+            };
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                self.group_idx[0] = idx;
+                idx *= if entry.has_pawns {
+                    encoding.lead_pawns_size[self.group_len[0]][file]
+                } else if entry.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                self.group_idx[1] = idx;
+                idx *= encoding.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= encoding.binomial[self.group_len[next]][free_squares];
+                free_squares -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        self.group_idx[n] = idx;
+    }
+
+    /// Read the compression header at `pos`, returning where the next one starts
+    fn set_sizes(&mut self, data: &[u8], mut pos: usize) -> Option<usize> {
+        self.flags = *data.get(pos)?;
+        pos += 1;
+        if self.flags & SINGLE_VALUE != 0 {
+            self.min_sym_len = *data.get(pos)?;
+            return Some(pos + 1);
+        }
+
+        let table_size = self.group_idx[self.group_len.iter().position(|&len| len == 0)?];
+        self.block_size = 1usize.checked_shl(*data.get(pos)? as u32)?;
+        self.span = 1usize.checked_shl(*data.get(pos + 1)? as u32)?;
+        self.sparse_index_size = table_size.div_ceil(self.span as u64) as usize;
+        let padding = *data.get(pos + 2)? as usize;
+        self.num_blocks = read_u32_le(data, pos + 3)? as usize;
+        // padded so the sparse index can't point past the block lengths
+        self.block_lengths_size = self.num_blocks + padding;
+        let max_sym_len = *data.get(pos + 7)?;
+        self.min_sym_len = *data.get(pos + 8)?;
+        pos += 9;
+        if max_sym_len < self.min_sym_len {
+            return None;
+        }
+
+        // longer codes have lower values, so the lowest symbol of each length
+        // gives the smallest code of each length
+        self.lowest_sym = pos;
+        let lengths = (max_sym_len - self.min_sym_len) as usize + 1;
+        let lowest_sym = |i: usize| read_u16_le(data, self.lowest_sym + 2 * i).map(u64::from);
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] =
+                (self.base64[i + 1] + lowest_sym(i)?).checked_sub(lowest_sym(i + 1)?)? / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl(64u32.checked_sub(i as u32 + self.min_sym_len as u32)?)?;
+        }
+        pos += 2 * lengths;
+
+        let symbols = read_u16_le(data, pos)? as usize;
+        pos += 2;
+        self.btree = pos;
+        if data.len() < self.btree + 3 * symbols {
+            return None;
+        }
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for symbol in 0..symbols {
+            if !visited[symbol] {
+                self.symlen[symbol] = self.set_symlen(data, symbol, &mut visited)?;
+            }
+        }
+        Some(pos + 3 * symbols + (symbols & 1))
+    }
+
+    /// Every symbol is either a result or a pair of earlier symbols
+    fn set_symlen(&mut self, data: &[u8], symbol: usize, visited: &mut [bool]) -> Option<u8> {
+        visited[symbol] = true;
+        let (left, right) = self.pair(data, symbol);
+        if right == 0xFFF {
+            return Some(0);
+        }
+        if left >= self.symlen.len() || right >= self.symlen.len() {
+            return None;
+        }
+        if !visited[left] {
+            self.symlen[left] = self.set_symlen(data, left, visited)?;
+        }
+        if !visited[right] {
+            self.symlen[right] = self.set_symlen(data, right, visited)?;
+        }
+        Some(
+            self.symlen[left]
+                .wrapping_add(self.symlen[right])
+                .wrapping_add(1),
+        )
+    }
+
+    /// The two symbols a symbol expands into, packed as 12 bits each
+    fn pair(&self, data: &[u8], symbol: usize) -> (usize, usize) {
+        let bytes = &data[self.btree + 3 * symbol..self.btree + 3 * symbol + 3];
+        let left = ((bytes[1] as usize & 0xF) << 8) | bytes[0] as usize;
+        let right = ((bytes[2] as usize) << 4) | (bytes[1] as usize >> 4);
+        (left, right)
+    }
+
+    fn block_length(&self, data: &[u8], block: usize) -> Option<i64> {
+        if block >= self.block_lengths_size {
+            return None;
+        }
+        read_u16_le(data, self.block_lengths + 2 * block).map(i64::from)
+    }
+
+    /// The stored value of the position at `idx`
+    fn decompress(&self, data: &[u8], idx: u64) -> Option<i32> {
+        if self.flags & SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len as i32);
+        }
+
+        // the sparse index points near the position, then walk the block lengths to it
+        let k = (idx / self.span as u64) as usize;
+        if k >= self.sparse_index_size {
+            return None;
+        }
+        let mut block = read_u32_le(data, self.sparse_index + 6 * k)? as usize;
+        let mut offset = read_u16_le(data, self.sparse_index + 6 * k + 4)? as i64;
+        offset += (idx % self.span as u64) as i64 - (self.span / 2) as i64;
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += self.block_length(data, block)? + 1;
+        }
+        while offset > self.block_length(data, block)? {
+            offset -= self.block_length(data, block)? + 1;
+            block += 1;
+        }
+
+        // decode symbols until the one that covers the position
+        let mut pos = self.data + block * self.block_size;
+        let mut buffer = read_u64_be(data, pos)?;
+        pos += 8;
+        let mut buffer_bits = 64;
+        let mut symbol;
+        loop {
+            let mut len = 0;
+            while buffer < self.base64[len] {
+                len += 1;
+            }
+            let code_len = (len + self.min_sym_len as usize) as u32;
+            symbol = ((buffer - self.base64[len])
+                .checked_shr(64 - code_len)
+                .unwrap_or(0)) as usize;
+            symbol += read_u16_le(data, self.lowest_sym + 2 * len)? as usize;
+            let symlen = *self.symlen.get(symbol)? as i64;
+            if offset < symlen + 1 {
+                break;
+            }
+            offset -= symlen + 1;
+            buffer = buffer.checked_shl(code_len).unwrap_or(0);
+            buffer_bits -= code_len as i32;
+            if buffer_bits <= 32 {
+                buffer_bits += 32;
+                buffer |= (read_u32_be(data, pos).unwrap_or(0) as u64) << (64 - buffer_bits);
+                pos += 4;
+            }
+        }
+
+        // then expand the symbol down to the result at the offset
+        while self.symlen[symbol] != 0 {
+            let (left, right) = self.pair(data, symbol);
+            let left_len = *self.symlen.get(left)? as i64;
+            if offset < left_len + 1 {
+                symbol = left;
+            } else {
+                offset -= left_len + 1;
+                symbol = right;
+            }
+        }
+        Some(self.pair(data, symbol).0 as i32)
+    }
+}
+
+/// A WDL table file, read when it is first probed
+struct WdlEntry {
+    path: PathBuf,
+    /// Material key with the stronger side as white, and with the colors swapped
+    key: u64,
+    key2: u64,
+    piece_count: usize,
+    has_pawns: bool,
+    /// Whether either side has exactly one piece other than the king
+    has_unique_pieces: bool,
+    /// Pawns of the leading side, the one with fewer pawns, and the other side
+    pawn_count: [usize; 2],
+    table: OnceLock<Option<WdlTable>>,
+}
+
+impl WdlEntry {
+    fn new(path: PathBuf, counts: MaterialCounts) -> Self {
+        let [white, black] = counts;
+        let (white_pawns, black_pawns) =
+            (white[Pawn as usize] as usize, black[Pawn as usize] as usize);
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        Self {
+            path,
+            key: material_key(&counts),
+            key2: material_key(&[black, white]),
+            piece_count: counts.iter().flatten().map(|&n| n as usize).sum(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: counts
+                .iter()
+                .any(|pieces| pieces[..King as usize].contains(&1)),
+            pawn_count: if white_leads {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+            table: OnceLock::new(),
+        }
+    }
+
+    fn table(&self) -> Option<&WdlTable> {
+        self.table
+            .get_or_init(|| {
+                let data = fs::read(&self.path).ok()?;
+                WdlTable::parse(self, data)
+            })
+            .as_ref()
+    }
+}
+
+struct WdlTable {
+    data: Vec<u8>,
+    /// Subtables by the file of the leading pawn, a to d, and the side to move
+    pairs: Vec<Vec<PairsData>>,
+}
+
+impl WdlTable {
+    fn parse(entry: &WdlEntry, data: Vec<u8>) -> Option<Self> {
+        if data.get(..4)? != WDL_MAGIC {
+            return None;
+        }
+        let flags = *data.get(4)?;
+        let split = entry.key != entry.key2;
+        if (flags & SPLIT != 0) != split || (flags & HAS_PAWNS != 0) != entry.has_pawns {
+            return None;
+        }
+        let mut pos = 5;
+
+        let sides = if split { 2 } else { 1 };
+        let files = if entry.has_pawns { 4 } else { 1 };
+        let pawns_on_both_sides = entry.has_pawns && entry.pawn_count[1] > 0;
+        let mut pairs = vec![vec![PairsData::default(); sides]; files];
+        for (file, file_pairs) in pairs.iter_mut().enumerate() {
+            let order = *data.get(pos)?;
+            let pawn_order = if pawns_on_both_sides {
+                *data.get(pos + 1)?
+            } else {
+                0xFF
+            };
+            let orders = [
+                [order & 0xF, pawn_order & 0xF],
+                [order >> 4, pawn_order >> 4],
+            ];
+            pos += 1 + pawns_on_both_sides as usize;
+
+            for k in 0..entry.piece_count {
+                let pieces = *data.get(pos)?;
+                pos += 1;
+                for (side, pairs) in file_pairs.iter_mut().enumerate() {
+                    pairs.pieces[k] = if side == 0 { pieces & 0xF } else { pieces >> 4 };
+                }
+            }
+            for (pairs, order) in file_pairs.iter_mut().zip(orders) {
+                pairs.set_groups(entry, order, file);
+            }
+        }
+        pos += pos & 1;
+
+        for pairs in pairs.iter_mut().flatten() {
+            pos = pairs.set_sizes(&data, pos)?;
+        }
+        for pairs in pairs.iter_mut().flatten() {
+            pairs.sparse_index = pos;
+            pos += 6 * pairs.sparse_index_size;
+        }
+        for pairs in pairs.iter_mut().flatten() {
+            pairs.block_lengths = pos;
+            pos += 2 * pairs.block_lengths_size;
+        }
+        for pairs in pairs.iter_mut().flatten() {
+            pos = (pos + 0x3F) & !0x3F;
+            pairs.data = pos;
+            pos += pairs.num_blocks * pairs.block_size;
+            if pairs.num_blocks > 0 && pos > data.len() {
+                return None;
+            }
+        }
+
+        Some(Self { data, pairs })
+    }
+
+    fn probe(&self, entry: &WdlEntry, game: &ChessGame) -> Option<Wdl> {
+        let (pairs, idx) = self.index(entry, game)?;
+        Wdl::from_value(pairs.decompress(&self.data, idx)?)
+    }
+
+    /// The subtable holding the position and its index in it. The squares are flipped
+    /// and mirrored so the stronger side is white and the leading piece is in a canonical
+    /// part of the board, then each group of pieces is encoded as a combination of squares
+    fn index(&self, entry: &WdlEntry, game: &ChessGame) -> Option<(&PairsData, u64)> {
+        let encoding = encoding();
+
+        // symmetric tables only store white to move
+        let flip = (entry.key == entry.key2 && game.current_player() == ColorIndex::Black)
+            || material_key(&game_material(game)) != entry.key;
+        let (flip_color, flip_squares) = if flip { (8, 56) } else { (0, 0) };
+        let side = flip as usize ^ game.current_player() as usize;
+
+        let mut squares = [0usize; TB_PIECES];
+        let mut pieces = [0u8; TB_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = BitBoard::empty();
+        let mut file = 0;
+        if entry.has_pawns {
+            let lead_color = ColorIndex::from((self.pairs[0][0].pieces[0] ^ flip_color) >> 3);
+            lead_pawns = game.piece_masks()[(lead_color, Pawn)];
+            for square in lead_pawns {
+                squares[size] = *square as usize ^ flip_squares;
+                size += 1;
+            }
+            let lead = (0..size).max_by_key(|&i| encoding.map_pawns[squares[i]])?;
+            squares.swap(0, lead);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+        let lead_pawn_count = size;
+
+        for square in game.combined() ^ lead_pawns {
+            if size == TB_PIECES {
+                return None;
+            }
+            squares[size] = *square as usize ^ flip_squares;
+            pieces[size] = piece_code(game.color_at(square), game.piece_at(square)) ^ flip_color;
+            size += 1;
+        }
+
+        // order the pieces like the table
+        let pairs = self.pairs.get(file)?.get(side)?;
+        for i in lead_pawn_count..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|&j| pairs.pieces[i] == pieces[j]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // the leading piece goes on files a to d
+        if squares[0] % 8 > 3 {
+            squares[..size].iter_mut().for_each(|square| *square ^= 7);
+        }
+
+        let mut idx;
+        if entry.has_pawns {
+            idx = encoding.lead_pawn_idx[lead_pawn_count][squares[0]];
+            squares[1..lead_pawn_count].sort_by_key(|&square| encoding.map_pawns[square]);
+            for (i, &square) in squares.iter().enumerate().take(lead_pawn_count).skip(1) {
+                idx += encoding.binomial[i][encoding.map_pawns[square]];
+            }
+        } else {
+            // without pawns the leading piece also goes on ranks 1 to 4,
+            // and below the diagonal unless the whole leading group is on it
+            if squares[0] / 8 > 3 {
+                squares[..size].iter_mut().for_each(|square| *square ^= 56);
+            }
+            for i in 0..pairs.group_len[0] {
+                match off_diagonal(squares[i]) {
+                    0 => continue,
+                    off if off > 0 => squares[i..size]
+                        .iter_mut()
+                        .for_each(|square| *square = ((*square >> 3) | (*square << 3)) & 63),
+                    _ => {}
+                }
+                break;
+            }
+
+            idx = if entry.has_unique_pieces {
+                let [first, second, third] = [squares[0], squares[1], squares[2]];
+                let adjust1 = (second > first) as usize;
+                let adjust2 = (third > first) as usize + (third > second) as usize;
+                let (rank1, rank2, rank3) = (first / 8, second / 8, third / 8);
+                (if off_diagonal(first) != 0 {
+                    (encoding.map_a1d1d4[first] * 63 + (second - adjust1)) * 62 + third - adjust2
+                } else if off_diagonal(second) != 0 {
+                    (6 * 63 + rank1 * 28 + encoding.map_b1h1h7[second]) * 62 + third - adjust2
+                } else if off_diagonal(third) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank1 * 7 * 28
+                        + (rank2 - adjust1) * 28
+                        + encoding.map_b1h1h7[third]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank1 * 7 * 6
+                        + (rank2 - adjust1) * 6
+                        + (rank3 - adjust2)
+                }) as u64
+            } else {
+                encoding.map_kk[encoding.map_a1d1d4[squares[0]]][squares[1]] as u64
+            };
+        }
+
+        // the other groups, each as a combination of the squares left by the earlier groups
+        idx *= pairs.group_idx[0];
+        let mut start = pairs.group_len[0];
+        let mut remaining_pawns = entry.has_pawns && entry.pawn_count[1] > 0;
+        let mut next = 1;
+        while pairs.group_len[next] != 0 {
+            let len = pairs.group_len[next];
+            let group = start..start + len;
+            squares.get_mut(group.clone())?.sort_unstable();
+            let mut n = 0;
+            for (i, &square) in squares[group].iter().enumerate() {
+                let adjust = squares[..start].iter().filter(|&&s| square > s).count();
+                let square = square.checked_sub(adjust + 8 * remaining_pawns as usize)?;
+                n += encoding.binomial.get(i + 1)?[square];
+            }
+            remaining_pawns = false;
+            idx += n * pairs.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        Some((pairs, idx))
+    }
+}
+
+/// The WDL tables found in a search path
+#[derive(Default)]
+pub struct Tablebase {
+    /// Entries by the material key of both colorings
+    tables: HashMap<u64, Arc<WdlEntry>>,
+    table_count: usize,
+    max_pieces: usize,
+}
+
+impl Tablebase {
+    /// Find the WDL tables in the directories of `path`, separated like the `PATH`
+    /// environment variable. The tables are read into memory when first probed
+    pub fn new(path: &str) -> Self {
+        let mut tablebase = Self::default();
+        for dir in std::env::split_paths(path) {
+            let files = match fs::read_dir(dir) {
+                Ok(files) => files,
+                Err(_) => continue,
+            };
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().and_then(|e| e.to_str()) != Some(WDL_EXTENSION) {
+                    continue;
+                }
+                let counts = match path
+                    .file_stem()
+                    .and_then(|name| name.to_str())
+                    .and_then(parse_table_name)
+                {
+                    Some(counts) => counts,
+                    None => continue,
+                };
+                let entry = Arc::new(WdlEntry::new(path, counts));
+                if entry.piece_count > TB_PIECES || tablebase.tables.contains_key(&entry.key) {
+                    continue;
+                }
+                tablebase.table_count += 1;
+                tablebase.max_pieces = tablebase.max_pieces.max(entry.piece_count);
+                tablebase.tables.insert(entry.key2, entry.clone());
+                tablebase.tables.insert(entry.key, entry);
+            }
+        }
+        tablebase
+    }
+
+    /// Number of WDL tables found
+    pub fn table_count(&self) -> usize {
+        self.table_count
+    }
+
+    /// Most pieces in any of the tables, 0 without tables
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// The result for the side to move with perfect play, ignoring the fifty move counter.
+    /// `None` with castling rights, too many pieces or a missing or unreadable table
+    pub fn probe_wdl(&self, game: &ChessGame) -> Option<Wdl> {
+        if !self.covers(game) {
+            return None;
+        }
+        self.search(&mut game.clone())
+    }
+
+    /// The best move by the tables, see [`Tablebase::root_moves`]
+    pub fn probe_root(&self, game: &ChessGame) -> Option<Move> {
+        self.root_moves(game)?.first().copied()
+    }
+
+    /// The legal moves that keep the best result, with captures and pawn moves first since
+    /// they reset the fifty move counter. `None` if the position isn't covered by the tables
+    pub fn root_moves(&self, game: &ChessGame) -> Option<Vec<Move>> {
+        if !self.covers(game) {
+            return None;
+        }
+        let mut game = game.clone();
+        let mut results = Vec::new();
+        for move_ in game.legal_moves() {
+            let zeroing = game.is_capture(move_) || game.piece_at(move_.start()) == Pawn;
+            game.make_move(move_);
+            let wdl = self.search(&mut game);
+            game.unmake_move();
+            results.push((move_, -wdl?, zeroing));
+        }
+        let best = results.iter().map(|&(_, wdl, _)| wdl).max()?;
+        results.retain(|&(_, wdl, _)| wdl == best);
+        results.sort_by_key(|&(_, _, zeroing)| !zeroing);
+        Some(results.into_iter().map(|(move_, _, _)| move_).collect())
+    }
+
+    fn covers(&self, game: &ChessGame) -> bool {
+        game.combined().count_ones() as usize <= self.max_pieces
+            && game
+                .castling_rights()
+                .0
+                .iter()
+                .flatten()
+                .all(|&right| !right)
+    }
+
+    /// Resolve captures before probing: the tables don't account for en passant,
+    /// and a capture may do better than the stored result
+    fn search(&self, game: &mut ChessGame) -> Option<Wdl> {
+        let moves = game.legal_moves();
+        let mut best = Wdl::Loss;
+        let mut captures = 0;
+        for &move_ in &moves {
+            if !game.is_capture(move_) {
+                continue;
+            }
+            captures += 1;
+            game.make_move(move_);
+            let wdl = self.search(game);
+            game.unmake_move();
+            best = best.max(-wdl?);
+            if best == Wdl::Win {
+                return Some(best);
+            }
+        }
+
+        // with nothing but captures the table isn't needed
+        if captures > 0 && captures == moves.len() {
+            return Some(best);
+        }
+        Some(best.max(self.probe_table(game)?))
+    }
+
+    fn probe_table(&self, game: &ChessGame) -> Option<Wdl> {
+        if game.combined().count_ones() == 2 {
+            return Some(Wdl::Draw);
+        }
+        let entry = self.tables.get(&material_key(&game_material(game)))?;
+        entry.table()?.probe(entry, game)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::error::Error;
+
+    /// A table with one result per side to move for every position,
+    /// `pieces` being the piece codes in encoding order
+    pub(crate) fn single_value_table(pieces: &[u8], has_pawns: bool, results: [u8; 2]) -> Vec<u8> {
+        let mut data = WDL_MAGIC.to_vec();
+        data.push(SPLIT | if has_pawns { HAS_PAWNS } else { 0 });
+        let files = if has_pawns { 4 } else { 1 };
+        for _ in 0..files {
+            data.push(0);
+            data.extend(pieces.iter().map(|&piece| piece | piece << 4));
+        }
+        if data.len() % 2 == 1 {
+            data.push(0);
+        }
+        for _ in 0..files {
+            for result in results {
+                data.extend([SINGLE_VALUE, result]);
+            }
+        }
+        data
+    }
+
+    fn game(fen: &str) -> ChessGame {
+        let mut game = ChessGame::new();
+        game.set_from_fen(fen).unwrap();
+        game
+    }
+
+    /// White to move FEN of a few pieces on their squares
+    fn placement_fen(pieces: &[(usize, char)]) -> String {
+        let mut board = ['1'; 64];
+        for &(square, piece) in pieces {
+            board[square] = piece;
+        }
+        let ranks = board
+            .chunks(8)
+            .rev()
+            .map(|rank| rank.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("/");
+        let mut game = ChessGame::new();
+        game.set_from_fen(format!("{ranks} w - - 0 1")).unwrap();
+        game.fen()
+    }
+
+    pub(crate) fn temp_dir(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("cheers-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn encoding_tables() {
+        let encoding = encoding();
+        assert_eq!(encoding.map_kk.iter().flatten().max(), Some(&461));
+        assert_eq!(encoding.map_b1h1h7.iter().max(), Some(&27));
+        assert_eq!(encoding.map_a1d1d4[*Square::B1 as usize], 0);
+        assert_eq!(encoding.map_a1d1d4[*Square::D4 as usize], 9);
+        assert_eq!(encoding.binomial[2][6], 15);
+        assert_eq!(encoding.binomial[3][48], 17296);
+
+        // the pawn squares are numbered 0..47 once each, highest on the a and h files
+        let mut pawn_squares = encoding.map_pawns[8..56].to_vec();
+        pawn_squares.sort_unstable();
+        assert_eq!(pawn_squares, (0..48).collect::<Vec<_>>());
+        assert_eq!(encoding.map_pawns[*Square::A2 as usize], 47);
+        assert_eq!(encoding.lead_pawns_size[1], [6; 4]);
+    }
+
+    #[test]
+    fn table_names() {
+        let counts = parse_table_name("KRPvKR").unwrap();
+        assert_eq!(counts[0], [1, 0, 0, 1, 0, 1]);
+        assert_eq!(counts[1], [0, 0, 0, 1, 0, 1]);
+        assert_eq!(
+            material_key(&counts),
+            material_key(&game_material(&game("8/8/3k4/8/2r5/8/4PR2/4K3 w - - 0 1")))
+        );
+        assert!(parse_table_name("KQvKK").is_none());
+        assert!(parse_table_name("KXvK").is_none());
+    }
+
+    #[test]
+    fn index_symmetry() {
+        // positions that mirror each other share an index, and every index fits in the table
+        let entry = WdlEntry::new(PathBuf::new(), parse_table_name("KRvK").unwrap());
+        let table = WdlTable::parse(&entry, single_value_table(&[6, 4, 14], false, [4, 2]))
+            .expect("valid table");
+        let pawn_entry = WdlEntry::new(PathBuf::new(), parse_table_name("KPvK").unwrap());
+        let pawn_table =
+            WdlTable::parse(&pawn_entry, single_value_table(&[1, 6, 14], true, [4, 2]))
+                .expect("valid table");
+
+        let mut rng = 0x2545f4914f6cdd1du64;
+        let mut next = || {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            rng as usize
+        };
+        let mut checked = 0;
+        while checked < 2000 {
+            let (king, piece, enemy_king) = (next() % 64, next() % 64, next() % 64);
+            if king == piece
+                || piece == enemy_king
+                || ((lookup_king(Square::from(king)) | Square::from(king).bitboard())
+                    & Square::from(enemy_king).bitboard())
+                .is_not_empty()
+            {
+                continue;
+            }
+            for (entry, table, piece_char) in
+                [(&entry, &table, 'R'), (&pawn_entry, &pawn_table, 'P')]
+            {
+                if piece_char == 'P' && !(8..56).contains(&piece) {
+                    continue;
+                }
+                let fen = |flip: usize| {
+                    placement_fen(&[
+                        (king ^ flip, 'K'),
+                        (piece ^ flip, piece_char),
+                        (enemy_king ^ flip, 'k'),
+                    ])
+                };
+                let (pairs, idx) = table.index(entry, &game(&fen(0))).unwrap();
+                let size = pairs.group_idx[pairs.group_len.iter().position(|&l| l == 0).unwrap()];
+                assert!(idx < size, "{} has index {idx} of {size}", fen(0));
+                let (_, mirrored) = table.index(entry, &game(&fen(7))).unwrap();
+                assert_eq!(idx, mirrored, "{}", fen(0));
+                if piece_char == 'R' {
+                    let (_, flipped) = table.index(entry, &game(&fen(56))).unwrap();
+                    assert_eq!(idx, flipped, "{}", fen(0));
+                }
+            }
+            checked += 1;
+        }
+    }
+
+    #[test]
+    fn decompress_pairs() {
+        // a KRvK table with white to move compressed from two results, a draw coded `00`
+        // and a win coded `01`, and the pair of a draw and a win coded `1`
+        let result = |idx: usize| if idx % 3 == 1 { 4 } else { 2 };
+        let size = 31332;
+        let (block_bits, span) = (512, 128);
+
+        let mut blocks = vec![Vec::new()];
+        let mut block_positions = vec![0];
+        let mut idx = 0;
+        while idx < size {
+            let (bits, positions): (&[bool], _) =
+                if result(idx) == 2 && idx + 1 < size && result(idx + 1) == 4 {
+                    (&[true], 2)
+                } else if result(idx) == 2 {
+                    (&[false, false], 1)
+                } else {
+                    (&[false, true], 1)
+                };
+            if blocks.last().unwrap().len() + bits.len() > block_bits {
+                blocks.push(Vec::new());
+                block_positions.push(0);
+            }
+            blocks.last_mut().unwrap().extend_from_slice(bits);
+            *block_positions.last_mut().unwrap() += positions;
+            idx += positions;
+        }
+
+        let mut data = WDL_MAGIC.to_vec();
+        data.extend([SPLIT, 0, 0x66, 0x44, 0xEE, 0]);
+        data.extend([0, 6, 7, 0]);
+        data.extend((blocks.len() as u32).to_le_bytes());
+        // code lengths 1 to 2, the lowest symbols of each length, then three symbols
+        data.extend([2, 1, 2, 0, 0, 0, 3, 0]);
+        for (left, right) in [(2usize, 0xFFFusize), (4, 0xFFF), (0, 1)] {
+            data.extend([
+                left as u8,
+                ((left >> 8) | (right & 0xF) << 4) as u8,
+                (right >> 4) as u8,
+            ]);
+        }
+        data.push(0);
+        data.extend([SINGLE_VALUE, 2]);
+
+        // the sparse index entry k points at position k * span + span / 2
+        for k in 0..size.div_ceil(span) {
+            let position = k * span + span / 2;
+            let mut block = 0;
+            let mut first = 0;
+            while block + 1 < blocks.len() && first + block_positions[block] <= position {
+                first += block_positions[block];
+                block += 1;
+            }
+            data.extend((block as u32).to_le_bytes());
+            data.extend(((position - first) as u16).to_le_bytes());
+        }
+        for positions in &block_positions {
+            data.extend((*positions as u16 - 1).to_le_bytes());
+        }
+        data.resize(data.len().next_multiple_of(64), 0);
+        for block in &blocks {
+            let mut bytes = vec![0u8; block_bits / 8];
+            for (i, &bit) in block.iter().enumerate() {
+                bytes[i / 8] |= (bit as u8) << (7 - i % 8);
+            }
+            data.extend(bytes);
+        }
+
+        let entry = WdlEntry::new(PathBuf::new(), parse_table_name("KRvK").unwrap());
+        let table = WdlTable::parse(&entry, data).expect("valid table");
+        let pairs = &table.pairs[0][0];
+        assert_eq!(pairs.group_idx[1], size as u64);
+        for idx in 0..size {
+            assert_eq!(
+                pairs.decompress(&table.data, idx as u64),
+                Some(result(idx)),
+                "{idx}"
+            );
+        }
+        assert_eq!(pairs.decompress(&table.data, size as u64 + 1000), None);
+        assert_eq!(table.pairs[0][1].decompress(&table.data, 0), Some(2));
+    }
+
+    #[test]
+    fn probe_single_value_table() -> Result<(), Box<dyn Error>> {
+        let dir = temp_dir("syzygy")?;
+        // every KQvK position is a win with white to move and a loss with black to move,
+        // and every KRvK position a draw
+        fs::write(
+            dir.join("KQvK.rtbw"),
+            single_value_table(&[6, 5, 14], false, [4, 0]),
+        )?;
+        fs::write(
+            dir.join("KRvK.rtbw"),
+            single_value_table(&[6, 4, 14], false, [2, 2]),
+        )?;
+        fs::write(dir.join("README.txt"), "not a table")?;
+        let tablebase = Tablebase::new(dir.to_str().unwrap());
+        assert_eq!(tablebase.table_count(), 2);
+        assert_eq!(tablebase.max_pieces(), 3);
+
+        let probe = |fen: &str| tablebase.probe_wdl(&game(fen));
+        assert_eq!(probe("7k/8/8/8/8/3Q4/8/K7 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(probe("7k/8/8/8/8/3Q4/8/K7 b - - 0 1"), Some(Wdl::Loss));
+        // black as the stronger side
+        assert_eq!(probe("7K/8/8/8/8/3q4/8/k7 b - - 0 1"), Some(Wdl::Win));
+        assert_eq!(probe("7K/8/8/8/8/3q4/8/k7 w - - 0 1"), Some(Wdl::Loss));
+        // capturing the queen draws
+        assert_eq!(probe("8/8/8/8/8/8/2kQ4/K7 b - - 0 1"), Some(Wdl::Draw));
+        assert_eq!(probe("4k3/8/8/8/8/8/8/R3K3 w - - 0 1"), Some(Wdl::Draw));
+        // castling rights, missing tables and too many pieces
+        assert_eq!(probe("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1"), None);
+        assert_eq!(probe("7k/8/8/8/8/3B4/8/K7 w - - 0 1"), None);
+        assert_eq!(probe("7k/8/8/8/8/3Q4/8/K6R w - - 0 1"), None);
+
+        // the queen can't be given away
+        let game = game("7k/8/8/8/8/3Q4/8/K7 w - - 0 1");
+        let root_moves = tablebase.root_moves(&game).unwrap();
+        assert_eq!(root_moves.len(), game.legal_moves().len() - 1);
+        assert!(root_moves.iter().all(|m| m.coords() != "d3h7"));
+        assert!(tablebase.probe_root(&game).is_some());
+
+        assert_eq!(Tablebase::new("").table_count(), 0);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
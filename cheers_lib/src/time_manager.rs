@@ -0,0 +1,118 @@
+use crate::moves::Move;
+
+/// Score drop between iterations that counts as the root failing low
+const FAIL_LOW_MARGIN: i32 = 30;
+/// Iterations with the same best move before we finish early
+const STABLE_ITERATIONS: usize = 4;
+
+/// Decides when iterative deepening should stop, based on how settled the search is.
+///
+/// The soft limit is stretched when the best move changes or the score drops,
+/// and shortened when the best move has been stable for a while.
+/// It never exceeds the hard limit.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeManager {
+    soft_limit_ms: usize,
+    hard_limit_ms: usize,
+    best_move: Move,
+    stable_iterations: usize,
+    last_score: Option<i32>,
+    scale_percent: usize,
+}
+
+impl TimeManager {
+    pub fn new(soft_limit_ms: usize, hard_limit_ms: usize) -> Self {
+        Self {
+            soft_limit_ms,
+            hard_limit_ms,
+            best_move: Move::null(),
+            stable_iterations: 0,
+            last_score: None,
+            scale_percent: 100,
+        }
+    }
+
+    /// Record the result of a completed iteration
+    pub fn update(&mut self, best_move: Move, score: i32) {
+        let same_move = best_move.start() == self.best_move.start()
+            && best_move.target() == self.best_move.target();
+        if same_move {
+            self.stable_iterations += 1;
+        } else {
+            self.stable_iterations = 0;
+            self.best_move = best_move;
+        }
+        let fail_low = self
+            .last_score
+            .is_some_and(|last| score < last - FAIL_LOW_MARGIN);
+        self.last_score = Some(score);
+
+        self.scale_percent = if fail_low {
+            // the position is worse than we thought, look for something better
+            200
+        } else if !same_move {
+            // the best move just changed, make sure it holds up
+            150
+        } else if self.stable_iterations >= STABLE_ITERATIONS {
+            50
+        } else {
+            100
+        };
+    }
+
+    /// The current soft limit, adjusted for the stability of the search
+    pub fn soft_limit_ms(&self) -> usize {
+        (self.soft_limit_ms * self.scale_percent / 100).min(self.hard_limit_ms)
+    }
+
+    pub fn hard_limit_ms(&self) -> usize {
+        self.hard_limit_ms
+    }
+
+    /// Whether there is no time to start another iteration
+    pub fn should_stop(&self, elapsed_ms: usize) -> bool {
+        elapsed_ms >= self.soft_limit_ms()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cheers_bitboards::Square;
+
+    #[test]
+    fn fail_low_extends_deadline() {
+        let mut tm = TimeManager::new(1000, 3000);
+        let e2e4 = Move::pawn_double_push(Square::E2, Square::E4);
+        let d2d4 = Move::pawn_double_push(Square::D2, Square::D4);
+
+        // scripted iterations: (best move, score, clock when the iteration finished)
+        let iterations = [(e2e4, 30, 100), (e2e4, 35, 300), (e2e4, 32, 600)];
+        for (best_move, score, elapsed) in iterations {
+            tm.update(best_move, score);
+            assert!(!tm.should_stop(elapsed));
+        }
+        assert!(tm.should_stop(1000));
+
+        // the root fails low, so keep searching past the original soft limit
+        tm.update(e2e4, -50);
+        assert_eq!(tm.soft_limit_ms(), 2000);
+        assert!(!tm.should_stop(1200));
+
+        // a new best move also gets extra time
+        tm.update(d2d4, -20);
+        assert_eq!(tm.soft_limit_ms(), 1500);
+
+        // stable best move, finish early
+        for _ in 0..STABLE_ITERATIONS {
+            tm.update(d2d4, -20);
+        }
+        assert_eq!(tm.soft_limit_ms(), 500);
+
+        // never beyond the hard limit
+        let mut tm = TimeManager::new(2000, 3000);
+        tm.update(e2e4, 0);
+        tm.update(e2e4, -100);
+        assert_eq!(tm.soft_limit_ms(), 3000);
+    }
+}
@@ -1,8 +1,17 @@
-use std::sync::{atomic::*, Arc, RwLock};
+use std::sync::{atomic::*, Arc};
 
 use cheers_bitboards::Square;
 
-use crate::{moves::Move, types::PieceIndex};
+use crate::{
+    chessgame::ChessGame,
+    moves::Move,
+    types::{PieceIndex, PieceIndex::*},
+};
+
+/// Generations that can be told apart in an entry
+const GENERATIONS: u8 = 16;
+/// Marks an entry without a static eval
+const NO_EVAL: i16 = i16::MIN;
 
 pub const TT_DEFAULT_SIZE: usize = 1 << 22; // 2^22 entries for ~64MB
 
@@ -26,6 +35,8 @@ impl NodeType {
 
 pub struct TTEntry {
     pub score: i32,
+    /// Static evaluation of the position, if it was computed
+    pub static_eval: Option<i32>,
     pub depth: i8,
     pub move_start: Square,
     pub move_target: Square,
@@ -34,22 +45,77 @@ pub struct TTEntry {
     pub double_pawn_push: bool,
     pub en_passent_capture: bool,
     pub castling: bool,
+    /// Search the entry was written in, wrapping at `GENERATIONS`
+    pub generation: u8,
 }
 
+/// Entries are packed into a single u64, from the least significant bit:
+///
+/// | bits  | field                              |
+/// |-------|------------------------------------|
+/// | 0-15  | score, clamped to i16              |
+/// | 16-31 | static eval, or `NO_EVAL`          |
+/// | 32-39 | depth                              |
+/// | 40-45 | move start square                  |
+/// | 46-51 | move target square                 |
+/// | 52-54 | promotion piece                    |
+/// | 55-56 | node type                          |
+/// | 57    | double pawn push                   |
+/// | 58    | en passent capture                 |
+/// | 59    | castling                           |
+/// | 60-63 | generation                         |
 impl TTEntry {
     pub fn from_data(data: u64) -> Self {
+        let static_eval = ((data >> 16) & 0xFFFF) as u16 as i16;
         Self {
-            score: (data & 0xFFFFFFFF) as i32,
+            score: (data & 0xFFFF) as u16 as i16 as i32,
+            static_eval: (static_eval != NO_EVAL).then_some(static_eval as i32),
             depth: ((data >> 32) & 0xFF) as i8,
-            move_start: ((data >> (32 + 8)) & 0xFF).into(),
-            move_target: ((data >> (32 + 8 + 8)) & 0xFF).into(),
-            promotion: PieceIndex::from_u8(((data >> (32 + 8 + 8 + 8)) & 0b111) as u8),
-            node_type: NodeType::from_u8(((data >> (32 + 8 + 8 + 8 + 3)) & 0b11) as u8),
-            double_pawn_push: ((data >> (32 + 8 + 8 + 8 + 3 + 2)) & 0b1) != 0,
-            en_passent_capture: ((data >> (32 + 8 + 8 + 8 + 3 + 2 + 1)) & 0b1) != 0,
-            castling: ((data >> (32 + 8 + 8 + 8 + 3 + 2 + 1 + 1)) & 0b1) != 0,
+            move_start: ((data >> (32 + 8)) & 0x3F).into(),
+            move_target: ((data >> (32 + 8 + 6)) & 0x3F).into(),
+            promotion: PieceIndex::from_u8(((data >> (32 + 8 + 6 + 6)) & 0b111) as u8),
+            node_type: NodeType::from_u8(((data >> (32 + 8 + 6 + 6 + 3)) & 0b11) as u8),
+            double_pawn_push: ((data >> (32 + 8 + 6 + 6 + 3 + 2)) & 0b1) != 0,
+            en_passent_capture: ((data >> (32 + 8 + 6 + 6 + 3 + 2 + 1)) & 0b1) != 0,
+            castling: ((data >> (32 + 8 + 6 + 6 + 3 + 2 + 1 + 1)) & 0b1) != 0,
+            generation: ((data >> (32 + 8 + 6 + 6 + 3 + 2 + 1 + 1 + 1)) & 0xF) as u8,
         }
     }
+
+    fn to_data(&self) -> u64 {
+        let clamp = |score: i32| score.clamp(NO_EVAL as i32 + 1, i16::MAX as i32) as i16 as u16;
+        let mut data = 0u64;
+        data |= clamp(self.score) as u64;
+        data |= (self.static_eval.map_or(NO_EVAL as u16, clamp) as u64) << 16;
+        data |= ((self.depth as u8) as u64) << 32;
+        data |= (*self.move_start as u64) << (32 + 8);
+        data |= (*self.move_target as u64) << (32 + 8 + 6);
+        data |= (self.promotion as u64) << (32 + 8 + 6 + 6);
+        data |= (self.node_type as u64) << (32 + 8 + 6 + 6 + 3);
+        data |= (self.double_pawn_push as u64) << (32 + 8 + 6 + 6 + 3 + 2);
+        data |= (self.en_passent_capture as u64) << (32 + 8 + 6 + 6 + 3 + 2 + 1);
+        data |= (self.castling as u64) << (32 + 8 + 6 + 6 + 3 + 2 + 1 + 1);
+        data |= (self.generation as u64) << (32 + 8 + 6 + 6 + 3 + 2 + 1 + 1 + 1);
+        data
+    }
+
+    /// The stored best move, if it is legal in the position.
+    /// A different position with a colliding hash can leave a move that isn't.
+    pub fn best_move(&self, game: &ChessGame) -> Option<Move> {
+        let move_ = Move::new(
+            self.move_start,
+            self.move_target,
+            game.piece_at(self.move_start),
+            self.promotion,
+            // a Chess960 castling move targets the king's own rook
+            self.en_passent_capture
+                || (!self.castling && game.piece_at(self.move_target) != NoPiece),
+            self.double_pawn_push,
+            self.en_passent_capture,
+            self.castling,
+        );
+        game.is_legal(move_).then_some(move_)
+    }
 }
 
 #[derive(Default)]
@@ -58,9 +124,12 @@ struct Entry {
     data: AtomicU64,
 }
 
+/// Shared between search threads without locking: entries are written and read atomically,
+/// and torn entries are detected by storing the key XORed with the data.
 #[derive(Clone)]
 pub struct TranspositionTable {
-    table: Arc<RwLock<Vec<Entry>>>,
+    table: Arc<[Entry]>,
+    generation: Arc<AtomicU8>,
 }
 
 impl TranspositionTable {
@@ -69,61 +138,132 @@ impl TranspositionTable {
         if length != 0 {
             length = length.next_power_of_two();
         }
-        let mut table = Vec::with_capacity(length);
-        for _ in 0..length {
-            table.push(Entry::default());
-        }
         Self {
-            table: Arc::new(RwLock::new(table)),
+            table: (0..length).map(|_| Entry::default()).collect(),
+            generation: Arc::new(AtomicU8::new(0)),
         }
     }
 
+    /// Replace the table with an empty one of the new size.
+    /// Clones of the table made before resizing keep using the old one.
     pub fn set_size(&mut self, size_mb: usize) {
-        let mut length = size_mb * 1024 * 1024 / std::mem::size_of::<Entry>();
-        length = length.next_power_of_two();
+        *self = Self::new(size_mb);
+    }
+
+    /// Start a new generation, so entries from earlier searches get replaced first
+    pub fn new_search(&self) {
+        self.generation
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |g| {
+                Some((g + 1) % GENERATIONS)
+            })
+            .unwrap();
+    }
+
+    /// Empty every entry and restart the generations, keeping the allocation
+    pub fn clear(&self) {
+        for entry in self.table.iter() {
+            entry.key.store(0, Ordering::Relaxed);
+            entry.data.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Estimated permille of the table in use, from the first 1000 entries
+    pub fn hashfull(&self) -> usize {
+        let sample = &self.table[..self.table.len().min(1000)];
+        if sample.is_empty() {
+            return 0;
+        }
+        let used = sample
+            .iter()
+            .filter(|entry| entry.key.load(Ordering::Relaxed) != 0)
+            .count();
+        used * 1000 / sample.len()
+    }
+
+    fn generation(&self) -> u8 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    fn entry(&self, hash: u64) -> Option<&Entry> {
         self.table
-            .write()
-            .unwrap()
-            .resize_with(length, Entry::default);
+            .get(hash as usize & self.table.len().wrapping_sub(1))
     }
 
-    pub fn set(&self, hash: u64, best_move: Move, depth: i8, score: i32, node_type: NodeType) {
+    pub fn set(
+        &self,
+        hash: u64,
+        best_move: Move,
+        depth: i8,
+        score: i32,
+        node_type: NodeType,
+        static_eval: Option<i32>,
+    ) {
         use self::Ordering::*;
-        let table = self.table.read().unwrap();
-        let index = hash as usize & (table.len() - 1);
-
-        let stored = match table.get(index) {
+        let stored = match self.entry(hash) {
             Some(entry) => entry,
             None => return,
         };
 
-        let stored_depth = (stored.data.load(Acquire) >> 24) & 0xFF;
-        if stored_depth > depth as u64 {
-            // depth-preferred replacement
+        // replace anything from an earlier search, otherwise prefer the deeper entry
+        let existing = TTEntry::from_data(stored.data.load(Acquire));
+        if existing.generation == self.generation() && existing.depth > depth {
             return;
         }
 
-        let mut data = 0u64;
-        data |= score as u32 as u64;
-        data |= ((depth as u8) as u64) << 32;
-        data |= (*best_move.start() as u64) << (32 + 8);
-        data |= (*best_move.target() as u64) << (32 + 8 + 8);
-        data |= (best_move.promotion() as u64) << (32 + 8 + 8 + 8);
-        data |= (node_type as u64) << (32 + 8 + 8 + 8 + 3);
-        data |= (best_move.double_pawn_push() as u64) << (32 + 8 + 8 + 8 + 3 + 2);
-        data |= (best_move.en_passent() as u64) << (32 + 8 + 8 + 8 + 3 + 2 + 1);
-        data |= (best_move.castling() as u64) << (32 + 8 + 8 + 8 + 3 + 2 + 1 + 1);
+        let entry = self.entry_for(best_move, depth, score, node_type, static_eval);
+        Self::write(stored, hash, entry);
+    }
+
+    /// Store an entry regardless of the depth of the existing entry
+    pub fn insert(
+        &self,
+        hash: u64,
+        best_move: Move,
+        depth: i8,
+        score: i32,
+        node_type: NodeType,
+        static_eval: Option<i32>,
+    ) {
+        if let Some(stored) = self.entry(hash) {
+            let entry = self.entry_for(best_move, depth, score, node_type, static_eval);
+            Self::write(stored, hash, entry);
+        }
+    }
+
+    fn entry_for(
+        &self,
+        best_move: Move,
+        depth: i8,
+        score: i32,
+        node_type: NodeType,
+        static_eval: Option<i32>,
+    ) -> TTEntry {
+        TTEntry {
+            score,
+            static_eval,
+            depth,
+            move_start: best_move.start(),
+            move_target: best_move.target(),
+            promotion: best_move.promotion(),
+            node_type,
+            double_pawn_push: best_move.double_pawn_push(),
+            en_passent_capture: best_move.en_passent(),
+            castling: best_move.castling(),
+            generation: self.generation(),
+        }
+    }
 
+    fn write(stored: &Entry, hash: u64, entry: TTEntry) {
+        use self::Ordering::*;
+        let data = entry.to_data();
         stored.key.store(hash ^ data, Release);
         stored.data.store(data, Release);
     }
 
     pub fn get(&self, hash: u64) -> Option<TTEntry> {
         use self::Ordering::*;
-        let table = self.table.read().unwrap();
-        let index = hash as usize & (table.len() - 1);
-
-        let stored = table.get(index)?;
+        let stored = self.entry(hash)?;
 
         let data = stored.data.load(Acquire);
 
@@ -136,3 +276,137 @@ impl TranspositionTable {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cheers_bitboards::Square;
+
+    use super::{NodeType::*, *};
+
+    #[test]
+    fn old_generations_replaced_first() {
+        let tt = TranspositionTable::new(1);
+        let len = tt.table.len() as u64;
+        let move_ = Move::knight_move(Square::G1, Square::F3, false);
+
+        for hash in 0..len {
+            tt.set(hash, move_, 10, 1, Exact, None);
+        }
+        // deeper entries from the same search are kept
+        for hash in 0..len / 2 {
+            tt.set(hash + len, move_, 2, 2, Exact, None);
+        }
+        assert!((0..len).all(|hash| tt.get(hash).is_some_and(|e| e.score == 1)));
+
+        tt.new_search();
+        for hash in 0..len / 2 {
+            tt.set(hash + len, move_, 2, 3, Exact, None);
+        }
+        for hash in 0..len / 2 {
+            assert!(tt.get(hash).is_none());
+            let entry = tt.get(hash + len).unwrap();
+            assert_eq!((entry.score, entry.depth, entry.generation), (3, 2, 1));
+        }
+        // the rest of the old entries are still there until something replaces them
+        assert!((len / 2..len).all(|hash| tt.get(hash).is_some_and(|e| e.score == 1)));
+
+        // entries from the current search are depth-preferred again
+        tt.set(len, move_, 1, 4, Exact, None);
+        assert_eq!(tt.get(len).unwrap().score, 3);
+
+        // generations wrap around
+        for _ in 0..GENERATIONS {
+            tt.new_search();
+        }
+        tt.set(len, move_, 1, 5, Exact, None);
+        assert_eq!(tt.get(len).unwrap().generation, 1);
+        assert_eq!(tt.get(len).unwrap().score, 3);
+    }
+
+    #[test]
+    fn clear_and_hashfull() {
+        let tt = TranspositionTable::new(1);
+        let move_ = Move::knight_move(Square::G1, Square::F3, false);
+        assert_eq!(tt.hashfull(), 0);
+        for hash in 0..250 {
+            tt.set(hash, move_, 1, 0, Exact, None);
+        }
+        assert_eq!(tt.hashfull(), 250);
+        for hash in 0..tt.table.len() as u64 {
+            tt.set(hash, move_, 1, 0, Exact, None);
+        }
+        assert_eq!(tt.hashfull(), 1000);
+
+        let shared = tt.clone();
+        tt.new_search();
+        tt.clear();
+        assert_eq!(tt.hashfull(), 0);
+        assert!(tt.get(1).is_none());
+        assert_eq!(tt.generation(), 0);
+        // clearing doesn't reallocate, so clones still share the table
+        assert!(Arc::ptr_eq(&tt.table, &shared.table));
+        assert_eq!(TranspositionTable::new(0).hashfull(), 0);
+    }
+
+    #[test]
+    fn static_eval() {
+        let tt = TranspositionTable::new(1);
+        let move_ = Move::knight_move(Square::G1, Square::F3, false);
+        tt.set(1, move_, 3, 25, Exact, None);
+        assert_eq!(tt.get(1).unwrap().static_eval, None);
+        tt.set(1, move_, 3, 25, Exact, Some(-40));
+        assert_eq!(tt.get(1).unwrap().static_eval, Some(-40));
+
+        // out of range values are clamped and never read back as missing
+        tt.set(2, move_, 3, i32::MIN + 1, LowerBound, Some(i32::MIN));
+        let entry = tt.get(2).unwrap();
+        assert_eq!(entry.score, -i16::MAX as i32);
+        assert_eq!(entry.static_eval, Some(-i16::MAX as i32));
+        tt.set(2, move_, 3, i32::MAX, UpperBound, Some(40_000));
+        let entry = tt.get(2).unwrap();
+        assert_eq!(entry.score, i16::MAX as i32);
+        assert_eq!(entry.static_eval, Some(i16::MAX as i32));
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let tt = TranspositionTable::new(1);
+        tt.new_search();
+        let moves = [
+            Move::pawn_capture_promotion(Square::G7, Square::H8, Queen),
+            Move::pawn_double_push(Square::A2, Square::A4),
+            Move::pawn_enpassent_capture(Square::E5, Square::D6),
+            Move::king_castle(Square::E8, Square::C8),
+        ];
+        for (i, &move_) in moves.iter().enumerate() {
+            tt.insert(
+                i as u64,
+                move_,
+                -3,
+                -20_000,
+                UpperBound,
+                Some(i as i32 * 100 - 150),
+            );
+            let entry = tt.get(i as u64).unwrap();
+            assert_eq!(
+                (entry.score, entry.depth, entry.generation),
+                (-20_000, -3, 1)
+            );
+            assert!(entry.node_type == UpperBound);
+            assert!(entry.move_start == move_.start() && entry.move_target == move_.target());
+            assert_eq!(entry.promotion, move_.promotion());
+            assert_eq!(
+                (
+                    entry.double_pawn_push,
+                    entry.en_passent_capture,
+                    entry.castling
+                ),
+                (
+                    move_.double_pawn_push(),
+                    move_.en_passent(),
+                    move_.castling()
+                )
+            );
+        }
+    }
+}
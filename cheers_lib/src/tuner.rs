@@ -0,0 +1,346 @@
+//! Texel tuning of [`EvalParams`] against game results.
+//!
+//! Every parameter is an `[mg, eg]` pair, and [`EvalTrace`] counts how often each term
+//! applies to white and black in the same layout, so the evaluation of a position is linear
+//! in the parameters and the mean squared error of its sigmoid against the results can be
+//! minimised by gradient descent.
+
+use std::{error::Error, path::Path, thread};
+
+use crate::{
+    chessgame::{ChessGame, EvalParams, EvalTrace, EVAL_PARAMS},
+    moves::Move,
+    search::Search,
+};
+
+/// A position's traced terms, reduced to the coefficients that don't cancel out
+#[derive(Clone, Debug)]
+pub struct TuningEntry {
+    /// 0 in the opening up to 256 for a bare endgame, as in [`ChessGame::game_phase`]
+    phase: i32,
+    /// 1 for a white win, 0.5 for a draw and 0 for a black win
+    result: f64,
+    /// Index of the midgame parameter of a pair, and white's count minus black's
+    coefficients: Vec<(usize, i32)>,
+}
+
+impl TuningEntry {
+    /// Trace the quiet position that quiescence search reaches from `fen`,
+    /// so positions in the middle of an exchange aren't scored by the exchange
+    pub fn new(fen: &str, result: f64) -> Result<Self, Box<dyn Error>> {
+        let mut game = ChessGame::new();
+        // EPD records leave out the move counters
+        if fen.split_whitespace().count() == 4 {
+            game.set_from_fen(format!("{fen} 0 1"))?;
+        } else {
+            game.set_from_fen(fen)?;
+        }
+        let mut search = Search::new(game);
+        let (_, trace) =
+            search._quiesce::<EvalTrace>(i32::MIN + 1, i32::MAX - 1, -1, Move::null(), EVAL_PARAMS);
+
+        // the side to move is left over at the end of the trace
+        let coefficients = trace
+            .to_array()
+            .chunks_exact(2)
+            .enumerate()
+            .filter(|(_, c)| c[0] != c[1])
+            .map(|(i, c)| (2 * i, c[0] - c[1]))
+            .collect();
+
+        // phase of the traced position, which may have had pieces traded off
+        let material = trace.knight_count.iter().sum::<i32>()
+            + trace.bishop_count.iter().sum::<i32>()
+            + 2 * trace.rook_count.iter().sum::<i32>()
+            + 4 * trace.queen_count.iter().sum::<i32>();
+        let phase = 256 * (24 - material.min(24)) / 24;
+
+        Ok(Self {
+            phase,
+            result,
+            coefficients,
+        })
+    }
+
+    /// Evaluation from white's point of view, without the endgame scaling
+    pub fn linear_evaluation(&self, params: &[f64]) -> f64 {
+        let (mut mg, mut eg) = (0.0, 0.0);
+        for &(index, coefficient) in &self.coefficients {
+            mg += params[index] * coefficient as f64;
+            eg += params[index + 1] * coefficient as f64;
+        }
+        ((256 - self.phase) as f64 * mg + self.phase as f64 * eg) / 256.0
+    }
+}
+
+/// Parse a labelled position: a FEN followed by the game result, either as
+/// `result: 0.5` like the tuning data extracted from PGNs, `[0.5]` or `"1/2-1/2"`
+pub fn parse_labelled_position(line: &str) -> Result<(&str, f64), Box<dyn Error>> {
+    let line = line.trim().trim_end_matches(';');
+    let (fen, label) = if let Some((fen, label)) = line.split_once(" result: ") {
+        (fen, label)
+    } else if let Some((fen, label)) = line.rsplit_once(" [") {
+        (fen, label.trim_end_matches(']'))
+    } else if let Some((fen, label)) = line.split_once(" \"") {
+        // an EPD opcode such as `c9` may come before the quoted result
+        (epd_fen(fen), label.trim_end_matches('"'))
+    } else {
+        return Err(format!("No result in labelled position: {line}").into());
+    };
+    let result = match label.trim() {
+        "1-0" => 1.0,
+        "0-1" => 0.0,
+        "1/2-1/2" => 0.5,
+        other => other.parse::<f64>()?,
+    };
+    if !(0.0..=1.0).contains(&result) {
+        return Err(format!("Result out of range: {result}").into());
+    }
+    Ok((fen, result))
+}
+
+/// The FEN fields at the start of an EPD record: the first four, and the move counters
+/// if they follow, leaving out any opcodes
+fn epd_fen(record: &str) -> &str {
+    let fields = record.split_whitespace().collect::<Vec<_>>();
+    let count = if fields.len() >= 6 && fields[4..6].iter().all(|f| f.parse::<u32>().is_ok()) {
+        6
+    } else {
+        fields.len().min(4)
+    };
+    let mut end = 0;
+    for field in &fields[..count] {
+        end += record[end..].find(field).unwrap_or(0) + field.len();
+    }
+    &record[..end]
+}
+
+/// Read labelled positions, one per line, see [`parse_labelled_position`]
+pub fn parse_entries(data: &str) -> Result<Vec<TuningEntry>, Box<dyn Error>> {
+    data.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (fen, result) = parse_labelled_position(line)?;
+            TuningEntry::new(fen, result)
+        })
+        .collect()
+}
+
+pub fn load_entries(path: impl AsRef<Path>) -> Result<Vec<TuningEntry>, Box<dyn Error>> {
+    parse_entries(&std::fs::read_to_string(path)?)
+}
+
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + (-k * score / 400.0).exp())
+}
+
+/// `f` applied to one slice of the entries per core, since there are usually millions
+fn map_chunks<R: Send>(entries: &[TuningEntry], f: impl Fn(&[TuningEntry]) -> R + Sync) -> Vec<R> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = entries.len().div_ceil(threads).max(1);
+    let f = &f;
+    thread::scope(|scope| {
+        let workers: Vec<_> = entries
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || f(chunk)))
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// Mean squared error of the predicted results against the actual results
+pub fn mean_squared_error(entries: &[TuningEntry], params: &[f64], k: f64) -> f64 {
+    if entries.is_empty() {
+        return 0.0;
+    }
+    map_chunks(entries, |chunk| {
+        chunk
+            .iter()
+            .map(|entry| (entry.result - sigmoid(entry.linear_evaluation(params), k)).powi(2))
+            .sum::<f64>()
+    })
+    .iter()
+    .sum::<f64>()
+        / entries.len() as f64
+}
+
+/// Gradient of [`mean_squared_error`] with respect to every parameter
+fn gradient(entries: &[TuningEntry], params: &[f64], k: f64) -> Vec<f64> {
+    let partial_gradients = map_chunks(entries, |chunk| {
+        let mut gradient = vec![0.0; params.len()];
+        for entry in chunk {
+            let s = sigmoid(entry.linear_evaluation(params), k);
+            let base = -2.0 * (entry.result - s) * s * (1.0 - s) * k / 400.0;
+            let mg_share = (256 - entry.phase) as f64 / 256.0;
+            let eg_share = entry.phase as f64 / 256.0;
+            for &(index, coefficient) in &entry.coefficients {
+                gradient[index] += base * mg_share * coefficient as f64;
+                gradient[index + 1] += base * eg_share * coefficient as f64;
+            }
+        }
+        gradient
+    });
+
+    let mut gradient = vec![0.0; params.len()];
+    for partial in partial_gradients {
+        gradient.iter_mut().zip(partial).for_each(|(g, p)| *g += p);
+    }
+    let len = entries.len().max(1) as f64;
+    gradient.iter_mut().for_each(|g| *g /= len);
+    gradient
+}
+
+/// The sigmoid scale with the lowest error for the current parameters,
+/// found by narrowing down one decimal digit at a time
+pub fn tune_k(entries: &[TuningEntry], params: &EvalParams) -> f64 {
+    let params = params.to_array().map(|x| x as f64);
+    let (mut start, mut end, mut step) = (0.0, 10.0, 1.0);
+    let mut best_k = start;
+    let mut best = mean_squared_error(entries, &params, best_k);
+    for _ in 0..6 {
+        let mut k = start;
+        while k <= end {
+            let error = mean_squared_error(entries, &params, k);
+            if error < best {
+                best = error;
+                best_k = k;
+            }
+            k += step;
+        }
+        start = (best_k - step).max(0.0);
+        end = best_k + step;
+        step /= 10.0;
+    }
+    best_k
+}
+
+/// Gradient descent on the parameters with AdaGrad step sizes.
+/// `cheers_tuning` drives this over a full data set, with a decaying learning rate
+pub struct Tuner {
+    entries: Vec<TuningEntry>,
+    k: f64,
+    params: Vec<f64>,
+    adagrad: Vec<f64>,
+    learning_rate: f64,
+}
+
+impl Tuner {
+    /// Start tuning from `params`
+    pub fn new(entries: Vec<TuningEntry>, params: &EvalParams, k: f64) -> Self {
+        Self {
+            entries,
+            k,
+            params: params.to_array().iter().map(|&x| x as f64).collect(),
+            adagrad: vec![0.0; EvalParams::LEN],
+            learning_rate: 5.0,
+        }
+    }
+
+    /// Largest change to a parameter in the first iteration, shrinking as the
+    /// parameter's gradients add up
+    pub fn learning_rate(mut self, learning_rate: f64) -> Self {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    pub fn error(&self) -> f64 {
+        mean_squared_error(&self.entries, &self.params, self.k)
+    }
+
+    /// Take one step downhill and return the new error
+    pub fn step(&mut self) -> f64 {
+        let gradient = gradient(&self.entries, &self.params, self.k);
+        for ((param, adagrad), g) in self.params.iter_mut().zip(&mut self.adagrad).zip(gradient) {
+            *adagrad += g * g;
+            *param -= self.learning_rate * g / (1e-8 + adagrad.sqrt());
+        }
+        self.error()
+    }
+
+    /// The tuned parameters, rounded to whole centipawns
+    pub fn params(&self) -> EvalParams {
+        let mut params = [0; EvalParams::LEN];
+        for (rounded, param) in params.iter_mut().zip(&self.params) {
+            *rounded = param.round() as i32;
+        }
+        EvalParams::from_array(params)
+    }
+}
+
+/// Rust source for the parameters, to replace the definition of `EVAL_PARAMS`
+pub fn params_source(params: &EvalParams) -> String {
+    format!("pub const EVAL_PARAMS: EvalParams = {params:#?};\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = include_str!("tuningsample.txt");
+
+    #[test]
+    fn labelled_positions() -> Result<(), Box<dyn Error>> {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        assert_eq!(
+            parse_labelled_position(&format!("{fen} result: 1"))?,
+            (fen, 1.0)
+        );
+        assert_eq!(
+            parse_labelled_position(&format!("{fen} [0.5]"))?,
+            (fen, 0.5)
+        );
+        assert_eq!(
+            parse_labelled_position("4k3/8/8/8/8/8/4P3/4K3 w - - c9 \"0-1\";")?,
+            ("4k3/8/8/8/8/8/4P3/4K3 w - -", 0.0)
+        );
+        // an en passant square isn't an opcode
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6";
+        assert_eq!(
+            parse_labelled_position(&format!("{fen} \"1-0\""))?,
+            (fen, 1.0)
+        );
+        assert_eq!(
+            parse_labelled_position(&format!("{fen} 0 2 c9 \"1-0\";"))?,
+            (&*format!("{fen} 0 2"), 1.0)
+        );
+        TuningEntry::new(fen, 1.0)?;
+        assert!(parse_labelled_position(fen).is_err());
+        assert!(parse_labelled_position(&format!("{fen} [2]")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn trace_matches_params_layout() {
+        // the trace has one extra field for the side to move, and none for the two opposite
+        // bishop scales at the end of the params
+        assert_eq!(EvalTrace::LEN, EvalParams::LEN - 1);
+    }
+
+    #[test]
+    fn loss_decreases() -> Result<(), Box<dyn Error>> {
+        let entries = parse_entries(SAMPLE)?;
+        assert_eq!(entries.len(), SAMPLE.lines().count());
+
+        let k = tune_k(&entries, &EVAL_PARAMS);
+        assert!(k > 0.0);
+        let mut tuner = Tuner::new(entries, &EVAL_PARAMS, k);
+        let initial = tuner.error();
+        let mut last = initial;
+        for _ in 0..10 {
+            let error = tuner.step();
+            assert!(error <= last + 1e-12, "{error} > {last}");
+            last = error;
+        }
+        assert!(last < initial);
+
+        // the rounded parameters are still an improvement, and print as valid source
+        let tuned = tuner.params().to_array().map(|x| x as f64);
+        assert!(mean_squared_error(&parse_entries(SAMPLE)?, &tuned, k) < initial);
+        assert!(params_source(&tuner.params())
+            .starts_with("pub const EVAL_PARAMS: EvalParams = EvalParams {"));
+        Ok(())
+    }
+}
@@ -80,6 +80,44 @@ impl PieceIndex {
             _ => NoPiece,
         }
     }
+
+    /// The piece for a FEN character, uppercase for white and lowercase for black
+    pub fn from_fen_char(c: char) -> Option<(ColorIndex, Self)> {
+        use self::PieceIndex::*;
+        let piece = match c.to_ascii_lowercase() {
+            'p' => Pawn,
+            'n' => Knight,
+            'b' => Bishop,
+            'r' => Rook,
+            'q' => Queen,
+            'k' => King,
+            _ => return None,
+        };
+        let color = if c.is_ascii_uppercase() {
+            ColorIndex::White
+        } else {
+            ColorIndex::Black
+        };
+        Some((color, piece))
+    }
+
+    /// The FEN character for a piece of `color`, or `None` for `NoPiece`
+    pub fn to_fen_char(self, color: ColorIndex) -> Option<char> {
+        use self::PieceIndex::*;
+        let letter = match self {
+            Pawn => 'p',
+            Knight => 'n',
+            Bishop => 'b',
+            Rook => 'r',
+            Queen => 'q',
+            King => 'k',
+            NoPiece => return None,
+        };
+        Some(match color {
+            ColorIndex::White => letter.to_ascii_uppercase(),
+            ColorIndex::Black => letter,
+        })
+    }
 }
 
 impl Display for PieceIndex {
@@ -117,6 +155,7 @@ impl<T, const N: usize> IndexMut<PieceIndex> for [T; N] {
 
 use PieceIndex::*;
 pub const PIECES: [PieceIndex; 6] = [Pawn, Knight, Bishop, Rook, Queen, King];
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum CastlingIndex {
     Queenside = 0,
     Kingside = 1,
@@ -168,6 +207,24 @@ impl std::ops::IndexMut<(ColorIndex, PieceIndex)> for PieceMasks {
     }
 }
 
+/// Start files of the king and the castling rooks, the e, a and h files in standard chess.
+/// In Chess960 they can be anywhere on the back rank
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CastlingFiles {
+    pub king: usize,
+    /// Indexed by [`CastlingIndex`]
+    pub rooks: [usize; 2],
+}
+
+impl Default for CastlingFiles {
+    fn default() -> Self {
+        Self {
+            king: 4,
+            rooks: [0, 7],
+        }
+    }
+}
+
 #[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
 pub struct CastlingRights(pub [[bool; 2]; 2]);
 impl std::ops::Index<(ColorIndex, CastlingIndex)> for CastlingRights {
@@ -194,3 +251,25 @@ impl std::ops::IndexMut<ColorIndex> for CastlingRights {
         &mut self.0[index as usize]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fen_chars() {
+        for c in "PNBRQKpnbrqk".chars() {
+            let (color, piece) = PieceIndex::from_fen_char(c).unwrap();
+            assert_eq!(piece.to_fen_char(color), Some(c));
+        }
+        for color in [ColorIndex::White, ColorIndex::Black] {
+            for piece in PIECES {
+                let c = piece.to_fen_char(color).unwrap();
+                assert_eq!(PieceIndex::from_fen_char(c), Some((color, piece)));
+            }
+            assert_eq!(NoPiece.to_fen_char(color), None);
+        }
+        assert_eq!(PieceIndex::from_fen_char('x'), None);
+        assert_eq!(PieceIndex::from_fen_char('1'), None);
+    }
+}
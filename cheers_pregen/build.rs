@@ -138,8 +138,8 @@ mod consts {
     pub const SEVENTH_RANK: BitBoard = BitBoard(0x00FF000000000000);
     pub const EIGHTH_RANK: BitBoard = BitBoard(0xFF00000000000000);
 
-    pub const LIGHT_SQUARES: BitBoard = BitBoard(0x5555555555555555);
-    pub const DARK_SQUARES: BitBoard = BitBoard(0xAAAAAAAAAAAAAAAA);
+    pub const LIGHT_SQUARES: BitBoard = BitBoard(0x55AA55AA55AA55AA);
+    pub const DARK_SQUARES: BitBoard = BitBoard(0xAA55AA55AA55AA55);
 
     pub const LONG_DIAGONALS: BitBoard = BitBoard(0x8142241818244281);
 }
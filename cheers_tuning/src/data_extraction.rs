@@ -5,24 +5,6 @@ pub enum GameResult {
     Draw,
     Abandoned,
 }
-impl GameResult {
-    pub fn into_f64(self) -> f64 {
-        match self {
-            WhiteWin => 1.0,
-            BlackWin => 0.0,
-            Draw | Abandoned => 0.5,
-        }
-    }
-    pub fn from_f64(n: f64) -> Self {
-        if n.abs() < 0.01 {
-            BlackWin
-        } else if (n - 1.0).abs() < 0.01 {
-            WhiteWin
-        } else {
-            Draw
-        }
-    }
-}
 use cheers_lib::{chessgame::ChessGame, moves::Move, types::PieceIndex};
 use pgn_reader::*;
 use GameResult::*;
@@ -3,20 +3,18 @@ use std::fs::{File, OpenOptions};
 use std::io::{prelude::*, stdout, BufWriter};
 use std::{io::BufReader, path::PathBuf};
 
-use cheers_lib::chessgame::{EvalParams, EVAL_PARAMS};
+use cheers_lib::{
+    chessgame::EVAL_PARAMS,
+    tuner::{params_source, parse_labelled_position, tune_k, Tuner, TuningEntry},
+};
 use clap::Parser;
 use indicatif::{ProgressBar, ProgressStyle};
 use pgn_reader::BufferedReader;
 use rayon::prelude::*;
 
-use crate::calculate_error::{calculate_error, calculate_gradient, data_to_entry, TuningEntry};
 use crate::data_extraction::FENWriter;
-use crate::k_tuning::tune_k;
 
-mod calculate_error;
 mod data_extraction;
-mod k_tuning;
-mod parameter_tuning;
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -35,6 +33,9 @@ struct Args {
     /// maximum tuning iterations
     #[clap(short, long, default_value_t = 1_000_000)]
     max_iters: usize,
+    /// largest change to a parameter in the first iteration
+    #[clap(short, long, default_value_t = 5.0)]
+    learning_rate: f64,
     /// learning rate step iterations
     #[clap(short, long, default_value_t = 1000)]
     rate_step_iters: usize,
@@ -98,14 +99,15 @@ fn main() -> Result<(), Box<dyn Error>> {
             .par_lines()
             .map(|l| {
                 entries_bar.clone().inc(1);
-                data_to_entry(l)
+                let (fen, result) = parse_labelled_position(l).map_err(|e| e.to_string())?;
+                TuningEntry::new(fen, result).map_err(|e| e.to_string())
             })
-            .collect::<Vec<TuningEntry>>();
+            .collect::<Result<Vec<TuningEntry>, String>>()?;
         entries_bar.finish();
         drop(data_string);
 
         println!("Optimising sigmoid K parameter...");
-        let best_k = tune_k(&data, args.count);
+        let best_k = tune_k(&data, &EVAL_PARAMS);
         println!("Best K: {best_k}");
 
         let mut output_file = OpenOptions::new()
@@ -113,29 +115,15 @@ fn main() -> Result<(), Box<dyn Error>> {
             .truncate(true)
             .create(true)
             .open("best_parameters.txt")?;
-        let mut eval_params = EVAL_PARAMS.to_array().map(|x| x as f64);
-
-        let mut adagrad = [0f64; EvalParams::LEN];
-        let mut rate = 1024.0;
+        let mut rate = args.learning_rate;
         let drop_rate = 2.0;
+        let mut tuner = Tuner::new(data, &EVAL_PARAMS, best_k).learning_rate(rate);
 
         for iter in 0..args.max_iters {
-            let gradient = calculate_gradient(&data, &eval_params, best_k);
-
-            for i in (0..adagrad.len()).step_by(2) {
-                adagrad[i] += (2.0 * gradient[i] / args.count as f64).powf(2.0);
-                adagrad[i + 1] += (2.0 * gradient[i + 1] / args.count as f64).powf(2.0);
-
-                eval_params[i] += (best_k / (200.0 * args.count as f64))
-                    * gradient[i]
-                    * (rate / (1e-8 + adagrad[i]).sqrt());
-                eval_params[i + 1] += (best_k / (200.0 * args.count as f64))
-                    * gradient[i + 1]
-                    * (rate / (1e-8 + adagrad[i + 1]).sqrt());
-            }
-            let error = calculate_error(&data, &eval_params, best_k, args.count);
+            let error = tuner.step();
             if iter != 0 && iter % args.rate_step_iters == 0 {
                 rate /= drop_rate;
+                tuner = tuner.learning_rate(rate);
             }
             print!("\rIter [{iter}] Error = [{error:.10}], Rate = [{rate:.10}]");
             stdout().flush()?;
@@ -144,8 +132,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             output_file.set_len(0)?;
             output_file.rewind()?;
 
-            let params = EvalParams::from_array(eval_params.map(|x| x as i32));
-            output_file.write_all(format!("{params:?}").as_bytes())?;
+            output_file.write_all(params_source(&tuner.params()).as_bytes())?;
         }
     }
     Ok(())